@@ -0,0 +1,72 @@
+extern crate interpreter;
+use interpreter::compiler::{Compiler, OptLevel};
+use interpreter::lexer::Lexer;
+use interpreter::parser::Parser;
+use interpreter::parser::ast::Program;
+use interpreter::vm::VM;
+use interpreter::regvm;
+use std::time::{Duration, Instant};
+
+// Times each workload at every optimization level of the stack VM and on the
+// register VM, and reports the speedup over the unoptimized bytecode. Without
+// loops in the language, the loop workload is a tail recursive count down.
+
+const WORKLOADS: [(&str, &str); 3] = [
+    ("fib", "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(22)"),
+    ("loop", "let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + n) } }; sum(20000, 0)"),
+    ("closures", "let adder = fn(a) { fn(b) { a + b } }; let apply = fn(f, n, acc) { if (0 < n) { apply(f, n - 1, f(acc)) } else { acc } }; apply(adder(3), 20000, 0)")
+];
+
+const RUNS: u32 = 20;
+
+// The recursive workloads nest 20000 calls deep
+const STACK_SIZE: usize = 1 << 18;
+
+fn main() {
+    for (name, input) in WORKLOADS {
+        let mut configs: Vec<(String, Box<dyn Fn() -> Duration>)> = vec![];
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let mut compiler = Compiler::with_opt_level(opt_level);
+            compiler.compile(parse(input)).unwrap();
+            let bytecode = compiler.bytecode();
+            configs.push((format!("{:?}", opt_level), Box::new(move || {
+                let mut vm = VM::with_stack_size(bytecode.clone(), STACK_SIZE);
+                let start = Instant::now();
+                vm.run().unwrap();
+                start.elapsed()
+            })));
+        }
+
+        let mut compiler = regvm::compiler::Compiler::new();
+        compiler.compile(parse(input)).unwrap();
+        let program = compiler.program();
+        configs.push(("register".to_string(), Box::new(move || {
+            let mut vm = regvm::VM::new(program.clone());
+            let start = Instant::now();
+            vm.run().unwrap();
+            start.elapsed()
+        })));
+
+        // Runs alternate between configurations so that drift affects all of
+        // them alike, and the fastest run of each is kept
+        let mut best = vec![Duration::MAX; configs.len()];
+        for _ in 0..RUNS {
+            for (i, (_, run)) in configs.iter().enumerate() {
+                best[i] = best[i].min(run());
+            }
+        }
+
+        for ((label, _), time) in configs.iter().zip(&best) {
+            println!("{:<10} {:<9} {:>10.3?} per run  {:.2}x", name, label, time, best[0].as_secs_f64() / time.as_secs_f64());
+        }
+    }
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
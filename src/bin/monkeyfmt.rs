@@ -0,0 +1,109 @@
+extern crate interpreter;
+
+use std::io::{self, Read};
+use std::{env, fs, process};
+
+use interpreter::parser::printer::{self, Style};
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
+
+// Whether the source in name was formatted already, or None when it does not parse
+fn format(name: &str, source: &str, style: Style) -> Option<String> {
+    match printer::format(source, style) {
+        Ok(formatted) => return Some(formatted),
+        Err(errors) => {
+            for (span, error) in errors {
+                eprintln!("{}:{}:{}: {}", name, span.line, span.column, error);
+            }
+            return None;
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+
+    let mut check = false;
+    args.retain(|arg| match *arg {
+        "--check" => { check = true; false },
+        _ => true
+    });
+
+    let mut style = Style::default();
+    args.retain(|arg| match arg.strip_prefix("--indent=") {
+        Some(n) => {
+            style.indent = n.parse().unwrap_or_else(|_| fail(format!("invalid indent {}", n)));
+            false
+        },
+        None => true
+    });
+
+    args.retain(|arg| match arg.strip_prefix("--width=") {
+        Some(n) => {
+            style.width = n.parse().unwrap_or_else(|_| fail(format!("invalid width {}", n)));
+            false
+        },
+        None => true
+    });
+
+    if args.iter().any(|x| x.starts_with('-')) {
+        eprintln!("usage: monkeyfmt [--check] [--indent=N] [--width=N] [FILE...]");
+        process::exit(2);
+    }
+
+    // Without files, from stdin to stdout
+    if args.is_empty() {
+        let mut source = String::new();
+        if let Err(x) = io::stdin().read_to_string(&mut source) {
+            fail(format!("could not read stdin: {}", x));
+        }
+        let formatted = format("<stdin>", &source, style).unwrap_or_else(|| process::exit(1));
+        if check && formatted != source {
+            println!("<stdin> is not formatted");
+            process::exit(1);
+        }
+        if !check {
+            print!("{}", formatted);
+        }
+        return;
+    }
+
+    // Files are rewritten in place, or with --check only named. Either way
+    // every file is seen to before the exit status says something went wrong.
+    let mut failed = false;
+    for path in args {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(x) => {
+                eprintln!("could not read {}: {}", path, x);
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = match format(path, &source, style) {
+            Some(formatted) => formatted,
+            None => {
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            failed = true;
+        } else if let Err(x) = fs::write(path, formatted) {
+            eprintln!("could not write {}: {}", path, x);
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,79 @@
+extern crate interpreter;
+
+use std::io::{self, Read};
+use std::{env, fs, process};
+
+use interpreter::lint::{self, Rule};
+
+// Prints what is found in source, and whether anything was
+fn lint(name: &str, source: &str, allowed: &[Rule]) -> bool {
+    match lint::check(source) {
+        Ok(diagnostics) => {
+            let mut found = false;
+            for diagnostic in diagnostics.iter().filter(|x| !allowed.contains(&x.rule)) {
+                println!("{}:{}", name, diagnostic);
+                found = true;
+            }
+            return found;
+        },
+        Err(errors) => {
+            for (span, error) in errors {
+                eprintln!("{}:{}:{}: {}", name, span.line, span.column, error);
+            }
+            return true;
+        }
+    }
+}
+
+fn usage() -> ! {
+    let rules: Vec<&str> = Rule::ALL.iter().map(|x| x.id()).collect();
+    eprintln!("usage: monkeylint [--allow=RULE]... [FILE...]");
+    eprintln!("rules: {}", rules.join(", "));
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+
+    // Rules left out everywhere
+    let mut allowed = vec![];
+    args.retain(|arg| match arg.strip_prefix("--allow=") {
+        Some(id) => {
+            allowed.push(Rule::from_id(id).unwrap_or_else(|| usage()));
+            false
+        },
+        None => true
+    });
+
+    if args.iter().any(|x| x.starts_with('-')) {
+        usage();
+    }
+
+    if args.is_empty() {
+        let mut source = String::new();
+        if let Err(x) = io::stdin().read_to_string(&mut source) {
+            eprintln!("could not read stdin: {}", x);
+            process::exit(1);
+        }
+        if lint("<stdin>", &source, &allowed) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut failed = false;
+    for path in args {
+        match fs::read_to_string(path) {
+            Ok(source) => failed |= lint(path, &source, &allowed),
+            Err(x) => {
+                eprintln!("could not read {}: {}", path, x);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
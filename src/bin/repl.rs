@@ -1,17 +1,61 @@
 extern crate interpreter;
 
-use std::io::{self, BufRead, Write};
+use std::io;
 use std::error::Error;
+use std::{env, fmt, fs, mem, process};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use interpreter::lexer::Lexer;
-use interpreter::parser::Parser;
-use interpreter::evaluator;
-use interpreter::compiler::Compiler;
-use interpreter::vm::VM;
+use interpreter::lexer::{Lexer, token::Token};
+use interpreter::parser::{Parser, json};
+use interpreter::evaluator::{self, Environment, memory};
+use interpreter::evaluator::interrupt::{self, Watchdog};
+use interpreter::compiler::{Compiler, ByteCode, OptLevel, disassembler, serialize};
+use interpreter::vm::{self, VM, verifier, value::Value};
+use interpreter::regvm;
+use interpreter::editor::{Editor, history::History};
+use interpreter::parser::ast::Program;
+use interpreter::evaluator::object::Object;
+
+// Whether programs are evaluated by walking the tree or compiled for a virtual machine
+#[derive(Clone, Copy, PartialEq)]
+enum Engine {
+    Eval,
+    Vm
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Eval => write!(f, "eval"),
+            Engine::Vm => write!(f, "vm")
+        }
+    }
+}
+
+// Which virtual machine runs compiled programs
+#[derive(Clone, Copy, PartialEq)]
+enum Machine {
+    Stack,
+    Register
+}
+
+// How compiled programs are built and run, as given on the command line
+#[derive(Clone, Copy)]
+struct Options {
+    engine: Engine,
+    machine: Machine,
+    opt_level: OptLevel,
+    stack_size: usize,
+    fuel: Option<u64>,
+    memory_limit: Option<usize>,
+    timeout: Option<Duration>
+}
 
 
 
-fn print_errors(errors: &Vec<Box<Error>>) {
+fn print_errors(errors: &Vec<Box<dyn Error>>) {
     let monkey_face = "             __,__
     .--.  .-\"     \"-.  .--.
    / .. \\/  .-. .-.  \\/ .. \\
@@ -32,52 +76,543 @@ fn print_errors(errors: &Vec<Box<Error>>) {
     }
 }
 
-fn start() {
-    let prompt = ">> ";
-    let mut env = evaluator::Environment::new();
+fn compile_source(input: &str, opt_level: OptLevel) -> Result<ByteCode, Vec<Box<dyn Error>>> {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => return Err(errors.iter().map(|x| x.to_string().into()).collect())
+    };
 
-    loop {
-        print!("{}", prompt);
-        io::stdout().flush();
-        let mut buffer = String::new();
-        io::stdin().read_line(&mut buffer);
-
-        let mut l = Lexer::new(&buffer.as_str());
-        let mut p = Parser::new(&mut l);
-
-        match p.parse_program() {
-            Ok(program) => {
-                let mut compiler = Compiler::new();
-                if let Err(x) = compiler.compile(program) {
-                    println!("{}", x)
-                }
+    let mut compiler = Compiler::with_opt_level(opt_level);
+    if let Err(x) = compiler.compile(program) {
+        return Err(vec![x]);
+    }
+    return Ok(compiler.bytecode());
+}
 
-                let mut machine = VM::new(compiler.bytecode());
-                if let Err(x) = machine.run() {
-                    println!("{}", x)
-                }
+// Compiles the source and lists its bytecode, annotated with the source lines
+fn disassemble(input: &str, opt_level: OptLevel) -> Result<String, Vec<Box<dyn Error>>> {
+    let bytecode = compile_source(input, opt_level)?;
+    return Ok(disassembler::disassemble(&bytecode, Some(input)));
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    return fs::read(path).unwrap_or_else(|x| fail(format!("could not read {}: {}", path, x)));
+}
+
+fn read_source(path: &str) -> String {
+    return String::from_utf8(read_file(path)).unwrap_or_else(|_| fail(format!("{} is not valid UTF-8", path)));
+}
+
+fn compile_file(path: &str, opt_level: OptLevel) -> ByteCode {
+    return compile_source(&read_source(path), opt_level).unwrap_or_else(|errors| {
+        print_errors(&errors);
+        process::exit(1);
+    });
+}
+
+// Either a compiled .mbc file or Monkey source, told apart by the magic number
+fn load_file(path: &str, opt_level: OptLevel) -> ByteCode {
+    let bytes = read_file(path);
+    if !bytes.starts_with(serialize::MAGIC) {
+        return compile_file(path, opt_level);
+    }
+    return ByteCode::read_from(&mut bytes.as_slice()).unwrap_or_else(|x| fail(format!("{}: {}", path, x)));
+}
+
+fn new_vm(bytecode: ByteCode, options: Options) -> VM {
+    let mut vm = VM::with_stack_size(bytecode, options.stack_size);
+    if let Some(fuel) = options.fuel {
+        vm.set_fuel(fuel);
+    }
+    if let Some(bytes) = options.memory_limit {
+        vm.set_memory_limit(bytes);
+    }
+    if let Some(timeout) = options.timeout {
+        vm.set_deadline(Instant::now() + timeout);
+    }
+    return vm;
+}
+
+// Statements that leave no value behind are not echoed
+fn result_of(vm: &VM) -> Option<Object> {
+    return match vm.last_popped_stack_elem() {
+        Object::Null => None,
+        x => Some(x)
+    }
+}
+
+// Runs to completion or exits, reporting the line the error happened on when known
+fn run_to_completion(name: &str, mut vm: VM) -> Option<Object> {
+    if let Err(x) = vm.run() {
+        match vm.current_line() {
+            Some(line) => fail(format!("{}:{}: {}", name, line, x)),
+            None => fail(format!("{}: {}", name, x))
+        }
+    }
+    return result_of(&vm);
+}
+
+// Prints the syntax tree of input as JSON, for tools outside the crate
+fn dump_ast(name: &str, input: &str) {
+    match json::dump(input) {
+        Ok(tree) => println!("{}", tree),
+        Err(errors) => {
+            for (span, error) in errors {
+                eprintln!("{}:{}:{}: {}", name, span.line, span.column, error);
+            }
+            process::exit(1);
+        }
+    }
+}
+
+// Runs a whole script, for use from the shell: errors go to stderr as
+// name:line:column and make the process exit with a failure status
+fn run_script(name: &str, input: &str, options: Options) {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            for (error, span) in errors.iter().zip(p.error_spans.iter()) {
+                eprintln!("{}:{}:{}: {}", name, span.line, span.column, error);
+            }
+            process::exit(1);
+        }
+    };
+
+    let result = match (options.engine, options.machine) {
+        (Engine::Vm, Machine::Stack) => {
+            let mut compiler = Compiler::with_opt_level(options.opt_level);
+            if let Err(x) = compiler.compile(program) {
+                fail(format!("{}: {}", name, x));
+            }
+            run_to_completion(name, new_vm(compiler.bytecode(), options))
+        },
+        _ => Session::new(options).execute(program, options).unwrap_or_else(|x| {
+            // The register machine keeps no line tables
+            match evaluator::error_span().filter(|_| options.engine == Engine::Eval) {
+                Some(span) => fail(format!("{}:{}: {}", name, span.line, x)),
+                None => fail(format!("{}: {}", name, x))
+            }
+        })
+    };
+    if let Some(x) = result {
+        println!("{}", x);
+    }
+}
+
+// Runs a source file or, on the stack VM, a compiled .mbc file
+fn run_file(path: &str, options: Options) {
+    let bytes = read_file(path);
+    if !bytes.starts_with(serialize::MAGIC) {
+        return run_script(path, &read_source(path), options);
+    }
+    if options.engine == Engine::Eval || options.machine == Machine::Register {
+        fail(format!("{}: only the stack VM runs compiled bytecode", path));
+    }
+
+    let bytecode = load_file(path, options.opt_level);
+    if let Err(x) = verifier::verify(&bytecode) {
+        fail(format!("{}: {}", path, x));
+    }
+    if let Some(x) = run_to_completion(path, new_vm(bytecode, options)) {
+        println!("{}", x);
+    }
+}
+
+// Runs the evaluator under the same limits the virtual machines get
+fn evaluate(program: Program, env: &mut Environment, options: Options) -> Result<Option<Object>, Box<dyn Error>> {
+    let deadline = options.timeout.map(|x| Instant::now() + x);
+    let run = || match options.fuel {
+        Some(mut fuel) => evaluator::evaluate_with_fuel(program, env, &mut fuel),
+        None => Ok(evaluator::evaluate(program, env))
+    };
+    let run = || interrupt::with_watchdog(Watchdog::new(None, deadline), run);
+    let evaluated = match options.memory_limit {
+        Some(limit) => memory::with_limit(limit, run).0,
+        None => run()
+    }?;
+
+    return match evaluated {
+        Object::Error(msg) => Err(msg.into()),
+        Object::Null => Ok(None),
+        x => Ok(Some(x))
+    }
+}
+
+// What each engine remembers from one input to the next
+struct Session {
+    env: Environment,
+    compiler: Compiler,
+    globals: Vec<Value>,
+    register_compiler: regvm::compiler::Compiler,
+    register_globals: Vec<Rc<Object>>
+}
 
-                match machine.last_popped() {
-                    Some(x) => println!("{}", x),
-                    None => {}
+impl Session {
+    fn new(options: Options) -> Self {
+        return Session{
+            env: Environment::new(),
+            compiler: Compiler::with_opt_level(options.opt_level),
+            globals: vec![],
+            register_compiler: regvm::compiler::Compiler::new(),
+            register_globals: vec![]
+        }
+    }
+
+    // Returns the value of the last expression statement, if any. Bindings made
+    // before a runtime error are kept, an input that does not compile leaves no trace.
+    fn execute(&mut self, program: Program, options: Options) -> Result<Option<Object>, Box<dyn Error>> {
+        match (options.engine, options.machine) {
+            (Engine::Eval, _) => return evaluate(program, &mut self.env, options),
+            (Engine::Vm, Machine::Stack) => {
+                let mut compiler = self.compiler.continuation();
+                compiler.compile(program)?;
+                let mut vm = new_vm(compiler.bytecode(), options);
+                self.compiler = compiler;
+                vm.set_globals(mem::take(&mut self.globals));
+                let result = vm.run();
+                let value = result_of(&vm);
+                self.globals = vm.into_globals();
+                result?;
+                return Ok(value);
+            },
+            (Engine::Vm, Machine::Register) => {
+                let mut compiler = self.register_compiler.continuation();
+                compiler.compile(program)?;
+                let mut vm = regvm::VM::new(compiler.program());
+                self.register_compiler = compiler;
+                if let Some(fuel) = options.fuel {
+                    vm.set_fuel(fuel);
                 }
+                if let Some(bytes) = options.memory_limit {
+                    vm.set_memory_limit(bytes);
+                }
+                if let Some(timeout) = options.timeout {
+                    vm.set_deadline(Instant::now() + timeout);
+                }
+                vm.set_globals(mem::take(&mut self.register_globals));
+                let result = vm.run();
+                let value = vm.last_popped().cloned();
+                self.register_globals = vm.into_globals();
+                result?;
+                return Ok(value);
+            }
+        }
+    }
 
-                
-                /*
-                let evaluated = evaluator::evaluate(program, &mut env);
-                match evaluated {
-                    evaluator::object::Object::Null => continue,
-                    _ => println!("{}", evaluated)
-                }*/
+    // The names bound so far by the engine in use and their values, in the
+    // order they were defined for the machines and by name for the evaluator
+    fn bindings(&self, options: Options) -> Vec<(String, Object)> {
+        match (options.engine, options.machine) {
+            (Engine::Eval, _) => {
+                let mut bindings: Vec<(String, Object)> = self.env.iter().map(|(name, x)| (name.0.clone(), x.borrow().clone())).collect();
+                bindings.sort_by(|a, b| a.0.cmp(&b.0));
+                return bindings;
             },
-            Err(errors) => print_errors(errors)
+            // A global whose input failed before setting it is still null
+            (Engine::Vm, Machine::Stack) => {
+                return self.compiler.symbol_table().globals().into_iter().map(|symbol| {
+                    let value = self.globals.get(symbol.index).map(|x| x.to_object()).unwrap_or(Object::Null);
+                    (symbol.name, value)
+                }).collect();
+            },
+            (Engine::Vm, Machine::Register) => {
+                return self.register_compiler.symbol_table().globals().into_iter().map(|symbol| {
+                    let value = self.register_globals.get(symbol.index).map(|x| (**x).clone()).unwrap_or(Object::Null);
+                    (symbol.name, value)
+                }).collect();
+            }
+        }
+    }
+}
+
+// Parses and runs one input of the session, printing its value or what went wrong
+fn execute_input(session: &mut Session, input: &str, options: Options) {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+
+    match p.parse_program() {
+        Ok(program) => {
+            match session.execute(program, options) {
+                Ok(Some(x)) => println!("{}", x),
+                Ok(None) => {},
+                Err(x) => println!("{}", x)
+            }
+        },
+        Err(errors) => print_errors(errors)
+    }
+}
+
+fn print_tokens(input: &str) {
+    let mut l = Lexer::new(input);
+    loop {
+        let token = l.next_token();
+        if token == Token::EOF {
+            return;
+        }
+        let span = l.span();
+        println!("{}:{}\t{:?}", span.line, span.column, token);
+    }
+}
+
+fn print_ast(input: &str) {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => println!("{:#?}", program),
+        Err(errors) => print_errors(errors)
+    }
+}
+
+const COMMANDS: &str = ":tokens <code>, :ast <code>, :bytecode <code>, :env, :engine [eval|vm], :load <file>, :reset";
+
+// Runs a line starting with a colon, which inspects the pipeline or the session
+// instead of being Monkey code
+fn run_command(line: &str, session: &mut Session, options: &mut Options) {
+    let (command, argument) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, "")
+    };
+
+    match command {
+        ":tokens" => print_tokens(argument),
+        ":ast" => print_ast(argument),
+        ":bytecode" => match disassemble(argument, options.opt_level) {
+            Ok(listing) => print!("{}", listing),
+            Err(errors) => print_errors(&errors)
+        },
+        ":env" => {
+            for (name, value) in session.bindings(*options) {
+                println!("{} = {}", name, value);
+            }
+        },
+        // Each engine keeps its own bindings, switching back finds them again
+        ":engine" => match argument {
+            "" => println!("{}", options.engine),
+            "eval" => options.engine = Engine::Eval,
+            "vm" => options.engine = Engine::Vm,
+            x => println!("unknown engine {}, expected eval or vm", x)
+        },
+        ":load" => match fs::read_to_string(argument) {
+            Ok(source) => execute_input(session, &source, *options),
+            Err(x) => println!("could not read {}: {}", argument, x)
+        },
+        ":reset" => *session = Session::new(*options),
+        x => println!("unknown command {}, expected one of {}", x, COMMANDS)
+    }
+}
+
+// Whether the input stops short of a whole program, with brackets left open,
+// an operator still waiting for its right operand or a string not yet closed
+fn is_incomplete(input: &str) -> bool {
+    let mut l = Lexer::new(input);
+    let mut depth = 0;
+    let mut last = Token::EOF;
+    loop {
+        let token = l.next_token();
+        match token {
+            Token::EOF => break,
+            Token::LParen | Token::LBrace | Token::LBracket => depth += 1,
+            Token::RParen | Token::RBrace | Token::RBracket => depth -= 1,
+            _ => {}
         }
+        last = token;
+    }
+
+    let waiting = matches!(last,
+        Token::Assign | Token::Plus | Token::Minus | Token::Bang | Token::Asterick | Token::Slash |
+        Token::LT | Token::GT | Token::Equal | Token::NotEqual | Token::Comma | Token::Colon |
+        Token::Let | Token::Return | Token::If | Token::Else | Token::Function
+    );
+    // Strings have no escapes, so an odd quote outside of comments is always an open one
+    let quotes = input.matches('"').count() - l.comments.iter().map(|x| x.text.matches('"').count()).sum::<usize>();
+    let open_string = quotes % 2 == 1;
+    return depth > 0 || waiting || open_string;
+}
+
+// The history is kept in the home directory, when there is one
+fn load_history() -> History {
+    let home = match env::var_os("HOME") {
+        Some(home) => home,
+        None => return History::new()
+    };
+    let mut path = PathBuf::from(home);
+    path.push(".monkey_history");
+    return History::load(path).unwrap_or_default();
+}
+
+fn start(mut options: Options) {
+    let prompt = ">> ";
+    let continuation = "... ";
+    let mut session = Session::new(options);
+    let mut editor = Editor::new(load_history());
+
+    'inputs: loop {
+        editor.names = session.bindings(options).into_iter().map(|(name, _)| name).collect();
+        let mut buffer = match editor.read_line(prompt) {
+            Ok(Some(line)) => line + "\n",
+            Ok(None) => return,
+            Err(x) if x.kind() == io::ErrorKind::Interrupted => continue,
+            Err(x) => fail(format!("could not read the input: {}", x))
+        };
 
+        if buffer.trim_start().starts_with(':') {
+            run_command(buffer.trim(), &mut session, &mut options);
+            continue;
+        }
+
+        // Keep reading until the input is whole, an empty line gives up and
+        // hands over what there is so the parser can say what is missing.
+        // Ctrl-C drops the whole input.
+        let mut eof = false;
+        while is_incomplete(&buffer) {
+            match editor.read_line(continuation) {
+                Ok(Some(line)) if line.trim().is_empty() => break,
+                Ok(Some(line)) => {
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                },
+                Ok(None) => {
+                    eof = true;
+                    break;
+                },
+                Err(x) if x.kind() == io::ErrorKind::Interrupted => continue 'inputs,
+                Err(x) => fail(format!("could not read the input: {}", x))
+            }
+        }
+
+        execute_input(&mut session, &buffer, options);
+
+        if eof {
+            return;
+        }
     }
 }
 
+fn usage() -> ! {
+    eprintln!("usage: repl [--engine=eval | --engine=vm] [-O0 | -O1 | -O2] [--vm=stack | --vm=register] [--stack-size=N] [--fuel=N] [--memory-limit=BYTES] [--timeout=MS] [FILE | -e CODE | --disassemble FILE | --compile FILE OUT.mbc | --dump-ast=json FILE | --dump-ast=json -e CODE]");
+    process::exit(2);
+}
+
+// The evaluator recurses on the native stack, so everything runs on a thread with room for it
 fn main() {
+    evaluator::with_stack(run_cli);
+}
+
+fn run_cli() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+
+    // Fully optimized unless a lower level is given
+    let mut opt_level = OptLevel::O2;
+    args.retain(|arg| match *arg {
+        "-O0" => { opt_level = OptLevel::O0; false },
+        "-O1" => { opt_level = OptLevel::O1; false },
+        "-O2" => { opt_level = OptLevel::O2; false },
+        _ => true
+    });
+
+    let mut engine = Engine::Vm;
+    args.retain(|arg| match *arg {
+        "--engine=eval" => { engine = Engine::Eval; false },
+        "--engine=vm" => { engine = Engine::Vm; false },
+        _ => true
+    });
+
+    let mut machine = Machine::Stack;
+    args.retain(|arg| match *arg {
+        "--vm=stack" => { machine = Machine::Stack; false },
+        "--vm=register" => { machine = Machine::Register; false },
+        _ => true
+    });
+
+    let mut stack_size = vm::STACK_SIZE;
+    args.retain(|arg| match arg.strip_prefix("--stack-size=") {
+        Some(n) => {
+            stack_size = n.parse().unwrap_or_else(|_| fail(format!("invalid stack size {}", n)));
+            false
+        },
+        None => true
+    });
+
+    let mut fuel = None;
+    args.retain(|arg| match arg.strip_prefix("--fuel=") {
+        Some(n) => {
+            fuel = Some(n.parse().unwrap_or_else(|_| fail(format!("invalid fuel {}", n))));
+            false
+        },
+        None => true
+    });
+
+    let mut memory_limit = None;
+    args.retain(|arg| match arg.strip_prefix("--memory-limit=") {
+        Some(n) => {
+            memory_limit = Some(n.parse().unwrap_or_else(|_| fail(format!("invalid memory limit {}", n))));
+            false
+        },
+        None => true
+    });
+
+    let mut timeout = None;
+    args.retain(|arg| match arg.strip_prefix("--timeout=") {
+        Some(ms) => {
+            timeout = Some(Duration::from_millis(ms.parse().unwrap_or_else(|_| fail(format!("invalid timeout {}", ms)))));
+            false
+        },
+        None => true
+    });
+
+    let mut dump = false;
+    args.retain(|arg| match arg.strip_prefix("--dump-ast=") {
+        Some("json") => { dump = true; false },
+        Some(format) => fail(format!("unknown syntax tree format {}, expected json", format)),
+        None => true
+    });
+
+    let options = Options{ engine, machine, opt_level, stack_size, fuel, memory_limit, timeout };
+    match args.as_slice() {
+        [] if !dump => {},
+        ["-e", input] if dump => return dump_ast("-e", input),
+        [path] if dump && !path.starts_with('-') => return dump_ast(path, &read_source(path)),
+        _ if dump => usage(),
+        ["--disassemble", path] => {
+            let bytes = read_file(path);
+            if bytes.starts_with(serialize::MAGIC) {
+                print!("{}", load_file(path, opt_level));
+            } else {
+                match disassemble(&read_source(path), opt_level) {
+                    Ok(listing) => print!("{}", listing),
+                    Err(errors) => {
+                        print_errors(&errors);
+                        process::exit(1);
+                    }
+                }
+            }
+            return;
+        },
+        ["--compile", path, out] => {
+            let bytecode = compile_file(path, opt_level);
+            let mut file = fs::File::create(out).unwrap_or_else(|x| fail(format!("could not create {}: {}", out, x)));
+            if let Err(x) = bytecode.write_to(&mut file) {
+                fail(format!("could not write {}: {}", out, x));
+            }
+            return;
+        },
+        ["-e", input] => return run_script("-e", input, options),
+        ["--run", path] | [path] if !path.starts_with('-') => return run_file(path, options),
+        _ => usage()
+    }
+
     println!("Hello! This is the Monkey programming language!");
     println!("Feel free to type in commands");
-    start();
-}
\ No newline at end of file
+    start(options);
+}
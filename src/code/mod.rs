@@ -1,18 +1,169 @@
-use crate::evaluator::object;
+pub mod peephole;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Instruction {
-    OpConstant(usize),
-    OpAdd,
-    OpSubtract,
-    OpMultiply,
-    OpDivide,
-    OpPop,
-    OpTrue,
-    OpFalse,
+// Instructions are encoded as one opcode byte followed by its operands, each
+// a fixed width big-endian unsigned integer as listed in the definition table.
+pub type Instructions = Vec<u8>;
+
+// Pairs of (byte offset, source line), each marking where the code for a line starts
+pub type LineTable = Vec<(usize, usize)>;
+
+pub struct Definition {
+    pub name: &'static str,
+    pub operand_widths: &'static [usize]
 }
 
-pub type Instructions = Vec<Instruction>;
+macro_rules! opcodes {
+    ($($op:ident => [$($width:expr),*]),* $(,)?) => {
+        #[repr(u8)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Opcode {
+            $($op),*
+        }
 
+        // Indexed by opcode byte
+        const OPCODES: &[Opcode] = &[$(Opcode::$op),*];
 
+        pub const DEFINITIONS: &[Definition] = &[
+            $(Definition{ name: stringify!($op), operand_widths: &[$($width),*] }),*
+        ];
+    }
+}
 
+opcodes! {
+    OpConstant => [2],
+    OpAdd => [],
+    OpSubtract => [],
+    OpMultiply => [],
+    OpDivide => [],
+    OpPop => [],
+    OpTrue => [],
+    OpFalse => [],
+    OpEqual => [],
+    OpNotEqual => [],
+    OpGreaterThan => [],
+    OpLessThan => [],
+    OpMinus => [],
+    OpBang => [],
+    // Jump targets are byte offsets within the enclosing function
+    OpJumpNotTruthy => [2],
+    OpJump => [2],
+    OpNull => [],
+    OpGetGlobal => [2],
+    OpSetGlobal => [2],
+    OpArray => [2],
+    OpHash => [2],
+    OpIndex => [],
+    OpCall => [1],
+    OpReturnValue => [],
+    OpReturn => [],
+    OpGetLocal => [1],
+    OpSetLocal => [1],
+    // Constant index of the compiled function, number of free variables on the stack
+    OpClosure => [2, 1],
+    OpGetFree => [1],
+    OpCurrentClosure => [],
+    // Index into `evaluator::builtins::BUILTINS`
+    OpGetBuiltin => [1],
+    // Superinstructions fused by the peephole pass
+    OpGetLocalGetLocal => [1, 1],
+    OpAddConst => [2],
+    OpSubConst => [2],
+    OpConstantPop => [2],
+    // OpGreaterThan followed by OpJumpNotTruthy
+    OpJumpNotGreater => [2],
+    // OpLessThan followed by OpJumpNotTruthy
+    OpJumpNotLess => [2],
+}
+
+impl Opcode {
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        return OPCODES.get(byte as usize).copied();
+    }
+
+    pub fn definition(self) -> &'static Definition {
+        return &DEFINITIONS[self as usize];
+    }
+}
+
+pub fn lookup(byte: u8) -> Option<&'static Definition> {
+    return DEFINITIONS.get(byte as usize);
+}
+
+// The largest value the operand-th operand of op can hold
+pub fn max_operand(op: Opcode, operand: usize) -> usize {
+    return (1 << (8 * op.definition().operand_widths[operand])) - 1;
+}
+
+// Operands out of range are a bug in the caller, which has to check them
+// against max_operand first
+pub fn make(op: Opcode, operands: &[usize]) -> Instructions {
+    let def = op.definition();
+    let mut instruction = vec![op as u8];
+    for (operand, width) in operands.iter().zip(def.operand_widths) {
+        assert!(*operand < 1 << (8 * width), "operand {} does not fit in {} bytes", operand, width);
+        match width {
+            2 => instruction.extend_from_slice(&(*operand as u16).to_be_bytes()),
+            1 => instruction.push(*operand as u8),
+            _ => unreachable!("unsupported operand width {}", width)
+        }
+    }
+    return instruction;
+}
+
+// Decodes the operands following an opcode, returning them with the number of bytes read
+pub fn read_operands(def: &Definition, ins: &[u8]) -> (Vec<usize>, usize) {
+    let mut operands = vec![];
+    let mut offset = 0;
+    for width in def.operand_widths {
+        match width {
+            2 => operands.push(read_u16(&ins[offset..]) as usize),
+            1 => operands.push(ins[offset] as usize),
+            _ => unreachable!("unsupported operand width {}", width)
+        }
+        offset += width;
+    }
+    return (operands, offset);
+}
+
+pub fn read_u16(ins: &[u8]) -> u16 {
+    return u16::from_be_bytes([ins[0], ins[1]]);
+}
+
+// Lists the instructions one per line, prefixed with their byte offset
+pub fn disassemble(ins: &[u8]) -> String {
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset < ins.len() {
+        out.push_str(&format!("{:04} {}\n", offset, format_instruction(&ins[offset..])));
+        offset += instruction_len(&ins[offset..]);
+    }
+    return out;
+}
+
+// Formats the instruction at the start of `ins`, e.g. "OpConstant 1"
+pub fn format_instruction(ins: &[u8]) -> String {
+    let def = match lookup(ins[0]) {
+        Some(def) => def,
+        None => return format!("ERROR: unknown opcode {}", ins[0])
+    };
+
+    let width: usize = def.operand_widths.iter().sum();
+    if ins.len() < 1 + width {
+        return format!("ERROR: {} is missing operands", def.name);
+    }
+
+    let (operands, _) = read_operands(def, &ins[1..]);
+    let mut out = def.name.to_string();
+    for operand in operands {
+        out.push_str(&format!(" {}", operand));
+    }
+    return out;
+}
+
+// The number of bytes taken by the instruction at the start of `ins`
+pub fn instruction_len(ins: &[u8]) -> usize {
+    return match lookup(ins[0]) {
+        Some(def) => (1 + def.operand_widths.iter().sum::<usize>()).min(ins.len()),
+        None => 1
+    }
+}
@@ -0,0 +1,96 @@
+use super::{Instructions, LineTable, Opcode, make, read_operands, read_u16};
+use std::collections::{HashMap, HashSet};
+
+struct Instruction {
+    offset: usize,
+    op: Opcode,
+    operands: Vec<usize>
+}
+
+// Fuses common pairs of instructions into superinstructions, saving the VM a
+// dispatch for each pair. A pair is only fused when no jump lands between its
+// two halves. Jump targets and the line table are moved to the new offsets; a
+// fused instruction keeps the line of its first half, and the line of the
+// second carries on from the instruction after it.
+pub fn optimize(ins: &[u8], lines: &LineTable) -> (Instructions, LineTable) {
+    let decoded = decode(ins);
+    let targets: HashSet<usize> = decoded.iter()
+        .filter(|x| is_jump(x.op))
+        .map(|x| x.operands[0])
+        .collect();
+
+    let mut out = vec![];
+    let mut new_offsets = HashMap::new();
+    let mut jumps = vec![];
+    let mut i = 0;
+    while i < decoded.len() {
+        let first = &decoded[i];
+        new_offsets.insert(first.offset, out.len());
+
+        let fused = match decoded.get(i + 1) {
+            Some(second) if !targets.contains(&second.offset) => fuse(first, second),
+            _ => None
+        };
+        let (op, operands, halves) = match fused {
+            Some((op, operands)) => (op, operands, 2),
+            None => (first.op, first.operands.clone(), 1)
+        };
+
+        if is_jump(op) {
+            jumps.push(out.len());
+        }
+        out.extend(make(op, &operands));
+        if halves == 2 {
+            // No jump lands on the second half, so only the line table looks it up
+            new_offsets.insert(decoded[i + 1].offset, out.len());
+        }
+        i += halves;
+    }
+    new_offsets.insert(ins.len(), out.len());
+
+    for position in jumps {
+        let target = new_offsets[&(read_u16(&out[position + 1..]) as usize)];
+        out[position + 1..position + 3].copy_from_slice(&(target as u16).to_be_bytes());
+    }
+
+    let mut new_lines: LineTable = vec![];
+    for (position, line) in lines {
+        let position = new_offsets.get(position).copied().unwrap_or(out.len());
+        match new_lines.last_mut() {
+            Some(last) if last.0 == position => last.1 = *line,
+            _ => new_lines.push((position, *line))
+        }
+    }
+    // A line for a fused second half that ended the code has nothing left to cover
+    new_lines.retain(|x| x.0 < out.len());
+    return (out, new_lines);
+}
+
+fn fuse(first: &Instruction, second: &Instruction) -> Option<(Opcode, Vec<usize>)> {
+    return match (first.op, second.op) {
+        (Opcode::OpGetLocal, Opcode::OpGetLocal) => Some((Opcode::OpGetLocalGetLocal, vec![first.operands[0], second.operands[0]])),
+        (Opcode::OpConstant, Opcode::OpAdd) => Some((Opcode::OpAddConst, first.operands.clone())),
+        (Opcode::OpConstant, Opcode::OpSubtract) => Some((Opcode::OpSubConst, first.operands.clone())),
+        (Opcode::OpConstant, Opcode::OpPop) => Some((Opcode::OpConstantPop, first.operands.clone())),
+        (Opcode::OpGreaterThan, Opcode::OpJumpNotTruthy) => Some((Opcode::OpJumpNotGreater, second.operands.clone())),
+        (Opcode::OpLessThan, Opcode::OpJumpNotTruthy) => Some((Opcode::OpJumpNotLess, second.operands.clone())),
+        _ => None
+    }
+}
+
+fn is_jump(op: Opcode) -> bool {
+    return matches!(op, Opcode::OpJump | Opcode::OpJumpNotTruthy | Opcode::OpJumpNotGreater | Opcode::OpJumpNotLess);
+}
+
+// The compiler only hands over well formed instructions
+fn decode(ins: &[u8]) -> Vec<Instruction> {
+    let mut decoded = vec![];
+    let mut offset = 0;
+    while offset < ins.len() {
+        let op = Opcode::from_byte(ins[offset]).expect("unknown opcode");
+        let (operands, read) = read_operands(op.definition(), &ins[offset + 1..]);
+        decoded.push(Instruction{ offset, op, operands });
+        offset += 1 + read;
+    }
+    return decoded;
+}
@@ -0,0 +1,62 @@
+use crate::code::{self, LineTable};
+use crate::evaluator::object::Object;
+use super::ByteCode;
+use std::fmt;
+
+// A listing of the main program, the constant pool and every compiled function
+// in it. When the source is given, each line of the listing that starts a new
+// source line is annotated with that line's text.
+pub fn disassemble(bytecode: &ByteCode, source: Option<&str>) -> String {
+    let source_lines: Vec<&str> = source.map(|x| x.lines().collect()).unwrap_or_default();
+
+    let mut out = String::from("== main ==\n");
+    list_instructions(&mut out, &bytecode.instructions, &bytecode.lines, &source_lines);
+
+    if bytecode.constants.is_empty() {
+        return out;
+    }
+
+    out.push_str("\n== constants ==\n");
+    for (idx, constant) in bytecode.constants.iter().enumerate() {
+        out.push_str(&format!("{:04} {}\n", idx, describe_constant(constant)));
+    }
+
+    for (idx, constant) in bytecode.constants.iter().enumerate() {
+        if let Object::CompiledFunction(func) = constant.as_ref() {
+            out.push_str(&format!("\n== fn {:04} (params={}, locals={}) ==\n", idx, func.num_params, func.num_locals));
+            list_instructions(&mut out, &func.instructions, &func.lines, &source_lines);
+        }
+    }
+    return out;
+}
+
+fn list_instructions(out: &mut String, ins: &[u8], lines: &LineTable, source_lines: &[&str]) {
+    let mut next_line = lines.iter().peekable();
+    let mut offset = 0;
+    while offset < ins.len() {
+        while let Some((_, line)) = next_line.next_if(|(position, _)| *position <= offset) {
+            match line.checked_sub(1).and_then(|x| source_lines.get(x)) {
+                Some(text) => out.push_str(&format!("     ; line {}: {}\n", line, text.trim())),
+                None => out.push_str(&format!("     ; line {}\n", line))
+            }
+        }
+
+        out.push_str(&format!("{:04} {}\n", offset, code::format_instruction(&ins[offset..])));
+        offset += code::instruction_len(&ins[offset..]);
+    }
+}
+
+fn describe_constant(obj: &Object) -> String {
+    return match obj {
+        Object::Integer(x) => format!("Integer {}", x),
+        Object::Str(x) => format!("String {:?}", x),
+        Object::CompiledFunction(func) => format!("CompiledFunction params={} locals={}", func.num_params, func.num_locals),
+        x => format!("{}", x)
+    }
+}
+
+impl fmt::Display for ByteCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", disassemble(self, None))
+    }
+}
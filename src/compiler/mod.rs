@@ -1,98 +1,472 @@
+pub mod symbol_table;
+pub mod disassembler;
+pub mod serialize;
+pub mod optimizer;
+
 use crate::{code, parser::ast::*};
-use crate::evaluator::object::Object;
+use crate::code::{Opcode, make};
+use crate::evaluator::object::{Object, CompiledFunction};
+use crate::evaluator::builtins::BUILTINS;
+use symbol_table::{SymbolTable, Symbol, SymbolScope};
+use std::collections::HashMap;
 use std::error::Error;
 use std::{rc::Rc};
 
+// O1 folds constant expressions, shares equal constants, prunes branches of
+// conditions known at compile time and drops statements following a return.
+// O2 also fuses common instruction pairs into superinstructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2
+}
+
 pub struct  Compiler {
+    opt_level: OptLevel,
+    constants: Vec<Rc<Object>>,
+    // Where each constant that O1 shares is in constants
+    shared: HashMap<ConstantKey, usize>,
+    symbol_table: SymbolTable,
+    // One scope per function being compiled, the main program at the bottom
+    scopes: Vec<CompilationScope>
+}
+
+// The constants equal ones are shared for, as something to look them up by
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Integer(i64),
+    Str(Rc<String>)
+}
+
+#[derive(Clone, Copy)]
+struct EmittedInstruction {
+    opcode: Opcode,
+    position: usize
+}
+
+#[derive(Default)]
+struct CompilationScope {
     instructions: code::Instructions,
-    constants: Vec<Rc<Object>>
+    lines: code::LineTable,
+    last_instruction: Option<EmittedInstruction>,
+    previous_instruction: Option<EmittedInstruction>
 }
 
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct  ByteCode {
     pub instructions: code::Instructions,
-    pub constants: Vec<Rc<Object>>
+    pub constants: Vec<Rc<Object>>,
+    pub lines: code::LineTable
 }
 
 
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Compiler {
     pub fn new() -> Self {
+        return Compiler::with_opt_level(OptLevel::O0);
+    }
+
+    pub fn with_opt_level(opt_level: OptLevel) -> Self {
+        let mut symbol_table = SymbolTable::new();
+        for (i, (name, _)) in BUILTINS.iter().enumerate() {
+            symbol_table.define_builtin(i, name);
+        }
+
         return Compiler{
-            instructions: vec![],
-            constants: vec![]
+            opt_level,
+            constants: vec![],
+            shared: HashMap::new(),
+            symbol_table,
+            scopes: vec![CompilationScope::default()]
         }
     }
 
-    fn add_constant(&mut self, obj: Object) -> usize {
+    // A compiler for the next input of a REPL session, which sees the globals
+    // and constants of the inputs before it and starts an empty main program
+    pub fn continuation(&self) -> Self {
+        return Compiler{
+            opt_level: self.opt_level,
+            constants: self.constants.clone(),
+            shared: self.shared.clone(),
+            symbol_table: self.symbol_table.clone(),
+            scopes: vec![CompilationScope::default()]
+        }
+    }
+
+    pub fn symbol_table(&self) -> &SymbolTable {
+        return &self.symbol_table;
+    }
+
+    fn add_constant(&mut self, obj: Object) -> Result<usize, Box<dyn Error>> {
+        let key = match &obj {
+            Object::Integer(x) if self.opt_level >= OptLevel::O1 => Some(ConstantKey::Integer(*x)),
+            Object::Str(x) if self.opt_level >= OptLevel::O1 => Some(ConstantKey::Str(x.clone())),
+            _ => None
+        };
+        if let Some(id) = key.as_ref().and_then(|x| self.shared.get(x)) {
+            return Ok(*id);
+        }
+        let id = fit(Opcode::OpConstant, 0, self.constants.len(), "constants")?;
         self.constants.push(Rc::new(obj));
-        return self.constants.len() - 1;
+        if let Some(key) = key {
+            self.shared.insert(key, id);
+        }
+        return Ok(id);
+    }
+
+    // Defines a let binding or parameter, as long as its index fits the instructions that reach it
+    fn define(&mut self, name: &Ident) -> Result<Symbol, Box<dyn Error>> {
+        let symbol = self.symbol_table.define(&name.0);
+        match symbol.scope {
+            SymbolScope::Global => fit(Opcode::OpSetGlobal, 0, symbol.index, "global bindings")?,
+            _ => fit(Opcode::OpSetLocal, 0, symbol.index, "local bindings in one function")?
+        };
+        return Ok(symbol);
+    }
+
+    fn current_scope(&mut self) -> &mut CompilationScope {
+        return self.scopes.last_mut().expect("compiler has no scope");
+    }
+
+    fn current_position(&mut self) -> usize {
+        return self.current_scope().instructions.len();
+    }
+
+    fn emit(&mut self, op: Opcode, operands: &[usize]) -> usize {
+        let scope = self.current_scope();
+        let position = scope.instructions.len();
+        scope.instructions.extend(make(op, operands));
+        scope.previous_instruction = scope.last_instruction;
+        scope.last_instruction = Some(EmittedInstruction{ opcode: op, position });
+        return position;
+    }
+
+    fn last_instruction_is(&mut self, op: Opcode) -> bool {
+        return self.current_scope().last_instruction.is_some_and(|x| x.opcode == op);
+    }
+
+    fn remove_last_instruction(&mut self) {
+        let scope = self.current_scope();
+        if let Some(last) = scope.last_instruction {
+            scope.instructions.truncate(last.position);
+            scope.last_instruction = scope.previous_instruction;
+        }
+    }
+
+    // Rewrites the operand of an already emitted instruction, such as a jump target
+    fn change_operand(&mut self, position: usize, operand: usize) -> Result<(), Box<dyn Error>> {
+        let scope = self.current_scope();
+        let op = Opcode::from_byte(scope.instructions[position]).expect("not an instruction");
+        let ins = make(op, &[fit(op, 0, operand, "bytes of code in one function")?]);
+        scope.instructions[position..position + ins.len()].copy_from_slice(&ins);
+        Ok(())
+    }
+
+    fn enter_scope(&mut self) {
+        self.scopes.push(CompilationScope::default());
+        let outer = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
     }
 
-    pub fn compile_literal(&mut self, lit: Literal) -> Result<(), Box<Error>> {
+    fn leave_scope(&mut self) -> (CompilationScope, SymbolTable) {
+        let scope = self.scopes.pop().expect("compiler has no scope");
+        let outer = self.symbol_table.outer.take().expect("left the global scope");
+        let inner = std::mem::replace(&mut self.symbol_table, *outer);
+        return (scope, inner);
+    }
+
+    // Notes the source line of the code emitted from here on. Statements that
+    // were not parsed, at line 0, leave the line table as it is.
+    fn mark_line(&mut self, stmt: &Stmt) {
+        let line = stmt.location().start.line;
+        if line == 0 {
+            return;
+        }
+
+        let scope = self.current_scope();
+        let position = scope.instructions.len();
+        match scope.lines.last_mut() {
+            Some((_, last_line)) if *last_line == line => {},
+            Some((last_position, last_line)) if *last_position == position => *last_line = line,
+            _ => scope.lines.push((position, line))
+        }
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) -> Result<(), Box<dyn Error>> {
+        match symbol.scope {
+            SymbolScope::Global => self.emit(Opcode::OpGetGlobal, &[symbol.index]),
+            SymbolScope::Local => self.emit(Opcode::OpGetLocal, &[symbol.index]),
+            SymbolScope::Free => {
+                let index = fit(Opcode::OpGetFree, 0, symbol.index, "free variables in one function")?;
+                self.emit(Opcode::OpGetFree, &[index])
+            },
+            SymbolScope::Function => self.emit(Opcode::OpCurrentClosure, &[]),
+            SymbolScope::Builtin => self.emit(Opcode::OpGetBuiltin, &[symbol.index])
+        };
+        Ok(())
+    }
+
+    pub fn compile_literal(&mut self, lit: &Literal) -> Result<(), Box<dyn Error>> {
         match lit {
             Literal::Int(x) => {
-                let id = self.add_constant(Object::Integer(x));
-                self.instructions.push(code::Instruction::OpConstant(id));
+                let id = self.add_constant(Object::Integer(*x))?;
+                self.emit(Opcode::OpConstant, &[id]);
             },
             Literal::Bool(x) => {
-                let op = if x { code::Instruction::OpTrue } else { code::Instruction::OpFalse };
-                self.instructions.push(op)
+                let op = if *x { Opcode::OpTrue } else { Opcode::OpFalse };
+                self.emit(op, &[]);
             },
-            _ => return Err("Not supported yet!".into())
+            Literal::Str(x) => {
+                let id = self.add_constant(Object::Str(Rc::clone(x)))?;
+                self.emit(Opcode::OpConstant, &[id]);
+            },
+            Literal::Hash(pairs) => {
+                let len = fit(Opcode::OpHash, 0, pairs.len(), "pairs in a hash literal")?;
+                for (key, value) in pairs {
+                    self.compile_expr(key)?;
+                    self.compile_expr(value)?;
+                }
+                self.emit(Opcode::OpHash, &[len]);
+            }
         }
         Ok(())
     }
 
-    fn push_infix(&mut self, op: &Infix) -> Result<(), Box<Error>> {
+    fn push_infix(&mut self, op: &Infix) -> Result<(), Box<dyn Error>> {
         let ins = match op {
-            Infix::Plus => code::Instruction::OpAdd,
-            Infix::Multiply => code::Instruction::OpMultiply,
-            Infix::Minus => code::Instruction::OpSubtract,
-            Infix::Divide => code::Instruction::OpDivide,
+            Infix::Plus => Opcode::OpAdd,
+            Infix::Multiply => Opcode::OpMultiply,
+            Infix::Minus => Opcode::OpSubtract,
+            Infix::Divide => Opcode::OpDivide,
+            Infix::Equal => Opcode::OpEqual,
+            Infix::NotEqual => Opcode::OpNotEqual,
+            Infix::GT => Opcode::OpGreaterThan,
+            Infix::LT => Opcode::OpLessThan,
             _ => return Err("Not supported yet!".into())
         };
 
-        self.instructions.push(ins);
+        self.emit(ins, &[]);
         Ok(())
     }
 
-    pub fn compile_expr(&mut self, expr: Expr) -> Result<(), Box<Error>> {
+    fn compile_block(&mut self, block: &[Stmt]) -> Result<(), Box<dyn Error>> {
+        for stmt in block {
+            self.compile_statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    // Compiles a block whose value is left on the stack, as needed by conditionals
+    fn compile_block_value(&mut self, block: &Block) -> Result<(), Box<dyn Error>> {
+        self.compile_block(block)?;
+
+        if self.last_instruction_is(Opcode::OpPop) {
+            self.remove_last_instruction();
+        } else {
+            self.emit(Opcode::OpNull, &[]);
+        }
+        Ok(())
+    }
+
+    fn compile_if(&mut self, condition: &Expr, consequence: &Block, alternative: &Option<Block>) -> Result<(), Box<dyn Error>> {
+        if self.opt_level >= OptLevel::O1 {
+            // The condition has been folded already, so a constant one is a literal
+            if let Some(value) = optimizer::constant(condition) {
+                match (optimizer::is_truthy(&value), alternative) {
+                    (true, _) => self.compile_block_value(consequence)?,
+                    (false, Some(block)) => self.compile_block_value(block)?,
+                    (false, None) => { self.emit(Opcode::OpNull, &[]); }
+                }
+                return Ok(());
+            }
+        }
+
+        self.compile_expr(condition)?;
+        let jump_not_truthy = self.emit(Opcode::OpJumpNotTruthy, &[0]);
+
+        self.compile_block_value(consequence)?;
+        let jump = self.emit(Opcode::OpJump, &[0]);
+
+        let after_consequence = self.current_position();
+        self.change_operand(jump_not_truthy, after_consequence)?;
+
+        match alternative {
+            Some(block) => self.compile_block_value(block)?,
+            None => { self.emit(Opcode::OpNull, &[]); }
+        }
+
+        let after_alternative = self.current_position();
+        self.change_operand(jump, after_alternative)?;
+        Ok(())
+    }
+
+    fn compile_function(&mut self, name: Option<&Ident>, params: &Params, body: &Block) -> Result<(), Box<dyn Error>> {
+        self.enter_scope();
+
+        if let Some(name) = name {
+            self.symbol_table.define_function_name(&name.0);
+        }
+
+        for param in params {
+            self.define(param)?;
+        }
+
+        self.compile_block(body)?;
+
+        if self.last_instruction_is(Opcode::OpPop) {
+            self.remove_last_instruction();
+            self.emit(Opcode::OpReturnValue, &[]);
+        }
+
+        if !self.last_instruction_is(Opcode::OpReturnValue) {
+            self.emit(Opcode::OpReturn, &[]);
+        }
+
+        let (mut scope, symbol_table) = self.leave_scope();
+        if self.opt_level >= OptLevel::O2 {
+            let (instructions, lines) = code::peephole::optimize(&scope.instructions, &scope.lines);
+            scope.instructions = instructions;
+            scope.lines = lines;
+        }
+
+        let num_free = fit(Opcode::OpClosure, 1, symbol_table.free_symbols.len(), "free variables in one function")?;
+        for free in &symbol_table.free_symbols {
+            self.load_symbol(free)?;
+        }
+
+        let func = CompiledFunction{
+            instructions: scope.instructions,
+            num_locals: symbol_table.num_definitions,
+            num_params: params.len(),
+            lines: scope.lines
+        };
+        let id = self.add_constant(Object::CompiledFunction(Rc::new(func)))?;
+        self.emit(Opcode::OpClosure, &[id, num_free]);
+        Ok(())
+    }
+
+    pub fn compile_expr(&mut self, expr: &Expr) -> Result<(), Box<dyn Error>> {
         match expr {
             Expr::Infix(left,op,right) => {
-                self.compile_expr(*left)?;
-                self.compile_expr(*right)?;
-                self.push_infix(&op)?;
+                self.compile_expr(left)?;
+                self.compile_expr(right)?;
+                self.push_infix(op)?;
+            },
+            Expr::Prefix(op, right) => {
+                self.compile_expr(right)?;
+                match op {
+                    Prefix::Minus => { self.emit(Opcode::OpMinus, &[]); },
+                    Prefix::Not => { self.emit(Opcode::OpBang, &[]); },
+                    Prefix::Plus => {}
+                }
             },
             Expr::LiteralExpr(lit) => self.compile_literal(lit)?,
-            _ => return Err("Not supported yet!".into())
+            Expr::If(condition, consequence, alternative) => self.compile_if(condition, consequence, alternative)?,
+            Expr::IdentExpr(ident) => {
+                let symbol = match self.symbol_table.resolve(&ident.0) {
+                    Some(symbol) => symbol,
+                    None => return Err(format!("unknown identifier: {}", ident).into())
+                };
+                self.load_symbol(&symbol)?;
+            },
+            Expr::Array(elements) => {
+                let len = fit(Opcode::OpArray, 0, elements.len(), "elements in an array literal")?;
+                for element in elements {
+                    self.compile_expr(element)?;
+                }
+                self.emit(Opcode::OpArray, &[len]);
+            },
+            Expr::IndexExpr(left, index) => {
+                self.compile_expr(left)?;
+                self.compile_expr(index)?;
+                self.emit(Opcode::OpIndex, &[]);
+            },
+            Expr::Fn(params, body) => self.compile_function(None, params, body)?,
+            Expr::Call(function, args) => {
+                self.compile_expr(function)?;
+                let len = fit(Opcode::OpCall, 0, args.len(), "arguments in one call")?;
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.emit(Opcode::OpCall, &[len]);
+            }
         }
         return Ok(());
     }
 
-    pub fn compile_statement(&mut self, stmt: Stmt) -> Result<(), Box<Error>> {
+    pub fn compile_statement(&mut self, stmt: &Stmt) -> Result<(), Box<dyn Error>> {
+        self.mark_line(stmt);
         match stmt {
-            Stmt::ExprStmt(x) => {
+            Stmt::ExprStmt(x, _) => {
                 self.compile_expr(x)?;
-                self.instructions.push(code::Instruction::OpPop);
-                Ok(())
+                self.emit(Opcode::OpPop, &[]);
+            },
+            Stmt::LetStmt(ident, Expr::Fn(params, body), _) => {
+                self.compile_function(Some(ident), params, body)?;
+                let symbol = self.define(ident)?;
+                self.emit_set(&symbol);
             },
-            _ => Err("Not supported yet!".into())
+            Stmt::LetStmt(ident, x, _) => {
+                self.compile_expr(x)?;
+                let symbol = self.define(ident)?;
+                self.emit_set(&symbol);
+            },
+            Stmt::ReturnStmt(x, _) => {
+                self.compile_expr(x)?;
+                self.emit(Opcode::OpReturnValue, &[]);
+            }
         }
+        Ok(())
     }
 
-    pub fn compile(&mut self, program: Program) -> Result<(), Box<Error>> {
-        for stmnt in program {
-            self.compile_statement(stmnt)?;
-        }
+    fn emit_set(&mut self, symbol: &Symbol) {
+        match symbol.scope {
+            SymbolScope::Global => self.emit(Opcode::OpSetGlobal, &[symbol.index]),
+            _ => self.emit(Opcode::OpSetLocal, &[symbol.index])
+        };
+    }
 
-        return Ok(())
+    // Compiles program, filling in the line tables from where its statements were
+    pub fn compile(&mut self, program: Program) -> Result<(), Box<dyn Error>> {
+        let program = match self.opt_level {
+            OptLevel::O0 => program,
+            _ => optimizer::optimize(program)
+        };
+        return self.compile_block(&program);
     }
 
 
     pub fn bytecode(&self) -> ByteCode {
-        return ByteCode{ instructions: self.instructions.clone(), constants: self.constants.clone() }
+        let main = &self.scopes[0];
+        let (instructions, lines) = match self.opt_level {
+            OptLevel::O2 => code::peephole::optimize(&main.instructions, &main.lines),
+            _ => (main.instructions.clone(), main.lines.clone())
+        };
+
+        return ByteCode{
+            instructions,
+            constants: self.constants.clone(),
+            lines
+        }
     }
 
 
 
-}
\ No newline at end of file
+}
+
+// Gives back value if it fits in the operand-th operand of op
+fn fit(op: Opcode, operand: usize, value: usize, what: &str) -> Result<usize, Box<dyn Error>> {
+    let max = code::max_operand(op, operand);
+    if value > max {
+        return Err(format!("too many {}: {} does not fit in {}, which takes at most {}", what, value, op.definition().name, max).into());
+    }
+    return Ok(value);
+}
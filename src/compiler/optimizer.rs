@@ -0,0 +1,97 @@
+use crate::evaluator::memory;
+use crate::parser::ast::*;
+use crate::parser::ast::visit;
+use std::rc::Rc;
+
+// Rewrites a program before O1 compiles it. Expressions built only from
+// literals are evaluated, and statements following a return are dropped.
+// Anything that would fail at run time, such as a division by zero or
+// mismatched types, is left alone so that the VM still reports the error.
+pub fn optimize(program: Program) -> Program {
+    return ConstantFolder.fold_block(program);
+}
+
+struct ConstantFolder;
+
+impl Folder for ConstantFolder {
+    fn fold_block(&mut self, mut block: Block) -> Block {
+        // Nothing after a return can run
+        if let Some(i) = block.iter().position(|x| matches!(x, Stmt::ReturnStmt(..))) {
+            block.truncate(i + 1);
+        }
+        return visit::rebuild_block(self, block);
+    }
+
+    // Operands are folded first, so that whole expressions fold from the inside out
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        let expr = visit::rebuild_expr(self, expr);
+        let folded = match &expr {
+            Expr::Prefix(op, right) => constant(right).and_then(|x| fold_prefix(op, x)),
+            Expr::Infix(left, op, right) => constant(left).zip(constant(right)).and_then(|(x, y)| fold_infix(x, op, y)),
+            _ => None
+        };
+        return match folded {
+            Some(x) => Expr::LiteralExpr(x),
+            None => expr
+        };
+    }
+}
+
+// The value of an expression that is a literal, other than a hash
+pub fn constant(expr: &Expr) -> Option<Literal> {
+    return match expr {
+        Expr::LiteralExpr(Literal::Hash(_)) => None,
+        Expr::LiteralExpr(x) => Some(x.clone()),
+        _ => None
+    };
+}
+
+fn fold_prefix(op: &Prefix, right: Literal) -> Option<Literal> {
+    return match (op, right) {
+        (Prefix::Minus, Literal::Int(x)) => Some(Literal::Int(x.wrapping_neg())),
+        (Prefix::Plus, x) => Some(x),
+        (Prefix::Not, Literal::Bool(x)) => Some(Literal::Bool(!x)),
+        (Prefix::Not, _) => Some(Literal::Bool(false)),
+        _ => None
+    }
+}
+
+fn fold_infix(left: Literal, op: &Infix, right: Literal) -> Option<Literal> {
+    return match (left, right) {
+        (Literal::Int(x), Literal::Int(y)) => match op {
+            Infix::Plus => Some(Literal::Int(x.wrapping_add(y))),
+            Infix::Minus => Some(Literal::Int(x.wrapping_sub(y))),
+            Infix::Multiply => Some(Literal::Int(x.wrapping_mul(y))),
+            Infix::Divide if y != 0 => Some(Literal::Int(x.wrapping_div(y))),
+            Infix::Equal => Some(Literal::Bool(x == y)),
+            Infix::NotEqual => Some(Literal::Bool(x != y)),
+            Infix::GT => Some(Literal::Bool(x > y)),
+            Infix::LT => Some(Literal::Bool(x < y)),
+            _ => None
+        },
+        (Literal::Bool(x), Literal::Bool(y)) => match op {
+            Infix::Equal => Some(Literal::Bool(x == y)),
+            Infix::NotEqual => Some(Literal::Bool(x != y)),
+            _ => None
+        },
+        (Literal::Str(x), Literal::Str(y)) => match op {
+            // Charged as the VM would be, so that a limit the compile runs under holds
+            Infix::Plus => {
+                memory::allocate(memory::string_size(x.len() + y.len())).ok()?;
+                Some(Literal::Str(Rc::new(format!("{}{}", x, y))))
+            },
+            Infix::Equal => Some(Literal::Bool(x == y)),
+            Infix::NotEqual => Some(Literal::Bool(x != y)),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// Whether a folded condition selects the consequence of an if
+pub fn is_truthy(lit: &Literal) -> bool {
+    return match lit {
+        Literal::Bool(x) => *x,
+        _ => true
+    }
+}
@@ -0,0 +1,198 @@
+use crate::code::{Instructions, LineTable};
+use crate::evaluator::object::{Object, CompiledFunction};
+use super::ByteCode;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+// Layout of a .mbc file, all integers big-endian:
+//
+//   magic      "MBC\0"
+//   version    u16
+//   flags      u8, FLAG_DEBUG when the debug section is present
+//   constants  u32 count, then per constant a tag byte and its payload:
+//                TAG_INT       i64
+//                TAG_STR       u32 length, UTF-8 bytes
+//                TAG_FUNCTION  u16 locals, u8 params, u32 length, instructions
+//   main       u32 length, instructions
+//   debug      the line table of the main program, then one per function
+//              constant in pool order, each as u32 count and (u32, u32) pairs
+pub const MAGIC: &[u8; 4] = b"MBC\0";
+// Bumped whenever the instruction set changes
+pub const VERSION: u16 = 3;
+
+const FLAG_DEBUG: u8 = 1;
+
+const TAG_INT: u8 = 0;
+const TAG_STR: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+impl ByteCode {
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Box<dyn Error>> {
+        let functions: Vec<&CompiledFunction> = self.constants.iter().filter_map(|x| match x.as_ref() {
+            Object::CompiledFunction(func) => Some(func.as_ref()),
+            _ => None
+        }).collect();
+        let debug = !self.lines.is_empty() || functions.iter().any(|func| !func.lines.is_empty());
+
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_be_bytes())?;
+        w.write_all(&[if debug { FLAG_DEBUG } else { 0 }])?;
+
+        write_len(w, self.constants.len())?;
+        for constant in &self.constants {
+            match constant.as_ref() {
+                Object::Integer(x) => {
+                    w.write_all(&[TAG_INT])?;
+                    w.write_all(&x.to_be_bytes())?;
+                },
+                Object::Str(x) => {
+                    w.write_all(&[TAG_STR])?;
+                    write_bytes(w, x.as_bytes())?;
+                },
+                Object::CompiledFunction(func) => {
+                    if func.num_locals > u16::MAX as usize || func.num_params > u8::MAX as usize {
+                        return Err("function has too many locals to serialize".into());
+                    }
+                    w.write_all(&[TAG_FUNCTION])?;
+                    w.write_all(&(func.num_locals as u16).to_be_bytes())?;
+                    w.write_all(&[func.num_params as u8])?;
+                    write_bytes(w, &func.instructions)?;
+                },
+                x => return Err(format!("cannot serialize constant {}", x).into())
+            }
+        }
+
+        write_bytes(w, &self.instructions)?;
+
+        if debug {
+            write_lines(w, &self.lines)?;
+            for func in functions {
+                write_lines(w, &func.lines)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<ByteCode, Box<dyn Error>> {
+        let mut magic = [0; 4];
+        if r.read_exact(&mut magic).is_err() || &magic != MAGIC {
+            return Err("not a bytecode file".into());
+        }
+
+        let version = u16::from_be_bytes(read_array(r)?);
+        if version != VERSION {
+            return Err(format!("unsupported bytecode version {}, expected {}", version, VERSION).into());
+        }
+
+        let [flags] = read_array(r)?;
+        if flags & !FLAG_DEBUG != 0 {
+            return Err(format!("unknown bytecode flags {:#04x}", flags).into());
+        }
+
+        let mut constants = vec![];
+        for _ in 0..read_len(r)? {
+            let [tag] = read_array(r)?;
+            let constant = match tag {
+                TAG_INT => Object::Integer(i64::from_be_bytes(read_array(r)?)),
+                TAG_STR => match String::from_utf8(read_bytes(r)?) {
+                    Ok(x) => Object::Str(Rc::new(x)),
+                    Err(_) => return Err("string constant is not valid UTF-8".into())
+                },
+                TAG_FUNCTION => {
+                    let num_locals = u16::from_be_bytes(read_array(r)?) as usize;
+                    let [num_params] = read_array(r)?;
+                    let instructions = read_bytes(r)?;
+                    Object::CompiledFunction(Rc::new(CompiledFunction{
+                        instructions,
+                        num_locals,
+                        num_params: num_params as usize,
+                        lines: vec![]
+                    }))
+                },
+                x => return Err(format!("unknown constant tag {}", x).into())
+            };
+            constants.push(Rc::new(constant));
+        }
+
+        let instructions: Instructions = read_bytes(r)?;
+
+        let mut lines = vec![];
+        if flags & FLAG_DEBUG != 0 {
+            lines = read_lines(r, &instructions)?;
+            for constant in constants.iter_mut() {
+                if let Object::CompiledFunction(func) = constant.as_ref() {
+                    let mut func = func.as_ref().clone();
+                    func.lines = read_lines(r, &func.instructions)?;
+                    *constant = Rc::new(Object::CompiledFunction(Rc::new(func)));
+                }
+            }
+        }
+
+        if r.read(&mut [0])? != 0 {
+            return Err("trailing data after bytecode".into());
+        }
+
+        return Ok(ByteCode{ instructions, constants, lines });
+    }
+}
+
+fn write_len<W: Write>(w: &mut W, len: usize) -> Result<(), Box<dyn Error>> {
+    if len > u32::MAX as usize {
+        return Err("section too large to serialize".into());
+    }
+    w.write_all(&(len as u32).to_be_bytes())?;
+    Ok(())
+}
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    write_len(w, bytes.len())?;
+    w.write_all(bytes)?;
+    Ok(())
+}
+
+fn write_lines<W: Write>(w: &mut W, lines: &LineTable) -> Result<(), Box<dyn Error>> {
+    write_len(w, lines.len())?;
+    for (position, line) in lines {
+        write_len(w, *position)?;
+        write_len(w, *line)?;
+    }
+    Ok(())
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> Result<[u8; N], Box<dyn Error>> {
+    let mut buf = [0; N];
+    if r.read_exact(&mut buf).is_err() {
+        return Err("unexpected end of bytecode file".into());
+    }
+    return Ok(buf);
+}
+
+fn read_len<R: Read>(r: &mut R) -> Result<usize, Box<dyn Error>> {
+    return Ok(u32::from_be_bytes(read_array(r)?) as usize);
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> Result<Vec<u8>, Box<dyn Error>> {
+    let len = read_len(r)?;
+    let mut buf = vec![];
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err("unexpected end of bytecode file".into());
+    }
+    return Ok(buf);
+}
+
+// Reads the line table of ins. Its entries must be in order, each inside ins and
+// on a line counted from 1, as the compiler writes them.
+fn read_lines<R: Read>(r: &mut R, ins: &[u8]) -> Result<LineTable, Box<dyn Error>> {
+    let mut lines: LineTable = vec![];
+    for _ in 0..read_len(r)? {
+        let (position, line) = (read_len(r)?, read_len(r)?);
+        let after_last = lines.last().is_none_or(|(last, _)| position > *last);
+        if position >= ins.len() || line == 0 || !after_last {
+            return Err(format!("line table entry out of range: line {} at {}", line, position).into());
+        }
+        lines.push((position, line));
+    }
+    return Ok(lines);
+}
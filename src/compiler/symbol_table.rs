@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Free,
+    Function,
+    Builtin
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub scope: SymbolScope,
+    pub index: usize
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    pub outer: Option<Box<SymbolTable>>,
+    store: HashMap<String, Symbol>,
+    pub num_definitions: usize,
+    // The symbols captured from enclosing scopes, in the order they are loaded onto the stack
+    pub free_symbols: Vec<Symbol>
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        return SymbolTable::default();
+    }
+
+    pub fn new_enclosed(outer: SymbolTable) -> Self {
+        return SymbolTable{
+            outer: Some(Box::new(outer)),
+            ..SymbolTable::default()
+        }
+    }
+
+    pub fn define(&mut self, name: &str) -> Symbol {
+        let scope = if self.outer.is_some() { SymbolScope::Local } else { SymbolScope::Global };
+        let symbol = Symbol{ name: name.to_string(), scope, index: self.num_definitions };
+        self.store.insert(name.to_string(), symbol.clone());
+        self.num_definitions += 1;
+        return symbol;
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &str) -> Symbol {
+        let symbol = Symbol{ name: name.to_string(), scope: SymbolScope::Builtin, index };
+        self.store.insert(name.to_string(), symbol.clone());
+        return symbol;
+    }
+
+    pub fn define_function_name(&mut self, name: &str) -> Symbol {
+        let symbol = Symbol{ name: name.to_string(), scope: SymbolScope::Function, index: 0 };
+        self.store.insert(name.to_string(), symbol.clone());
+        return symbol;
+    }
+
+    fn define_free(&mut self, original: Symbol) -> Symbol {
+        let symbol = Symbol{ name: original.name.clone(), scope: SymbolScope::Free, index: self.free_symbols.len() };
+        self.free_symbols.push(original);
+        self.store.insert(symbol.name.clone(), symbol.clone());
+        return symbol;
+    }
+
+    // The globals defined so far in the order they were defined, a name
+    // given to a second global only lists the latest
+    pub fn globals(&self) -> Vec<Symbol> {
+        let mut globals: Vec<Symbol> = self.store.values().filter(|x| x.scope == SymbolScope::Global).cloned().collect();
+        globals.sort_by_key(|x| x.index);
+        return globals;
+    }
+
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        if let Some(symbol) = self.store.get(name) {
+            return Some(symbol.clone());
+        }
+
+        let symbol = self.outer.as_mut()?.resolve(name)?;
+        return match symbol.scope {
+            SymbolScope::Global | SymbolScope::Builtin => Some(symbol),
+            _ => Some(self.define_free(symbol))
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+// The lines entered before, oldest first. With a file each new line is
+// appended to it straight away, so sessions side by side all keep theirs.
+
+pub const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>
+}
+
+impl History {
+    pub fn new() -> Self {
+        return History::default();
+    }
+
+    // The history kept in path, a file that does not exist yet is an empty one.
+    // Only the last MAX_ENTRIES lines are kept, the file is cut down to them.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let mut entries: Vec<String> = match fs::read_to_string(&path) {
+            Ok(x) => x.lines().map(|x| x.to_string()).collect(),
+            Err(x) if x.kind() == io::ErrorKind::NotFound => vec![],
+            Err(x) => return Err(x)
+        };
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            let mut contents = entries.join("\n");
+            contents.push('\n');
+            fs::write(&path, contents)?;
+        }
+        return Ok(History{ entries, path: Some(path) });
+    }
+
+    pub fn entries(&self) -> &[String] {
+        return &self.entries;
+    }
+
+    // Blank lines and repeats of the last line are not kept
+    pub fn add(&mut self, line: &str) -> io::Result<()> {
+        if line.trim().is_empty() || self.entries.last().map(|x| x.as_str()) == Some(line) {
+            return Ok(());
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)?;
+        }
+        return Ok(());
+    }
+
+    // The latest entry before `before` containing query
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        return self.entries[..before].iter().rposition(|x| x.contains(query));
+    }
+}
@@ -0,0 +1,410 @@
+pub mod history;
+mod terminal;
+
+use crate::evaluator::builtins::BUILTINS;
+use crate::lexer::KEYWORDS;
+use history::History;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+
+// A line editor for the REPL. On a terminal, keys are read one at a time in
+// raw mode and the line is drawn again after each of them; anything else,
+// such as piped input, is read a line at a time as it is.
+//
+// Keys as in readline: the arrows, Home and End move around, Up and Down go
+// through the history, Ctrl-R searches it, Tab completes keywords, builtins
+// and the names in `names`. Ctrl-A, E, B, F, K, U and W do what they usually do.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    KillToEnd,
+    KillToStart,
+    KillWord,
+    Search,
+    Cancel,
+    Clear,
+    Interrupt,
+    Eof,
+    Unknown
+}
+
+// The text being edited and where the cursor is in it, counted in chars
+#[derive(Debug, Clone, Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize
+}
+
+impl Line {
+    fn from(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        let cursor = chars.len();
+        return Line{ chars, cursor };
+    }
+
+    fn text(&self) -> String {
+        return self.chars.iter().collect();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    // The whitespace before the cursor and the word before that
+    fn kill_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    // Where the identifier ending at the cursor starts
+    fn word_start(&self) -> usize {
+        let mut start = self.cursor;
+        while start > 0 && (self.chars[start - 1].is_ascii_alphabetic() || self.chars[start - 1] == '_') {
+            start -= 1;
+        }
+        return start;
+    }
+}
+
+// A reverse search through the history, and the entry it has found
+#[derive(Debug, Default)]
+struct Search {
+    query: String,
+    found: Option<usize>
+}
+
+#[derive(Debug, Default)]
+pub struct Editor {
+    history: History,
+    // The names bound in the session, which Tab completes besides keywords and builtins
+    pub names: Vec<String>
+}
+
+impl Editor {
+    pub fn new(history: History) -> Self {
+        return Editor{ history, names: vec![] };
+    }
+
+    pub fn history(&self) -> &History {
+        return &self.history;
+    }
+
+    // The next line without its newline, None at the end of the input. Ctrl-C
+    // gives up on the line with an error of kind Interrupted.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let stdin = io::stdin();
+        if stdin.is_terminal() {
+            if let Ok(raw) = terminal::RawMode::enable() {
+                let result = self.read_line_from(prompt, &mut stdin.lock(), &mut io::stdout());
+                drop(raw);
+                return result;
+            }
+        }
+
+        print!("{}", prompt);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(None);
+        }
+        return Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()));
+    }
+
+    // Edits a line with the keys read from input, drawing it on output
+    pub fn read_line_from(&mut self, prompt: &str, input: &mut impl Read, output: &mut impl Write) -> io::Result<Option<String>> {
+        let mut line = Line::default();
+        // How far Up has gone back through the history, the line being typed is kept
+        // aside while it does
+        let mut position = self.history.entries().len();
+        let mut typed = String::new();
+        let mut search: Option<Search> = None;
+        refresh(prompt, &line, output)?;
+
+        loop {
+            let key = match read_key(input)? {
+                Some(key) => key,
+                None if line.chars.is_empty() => return Ok(None),
+                None => Key::Enter
+            };
+
+            if let Some(mut current) = search.take() {
+                let entries = self.history.entries();
+                let searching = match key {
+                    Key::Char(c) => {
+                        // The entry found so far may still match
+                        current.query.push(c);
+                        current.found = self.history.search(&current.query, current.found.map_or(entries.len(), |x| x + 1));
+                        true
+                    },
+                    Key::Backspace => {
+                        current.query.pop();
+                        current.found = self.history.search(&current.query, entries.len());
+                        true
+                    },
+                    Key::Search => {
+                        let before = current.found.unwrap_or(entries.len());
+                        if let Some(found) = self.history.search(&current.query, before) {
+                            current.found = Some(found);
+                        }
+                        true
+                    },
+                    Key::Cancel => false,
+                    // Any other key takes the entry found and goes on as usual
+                    _ => {
+                        if let Some(found) = current.found {
+                            line = Line::from(&entries[found]);
+                        }
+                        false
+                    }
+                };
+
+                if searching {
+                    draw_search(&current, entries, output)?;
+                    search = Some(current);
+                    continue;
+                }
+            }
+
+            match key {
+                Key::Enter => {
+                    write!(output, "\r\n")?;
+                    let text = line.text();
+                    self.history.add(&text)?;
+                    return Ok(Some(text));
+                },
+                Key::Interrupt => {
+                    write!(output, "^C\r\n")?;
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+                },
+                Key::Eof if line.chars.is_empty() => {
+                    write!(output, "\r\n")?;
+                    return Ok(None);
+                },
+                Key::Eof | Key::Delete => line.delete(),
+                Key::Char(c) => line.insert(c),
+                Key::Backspace => line.backspace(),
+                Key::Left => line.cursor = line.cursor.saturating_sub(1),
+                Key::Right => line.cursor = (line.cursor + 1).min(line.chars.len()),
+                Key::Home => line.cursor = 0,
+                Key::End => line.cursor = line.chars.len(),
+                Key::KillToEnd => line.chars.truncate(line.cursor),
+                Key::KillToStart => {
+                    line.chars.drain(..line.cursor);
+                    line.cursor = 0;
+                },
+                Key::KillWord => line.kill_word(),
+                Key::Up => {
+                    if position > 0 {
+                        if position == self.history.entries().len() {
+                            typed = line.text();
+                        }
+                        position -= 1;
+                        line = Line::from(&self.history.entries()[position]);
+                    }
+                },
+                Key::Down => {
+                    if position < self.history.entries().len() {
+                        position += 1;
+                        line = match self.history.entries().get(position) {
+                            Some(entry) => Line::from(entry),
+                            None => Line::from(&typed)
+                        };
+                    }
+                },
+                Key::Tab => self.complete(&mut line, output)?,
+                Key::Search => {
+                    let current = Search::default();
+                    draw_search(&current, self.history.entries(), output)?;
+                    search = Some(current);
+                    continue;
+                },
+                Key::Clear => write!(output, "\x1b[H\x1b[2J")?,
+                Key::Cancel | Key::Unknown => {}
+            }
+            refresh(prompt, &line, output)?;
+        }
+    }
+
+    // Completes the identifier before the cursor as far as all the candidates
+    // agree, and lists them when they do not agree on anything more
+    fn complete(&self, line: &mut Line, output: &mut impl Write) -> io::Result<()> {
+        let start = line.word_start();
+        let prefix: String = line.chars[start..line.cursor].iter().collect();
+        if prefix.is_empty() {
+            return Ok(());
+        }
+
+        let candidates = completions(&prefix, &self.names);
+        let common = match candidates.first() {
+            Some(first) => candidates.iter().fold(first.as_str(), |common, x| {
+                let len = common.chars().zip(x.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+                &common[..len]
+            }),
+            None => return Ok(())
+        };
+
+        if common.len() > prefix.len() {
+            for c in common[prefix.len()..].chars() {
+                line.insert(c);
+            }
+        } else if candidates.len() > 1 {
+            write!(output, "\r\n{}\r\n", candidates.join("  "))?;
+        }
+        return Ok(());
+    }
+}
+
+// The keywords, builtins and names starting with prefix, sorted and without repeats
+pub fn completions(prefix: &str, names: &[String]) -> Vec<String> {
+    let words = KEYWORDS.iter().chain(BUILTINS.iter().map(|(name, _)| name)).map(|x| x.to_string());
+    let mut candidates: Vec<String> = words.chain(names.iter().cloned()).filter(|x| x.starts_with(prefix)).collect();
+    candidates.sort();
+    candidates.dedup();
+    return candidates;
+}
+
+// Draws the prompt and the line over the one before and puts the cursor back
+fn refresh(prompt: &str, line: &Line, output: &mut impl Write) -> io::Result<()> {
+    write!(output, "\r{}{}\x1b[K\r", prompt, line.text())?;
+    let column = prompt.chars().count() + line.cursor;
+    if column > 0 {
+        write!(output, "\x1b[{}C", column)?;
+    }
+    return output.flush();
+}
+
+fn draw_search(search: &Search, entries: &[String], output: &mut impl Write) -> io::Result<()> {
+    let found = search.found.map_or("", |x| entries[x].as_str());
+    let failing = if search.found.is_none() && !search.query.is_empty() { "failing " } else { "" };
+    write!(output, "\r({}reverse-i-search)`{}': {}\x1b[K", failing, search.query, found)?;
+    return output.flush();
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(x) if x.kind() == io::ErrorKind::Interrupted => continue,
+            Err(x) => return Err(x)
+        }
+    }
+}
+
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let byte = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None)
+    };
+
+    let key = match byte {
+        1 => Key::Home,
+        2 => Key::Left,
+        3 => Key::Interrupt,
+        4 => Key::Eof,
+        5 => Key::End,
+        6 => Key::Right,
+        7 => Key::Cancel,
+        8 | 127 => Key::Backspace,
+        9 => Key::Tab,
+        10 | 13 => Key::Enter,
+        11 => Key::KillToEnd,
+        12 => Key::Clear,
+        14 => Key::Down,
+        16 => Key::Up,
+        18 => Key::Search,
+        21 => Key::KillToStart,
+        23 => Key::KillWord,
+        27 => read_escape(input)?,
+        0..=31 => Key::Unknown,
+        _ => read_char(byte, input)?
+    };
+    return Ok(Some(key));
+}
+
+// The rest of a char in UTF-8 whose first byte is lead
+fn read_char(lead: u8, input: &mut impl Read) -> io::Result<Key> {
+    let len = match lead {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(Key::Unknown)
+    };
+    let mut bytes = vec![lead];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(Key::Unknown)
+        }
+    }
+    return Ok(match std::str::from_utf8(&bytes) {
+        Ok(x) => Key::Char(x.chars().next().expect("decoded an empty char")),
+        Err(_) => Key::Unknown
+    });
+}
+
+// The keys terminals send as ESC [ or ESC O, then parameters and a final byte
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    match read_byte(input)? {
+        Some(b'[') | Some(b'O') => {},
+        _ => return Ok(Key::Unknown)
+    }
+
+    let mut sequence = String::new();
+    loop {
+        match read_byte(input)? {
+            Some(byte) => {
+                sequence.push(byte as char);
+                if (0x40..=0x7e).contains(&byte) {
+                    break;
+                }
+            },
+            None => return Ok(Key::Unknown)
+        }
+    }
+
+    return Ok(match sequence.as_str() {
+        "A" => Key::Up,
+        "B" => Key::Down,
+        "C" => Key::Right,
+        "D" => Key::Left,
+        "H" | "1~" | "7~" => Key::Home,
+        "F" | "4~" | "8~" => Key::End,
+        "3~" => Key::Delete,
+        _ => Key::Unknown
+    });
+}
@@ -0,0 +1,36 @@
+use std::io;
+use std::process::{Command, Stdio};
+
+// Puts the terminal on stdin into raw mode, where keys arrive one at a time
+// and are not echoed, and puts back the settings it found when dropped.
+// stty does the work so that no bindings to the C library are needed.
+
+pub struct RawMode {
+    saved: String
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<Self> {
+        let output = Command::new("stty").arg("-g").stdin(Stdio::inherit()).stderr(Stdio::null()).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other("stty could not read the terminal settings"));
+        }
+        let saved = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        stty(&["raw", "-echo"])?;
+        return Ok(RawMode{ saved });
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("stty").args(args).stdin(Stdio::inherit()).stderr(Stdio::null()).status()?;
+    if !status.success() {
+        return Err(io::Error::other("stty could not change the terminal settings"));
+    }
+    return Ok(());
+}
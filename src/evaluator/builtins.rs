@@ -3,6 +3,20 @@ use crate::evaluator::*;
 
 pub type Builtin = fn(Vec<Object>) -> Object;
 
+// The position of a builtin in this table is its index in `OpGetBuiltin`
+pub const BUILTINS: [(&str, Builtin); 6] = [
+    ("len", len),
+    ("first", first),
+    ("last", last),
+    ("rest", rest),
+    ("push", push),
+    ("puts", puts)
+];
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    return BUILTINS.iter().find(|(n, _)| *n == name).map(|(_, f)| *f);
+}
+
 
 pub fn len(args: Vec<Object>) -> Object {
     if args.len() != 1 {
@@ -20,7 +34,7 @@ pub fn first(args: Vec<Object>) -> Object {
         return Object::Error("wrong number of arguments".to_string());
     }
     match &args[0] {
-        Object::Array(x) => x[0].clone(),
+        Object::Array(x) => x.first().cloned().unwrap_or(Object::Null),
         _ => Object::Error("argument to first not supported".to_string())
     }
 }
@@ -31,7 +45,7 @@ pub fn last(args: Vec<Object>) -> Object {
         return Object::Error("wrong number of arguments".to_string());
     }
     match &args[0] {
-        Object::Array(x) => x[x.len()-1].clone(),
+        Object::Array(x) => x.last().cloned().unwrap_or(Object::Null),
         _ => Object::Error("argument to last not supported".to_string())
     }
 }
@@ -42,7 +56,11 @@ pub fn rest(args: Vec<Object>) -> Object {
         return Object::Error("wrong number of arguments".to_string());
     }
     match &args[0] {
-        Object::Array(x) => Object::Array(Rc::new(x[1..].to_vec())),
+        Object::Array(x) if x.is_empty() => Object::Null,
+        Object::Array(x) => match memory::allocate(memory::array_size(x.len() - 1)) {
+            Ok(_) => Object::Array(Rc::new(x[1..].to_vec())),
+            Err(x) => Object::Error(x.to_string())
+        },
         _ => Object::Error("argument to rest not supported".to_string())
     }
 }
@@ -53,6 +71,9 @@ pub fn push(args: Vec<Object>) -> Object {
     }
     match &args[0] {
         Object::Array(x) => {
+            if let Err(x) = memory::allocate(memory::array_size(x.len() + 1)) {
+                return Object::Error(x.to_string());
+            }
             let mut result = x.as_ref().clone();
             result.push(args[1].clone());
             Object::Array(Rc::new(result))
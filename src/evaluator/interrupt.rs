@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use std::{error::Error, fmt};
+
+// Lets a host stop a run from another thread, or once a deadline has passed.
+// Engines only look every CHECK_INTERVAL steps, so stopping is cooperative
+// and costs next to nothing while nobody asks for it.
+
+const CHECK_INTERVAL: u32 = 1024;
+
+// Cancels every run it was handed to, clones share the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        return CancelToken::default();
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.0.load(Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution timed out")
+    }
+}
+
+impl Error for Timeout {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Watchdog {
+    pub token: Option<CancelToken>,
+    pub deadline: Option<Instant>,
+    // Steps until the next look at the token and the clock
+    countdown: u32
+}
+
+impl Watchdog {
+    pub fn new(token: Option<CancelToken>, deadline: Option<Instant>) -> Self {
+        return Watchdog{ token, deadline, countdown: 0 };
+    }
+
+    pub fn check(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return Ok(());
+        }
+        self.countdown = CHECK_INTERVAL;
+
+        if let Some(token) = &self.token {
+            if token.is_cancelled() {
+                return Err(Box::new(Cancelled));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(Box::new(Timeout));
+            }
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // Watches the evaluation running on this thread, if anything does
+    static WATCHDOG: RefCell<Option<Watchdog>> = const { RefCell::new(None) };
+}
+
+// Runs f, an evaluation, under the watchdog
+pub fn with_watchdog<T>(watchdog: Watchdog, f: impl FnOnce() -> T) -> T {
+    let outer = WATCHDOG.with(|x| x.replace(Some(watchdog)));
+    let result = f();
+    WATCHDOG.with(|x| x.replace(outer));
+    return result;
+}
+
+pub fn check() -> Result<(), Box<dyn Error>> {
+    return WATCHDOG.with(|x| match x.borrow_mut().as_mut() {
+        Some(watchdog) => watchdog.check(),
+        None => Ok(())
+    });
+}
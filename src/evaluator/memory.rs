@@ -0,0 +1,56 @@
+use crate::evaluator::object::Object;
+use std::cell::Cell;
+use std::{error::Error, fmt, mem};
+
+// Accounts for the arrays, strings and hashes a script creates. Every engine
+// charges each new one here before building it, so that a host can cap what a
+// run allocates with `with_limit`. Sizes are estimates of the bytes an object
+// owns itself; elements shared with other objects are not counted again.
+//
+// The count is of everything allocated over the run, not of what is still
+// held: nothing is given back when an object is dropped, so a loop that keeps
+// building short-lived strings runs out as surely as one that keeps them.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryLimitExceeded;
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "memory limit exceeded")
+    }
+}
+
+impl Error for MemoryLimitExceeded {}
+
+thread_local! {
+    // The limit and the bytes allocated so far against it, None when nothing is accounted
+    static ALLOCATED: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+// Runs f with at most limit bytes to allocate in total, returning how many it allocated
+pub fn with_limit<T>(limit: usize, f: impl FnOnce() -> T) -> (T, usize) {
+    let outer = ALLOCATED.with(|x| x.replace(Some((limit, 0))));
+    let result = f();
+    let (_, used) = ALLOCATED.with(|x| x.replace(outer)).expect("memory usage was reset");
+    return (result, used);
+}
+
+pub fn allocate(bytes: usize) -> Result<(), MemoryLimitExceeded> {
+    return ALLOCATED.with(|x| match x.get() {
+        Some((limit, used)) if bytes > limit - used => Err(MemoryLimitExceeded),
+        Some((limit, used)) => { x.set(Some((limit, used + bytes))); Ok(()) },
+        None => Ok(())
+    });
+}
+
+pub fn array_size(len: usize) -> usize {
+    return len * mem::size_of::<Object>();
+}
+
+pub fn hash_size(len: usize) -> usize {
+    return 2 * len * mem::size_of::<Object>();
+}
+
+pub fn string_size(len: usize) -> usize {
+    return len;
+}
@@ -1,65 +1,157 @@
 pub mod builtins;
 pub mod object;
+pub mod memory;
+pub mod interrupt;
 use crate::parser::ast::*;
-use std::fmt;
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::{Cell, RefCell}, rc::Rc};
 use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use std::{error::Error, fmt};
 use object::Object;
 
 
 
 pub type Environment = std::collections::HashMap<Ident,Rc<RefCell<Object>>>;
 
+// Returned by every engine once a run has used up its execution budget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudgetExhausted;
+
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution budget exhausted")
+    }
+}
+
+impl Error for BudgetExhausted {}
+
+// How many calls may be in progress at once, the same in every engine. The
+// evaluator recurses on the native stack, about 5 KB of it per call in a debug
+// build, so going that deep takes more than the 2 MiB a spawned thread gets:
+// run it through with_stack.
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+// Native stack enough for MAX_CALL_DEPTH calls, each a few expressions deep
+pub const NATIVE_STACK_SIZE: usize = 64 << 20;
+
+// Runs f on a thread of its own with NATIVE_STACK_SIZE of stack, and gives back
+// what it returns. Limits and budgets are per thread, so set them up inside f.
+pub fn with_stack<T: Send, F: FnOnce() -> T + Send>(f: F) -> T {
+    let result = std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new().stack_size(NATIVE_STACK_SIZE);
+        return thread.spawn_scoped(scope, f).expect("could not start a thread").join();
+    });
+    return result.unwrap_or_else(|x| std::panic::resume_unwind(x));
+}
+
+thread_local! {
+    // Expressions the current evaluation may still evaluate, None when it is not metered
+    static FUEL: Cell<Option<u64>> = const { Cell::new(None) };
+    // Whether the metered evaluation has asked for more than it had
+    static EXHAUSTED: Cell<bool> = const { Cell::new(false) };
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // Where the innermost parsed statement the last evaluation failed in starts
+    static FAILED: Cell<Option<Span>> = const { Cell::new(None) };
+}
+
+fn take_step() -> bool {
+    return FUEL.with(|fuel| match fuel.get() {
+        Some(0) => { EXHAUSTED.with(|x| x.set(true)); false },
+        Some(n) => { fuel.set(Some(n - 1)); true },
+        None => true
+    });
+}
+
 pub fn evaluate_statement(statement: &Stmt, env: &mut Environment) -> Object {
-    return match statement {
-        Stmt::LetStmt(ident, expr) => evaluate_let(&ident, expr, env),
-        Stmt::ReturnStmt(expr) => evaluate_return(expr, env),
-        Stmt::ExprStmt(expr) => evaluate_expression(expr, env)
+    let result = match statement {
+        Stmt::LetStmt(ident, expr, _) => evaluate_let(ident, expr, env),
+        Stmt::ReturnStmt(expr, _) => evaluate_return(expr, env),
+        Stmt::ExprStmt(expr, _) => evaluate_expression(expr, env)
     };
+    // The statements the error passes through on its way out are not the ones it is about
+    let start = statement.location().start;
+    if let Object::Error(_) = result {
+        FAILED.with(|x| if x.get().is_none() && start.line > 0 { x.set(Some(start)) });
+    }
+    return result;
+}
 
+// Where the statement the last evaluation on this thread failed in starts, the
+// innermost one when the error came from a function it called
+pub fn error_span() -> Option<Span> {
+    return FAILED.with(|x| x.get());
 }
 
 pub fn evaluate_let(ident: &Ident, expr: &Expr, env: &mut Environment) -> Object {
     let r = evaluate_expression(expr, env);
-    env.insert(ident.clone(), Rc::new(RefCell::new(r)));
+    if is_unwinding(&r) {
+        return r;
+    }
+
+    let cell = Rc::new(RefCell::new(Object::Null));
+    let r = match (expr, r) {
+        // A function bound by let can call itself through its own environment
+        (Expr::Fn(..), Object::Closure(params, block, mut cenv)) => {
+            cenv.insert(ident.clone(), Rc::clone(&cell));
+            Object::Closure(params, block, cenv)
+        },
+        (_, r) => r
+    };
+    *cell.borrow_mut() = r;
+    env.insert(ident.clone(), cell);
     return Object::Null;
 }
 
 
 pub fn evaluate_return(expr: &Expr, env: &mut Environment) -> Object {
-    return Object::Ret(Box::new(evaluate_expression(expr, env)));
+    let r = evaluate_expression(expr, env);
+    if is_unwinding(&r) {
+        return r;
+    }
+    return Object::Ret(Box::new(r));
 }
 
 pub fn evaluate_expression(expression: &Expr, env: &mut Environment) -> Object {
+    if !take_step() {
+        return Object::Error(BudgetExhausted.to_string());
+    }
+    if let Err(x) = interrupt::check() {
+        return Object::Error(x.to_string());
+    }
+
     return match expression {
         Expr::LiteralExpr(literal) => evaluate_literal(literal, env),
         Expr::Prefix(prefix, expr) => evaluate_prefix(prefix, expr, env),
         Expr::Infix(left, infix, right) => evaluate_infix(left, infix, right, env),
         Expr::If(condition, consequence, alternative) => evaluate_if(condition, consequence, alternative, env),
         Expr::IdentExpr(ident) => evaluate_ident(ident, env),
-        Expr::Fn(params, block) => evaluate_closure(&params, &block, env),
+        Expr::Fn(params, block) => evaluate_closure(params, block, env),
         Expr::Call(ident, args) => evaluate_call(ident, args, env),
         Expr::Array(exprs) => evaluate_array(exprs, env),
         Expr::IndexExpr(arr, index) => evaluate_index(arr, index, env),
-        _ => Object::Null
     }
 }
 
 
 fn evaluate_index(arr: &Expr, index: &Expr, env: &mut Environment) -> Object {
+    let left = evaluate_expression(arr, env);
+    if is_unwinding(&left) {
+        return left;
+    }
 
-    match evaluate_expression(&arr, env){
+    let idx = evaluate_expression(index, env);
+    if is_unwinding(&idx) {
+        return idx;
+    }
+
+    match left {
         Object::Array(a) => {
-            let idx = match evaluate_expression(&index, env) {
+            let idx = match idx {
                 Object::Integer(x) => x,
                 _ => return Object::Error("Index is not an integer".to_string())
             };
             evaluate_array_index(a.as_ref(), idx)
         }
         Object::Hash(h) => {
-            let idx = evaluate_expression(index, env);
-
             return match h.get(&idx) {
                 Some(o) => o.clone(),
                 None => Object::Error("Not found in map".to_string())
@@ -69,18 +161,26 @@ fn evaluate_index(arr: &Expr, index: &Expr, env: &mut Environment) -> Object {
     }
  }
 
- fn evaluate_array_index(arr: &Vec<Object>, idx: i64) -> Object {
-     let max = (arr.len() - 1) as i64;
-     if idx < 0 || idx > max {
+ fn evaluate_array_index(arr: &[Object], idx: i64) -> Object {
+     if idx < 0 {
          return Object::Null;
-     } else {
-         return arr[idx as usize].clone();
+     }
+     return match arr.get(idx as usize) {
+         Some(obj) => obj.clone(),
+         None => Object::Null
      }
  }
 
 
 fn evaluate_array(exprs: &Vec<Expr>, env: &mut Environment) -> Object {
-    return Object::Array(Rc::new(evaluate_expressions(exprs, env)));
+    let elements = evaluate_expressions(exprs, env);
+    if elements.len() == 1 && is_unwinding(&elements[0]) {
+        return elements[0].clone();
+    }
+    if let Err(x) = memory::allocate(memory::array_size(elements.len())) {
+        return Object::Error(x.to_string());
+    }
+    return Object::Array(Rc::new(elements));
 }
 
 
@@ -93,24 +193,33 @@ pub fn is_truthy(obj: Object) -> bool {
     }
 }
 
-fn is_error(obj:  &Object) -> bool {
-    match obj {
-        Object::Error(msg) => true,
-        _ => false
-    }
+// Errors and return values skip the rest of every enclosing expression
+fn is_unwinding(obj: &Object) -> bool {
+    matches!(obj, Object::Error(_) | Object::Ret(_))
 }
 pub fn evaluate_call(expr: &Expr, args: &Args, env: &mut Environment) -> Object {
-    let function = evaluate_expression(&expr, env);
+    let function = evaluate_expression(expr, env);
+    if is_unwinding(&function) {
+        return function;
+    }
+
+    let args = evaluate_expressions(args, env);
+    if args.len() == 1 && is_unwinding(&args[0]) {
+        return args[0].clone();
+    }
 
     match function {
         Object::Builtin(name) => {
-            let args = evaluate_expressions(args, env);
             return name(args)
         },
         Object::Closure(params, block, cenv) => {
-            let args = evaluate_expressions(args, env);
-            if args.len() == 1 && is_error(&args[0]) {
-                return args[0].clone();
+            if params.len() != args.len() {
+                return Object::Error(format!("wrong number of arguments: want={}, got={}", params.len(), args.len()));
+            }
+
+            let depth = CALL_DEPTH.with(|x| x.get());
+            if depth >= MAX_CALL_DEPTH {
+                return Object::Error("stack overflow".to_string());
             }
 
             let mut enclosed = cenv.clone();
@@ -120,8 +229,13 @@ pub fn evaluate_call(expr: &Expr, args: &Args, env: &mut Environment) -> Object
             }
 
 
-            return evaluate_block(&block, &mut enclosed);
-            
+            CALL_DEPTH.with(|x| x.set(depth + 1));
+            let result = evaluate_block(&block, &mut enclosed);
+            CALL_DEPTH.with(|x| x.set(depth));
+            return match result {
+                Object::Ret(x) => *x,
+                x => x
+            }
 
         },
         _ => Object::Error(format!("Not callable: {}", function))
@@ -132,9 +246,9 @@ pub fn evaluate_call(expr: &Expr, args: &Args, env: &mut Environment) -> Object
 pub fn evaluate_expressions(expressions: &Vec<Expr>, env: &mut Environment) -> Vec<Object> {
     let mut output = vec![];
     for expression in expressions {
-        let exp = evaluate_expression(&expression, env);
-        if let Object::Error(msg) = exp {
-            return vec![Object::Error(msg)];
+        let exp = evaluate_expression(expression, env);
+        if is_unwinding(&exp) {
+            return vec![exp];
         } else {
             output.push(exp);
         }
@@ -147,10 +261,15 @@ pub fn evaluate_closure(params: &Rc<Params>, block: &Rc<Block>, env: &Environmen
 }
 
 pub fn evaluate_if(condition: &Expr, consequence: &Block, alternative: &Option<Block>, env: &mut Environment) -> Object {
-    if is_truthy(evaluate_expression(&condition, env)) {
-        return evaluate_block(&consequence, env);
+    let condition = evaluate_expression(condition, env);
+    if is_unwinding(&condition) {
+        return condition;
+    }
+
+    if is_truthy(condition) {
+        return evaluate_block(consequence, env);
     } else if let Some(alt) = alternative {
-        return evaluate_block(&alt, env);
+        return evaluate_block(alt, env);
     } else {
         return Object::Null
     }
@@ -158,8 +277,16 @@ pub fn evaluate_if(condition: &Expr, consequence: &Block, alternative: &Option<B
 }
 
 pub fn evaluate_infix(left: &Expr, infix: &Infix, right: &Expr, env: &mut Environment) -> Object {
-    let leftobj = evaluate_expression(&left, env);
-    let rightobj = evaluate_expression(&right, env);
+    let leftobj = evaluate_expression(left, env);
+    if is_unwinding(&leftobj) {
+        return leftobj;
+    }
+
+    let rightobj = evaluate_expression(right, env);
+    if is_unwinding(&rightobj) {
+        return rightobj;
+    }
+
     return match (leftobj, rightobj) {
         (Object::Integer(x), Object::Integer(y)) => evaluate_integer_infix(x, infix, y),
         (Object::Boolean(x), Object::Boolean(y)) => evaluate_boolean_infix(x, infix, y),
@@ -187,19 +314,24 @@ pub fn evaluate_string_infix(left: &String, infix: &Infix, right: &String) -> Ob
     match infix {
         Infix::Equal => Object::Boolean(left == right),
         Infix::NotEqual => Object::Boolean(left != right),
-        Infix::Plus => Object::Str(Rc::new(format!("{}{}", left, right))),
+        Infix::Plus => match memory::allocate(memory::string_size(left.len() + right.len())) {
+            Ok(_) => Object::Str(Rc::new(format!("{}{}", left, right))),
+            Err(x) => Object::Error(x.to_string())
+        },
         Infix::Call => Object::Error("uncallable".to_string()),
         _ => Object::Error("unknown operator".to_string()),
     }
 }
 
 
+// Integers wrap around on overflow
 pub fn evaluate_integer_infix(left: i64, infix: &Infix, right: i64) -> Object {
     match infix {
-        Infix::Plus => Object::Integer(left + right),
-        Infix::Minus => Object::Integer(left - right),
-        Infix::Multiply => Object::Integer(left * right),
-        Infix::Divide => Object::Integer(left / right),
+        Infix::Plus => Object::Integer(left.wrapping_add(right)),
+        Infix::Minus => Object::Integer(left.wrapping_sub(right)),
+        Infix::Multiply => Object::Integer(left.wrapping_mul(right)),
+        Infix::Divide if right == 0 => Object::Error("division by zero".to_string()),
+        Infix::Divide => Object::Integer(left.wrapping_div(right)),
         Infix::Equal => Object::Boolean(left == right),
         Infix::NotEqual => Object::Boolean(left != right),
         Infix::LT => Object::Boolean(left < right),
@@ -208,6 +340,8 @@ pub fn evaluate_integer_infix(left: i64, infix: &Infix, right: i64) -> Object {
     }
 }
 
+// Keys are only ever integers, booleans and strings, which hash by value
+#[allow(clippy::mutable_key_type)]
 pub fn evaluate_literal(literal: &Literal, env: &mut Environment) -> Object {
     return match literal {
         Literal::Bool(x) => Object::Boolean(*x),
@@ -216,21 +350,30 @@ pub fn evaluate_literal(literal: &Literal, env: &mut Environment) -> Object {
         Literal::Hash(x) => {
             let mut m: HashMap<Object,Object> = HashMap::new();
 
+            let mut pairs = vec![];
             for (key,value) in x {
-                let key_obj = match evaluate_expression(&key, env) {
-                    Object::Integer(x) => Object::Integer(x),
-                    Object::Boolean(x) => Object::Boolean(x),
-                    Object::Str(x) => Object::Str(x),
-                    _ => return Object::Error("invalid key object for hash".to_string())
-                };
+                let key_obj = evaluate_expression(key, env);
+                if is_unwinding(&key_obj) {
+                    return key_obj;
+                }
 
-                let val_obj = evaluate_expression(&value, env);
-                if let Object::Error(x) = val_obj {
-                    return Object::Error(x);
+                let val_obj = evaluate_expression(value, env);
+                if is_unwinding(&val_obj) {
+                    return val_obj;
                 }
 
-                m.insert(key_obj,val_obj);
+                pairs.push((key_obj, val_obj));
+            }
+
+            if let Err(x) = memory::allocate(memory::hash_size(pairs.len())) {
+                return Object::Error(x.to_string());
+            }
 
+            for (key_obj, val_obj) in pairs {
+                match key_obj {
+                    Object::Integer(_) | Object::Boolean(_) | Object::Str(_) => m.insert(key_obj, val_obj),
+                    _ => return Object::Error("invalid key object for hash".to_string())
+                };
             }
             return Object::Hash(m);
         }
@@ -240,33 +383,32 @@ pub fn evaluate_literal(literal: &Literal, env: &mut Environment) -> Object {
 
 pub fn evaluate_ident(ident: &Ident, env: &Environment) -> Object {
 
-    match ident.0.as_str() {
-        "len" => Object::Builtin(builtins::len),
-        "first" => Object::Builtin(builtins::first),
-        "last" => Object::Builtin(builtins::last),
-        "rest" => Object::Builtin(builtins::rest),
-        "push" => Object::Builtin(builtins::push),
-        "puts" => Object::Builtin(builtins::puts),
-        _ => match env.get(&ident) {
-            Some(obj) => obj.borrow_mut().clone(),
-            None => Object::Error(format!("unknown identifier: {}", ident))
-        }
+    if let Some(obj) = env.get(ident) {
+        return obj.borrow().clone();
+    }
+
+    match builtins::lookup(&ident.0) {
+        Some(builtin) => Object::Builtin(builtin),
+        None => Object::Error(format!("unknown identifier: {}", ident))
     }
 }
 
 fn evaluate_prefix(prefix: &Prefix, expr: &Expr, env: &mut Environment) -> Object {
 
+    let obj = evaluate_expression(expr, env);
+    if is_unwinding(&obj) {
+        return obj;
+    }
+
     return match prefix {
         Prefix::Minus => {
-            let obj = evaluate_expression(&expr, env);
             return match obj {
-                Object::Integer(x) => Object::Integer(-x),
+                Object::Integer(x) => Object::Integer(x.wrapping_neg()),
                 _ => Object::Error("unknown operator".to_string())
             }
         },
-        Prefix::Plus => evaluate_expression(&expr, env),
+        Prefix::Plus => obj,
         Prefix::Not => {
-            let obj = evaluate_expression(&expr, env);
             return match obj {
                 Object::Boolean(b) => Object::Boolean(!b),
                 Object::Null => Object::Boolean(true),
@@ -293,9 +435,11 @@ pub fn evaluate_block(block: &Block, env: &mut Environment) -> Object {
 }
 
 pub fn evaluate(program: Program, env: &mut Environment) -> Object {
+    FAILED.with(|x| x.set(None));
     let mut result = Object::Null;
-    for stmnt in program {
-        let r = evaluate_statement(&stmnt, env);
+    // By reference, so each statement is where the parser left it
+    for stmnt in &program {
+        let r = evaluate_statement(stmnt, env);
         match r {
             Object::Ret(x) => return *x,
             Object::Error(s) => return Object::Error(s),
@@ -305,4 +449,17 @@ pub fn evaluate(program: Program, env: &mut Environment) -> Object {
     }
 
     return result; 
-}
\ No newline at end of file
+}
+
+// Evaluates with a budget of one unit per expression, leaving what is left of it in fuel.
+// Running out is an Err, whatever the program itself evaluates to.
+pub fn evaluate_with_fuel(program: Program, env: &mut Environment, fuel: &mut u64) -> Result<Object, BudgetExhausted> {
+    let outer = FUEL.with(|x| x.replace(Some(*fuel)));
+    let outer_exhausted = EXHAUSTED.with(|x| x.replace(false));
+    let result = evaluate(program, env);
+    *fuel = FUEL.with(|x| x.replace(outer)).unwrap_or(0);
+    if EXHAUSTED.with(|x| x.replace(outer_exhausted)) {
+        return Err(BudgetExhausted);
+    }
+    return Ok(result);
+}
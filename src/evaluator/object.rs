@@ -5,7 +5,12 @@ use std::hash::{Hash, Hasher};
 use crate::parser::ast::*;
 use crate::evaluator::builtins::Builtin;
 use crate::evaluator::Environment;
+use crate::code::{Instructions, LineTable};
+use crate::regvm::instruction::Instruction;
+use crate::vm::value::Value;
 
+// Builtins compare by address, which is good enough to tell them apart.
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Object {
     Integer(i64),
@@ -17,7 +22,37 @@ pub enum Object {
     Closure(Rc<Params>, Rc<Block>, Environment),
     Builtin(Builtin),
     Array(Rc<Vec<Object>>),
-    Hash(HashMap<Object,Object>)
+    Hash(HashMap<Object,Object>),
+    CompiledFunction(Rc<CompiledFunction>),
+    CompiledClosure(Rc<CompiledClosure>),
+    RegisterClosure(Rc<RegisterClosure>)
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompiledFunction {
+    pub instructions: Instructions,
+    pub num_locals: usize,
+    pub num_params: usize,
+    pub lines: LineTable
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledClosure {
+    pub func: Rc<CompiledFunction>,
+    pub free: Vec<Value>
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RegisterFunction {
+    pub instructions: Vec<Instruction>,
+    pub num_registers: usize,
+    pub num_params: usize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterClosure {
+    pub func: Rc<RegisterFunction>,
+    pub free: Vec<Rc<Object>>
 }
 
 impl Eq for Object {}
@@ -40,12 +75,15 @@ impl fmt::Display for Object {
             Object::Boolean(x) => write!(f, "{}", x),
             Object::Null => write!(f, "null"),
             Object::Error(s) => write!(f, "ERROR {}", s),
-            Object::Closure(params, block, env) => write!(f, "({:?}) => {:?}", params, block),
+            Object::Closure(params, block, _env) => write!(f, "({:?}) => {:?}", params, block),
             Object::Str(x) => write!(f, "{}", x),
             Object::Ret(x) => write!(f, "{}", *x),
             Object::Builtin(x) => write!(f, "{:?}", x),
             Object::Array(x) => write!(f, "[{}]", x.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")),
-            Object::Hash(x) => write!(f, "{:?}", x)
+            Object::Hash(x) => write!(f, "{:?}", x),
+            Object::CompiledFunction(x) => write!(f, "CompiledFunction[{:p}]", Rc::as_ptr(x)),
+            Object::CompiledClosure(x) => write!(f, "Closure[{:p}]", Rc::as_ptr(x)),
+            Object::RegisterClosure(x) => write!(f, "Closure[{:p}]", Rc::as_ptr(x))
         }
     }
 }
\ No newline at end of file
@@ -2,20 +2,34 @@ pub mod token;
 
 use std::{ rc::Rc};
 use token::*;
+
+// The identifiers lookup_ident turns into keyword tokens
+pub const KEYWORDS: [&str; 7] = ["fn", "let", "if", "else", "return", "true", "false"];
+
 pub struct Lexer<'a> {
     input: &'a str,
+    // Byte offsets of ch and of the character after it
     position: usize,
     read_position: usize,
-    ch: char
+    ch: char,
+    line: usize,
+    column: usize,
+    token_start: Span,
+    // The comments skipped so far, trivia that is no token of its own
+    pub comments: Vec<Comment>
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         let mut l = Lexer {
-            input: input,
+            input,
             position: 0,
             read_position: 0,
-            ch: 0 as char
+            ch: 0 as char,
+            line: 1,
+            column: 0,
+            token_start: Span::default(),
+            comments: vec![]
         };
         l.read_char();
         return l;
@@ -24,21 +38,25 @@ impl<'a> Lexer<'a> {
 
 
     fn read_char(&mut self) {
-        self.ch = match self.input.chars().nth(self.read_position) {
-            Some(c) => c,
-            None => 0 as char,
-        };
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        self.ch = self.char_at(self.read_position);
         self.position = self.read_position;
-        self.read_position += 1;
+        self.read_position += self.ch.len_utf8();
 
     }
 
     fn is_letter(ch: char) -> bool {
-        return 'a' <= ch && ch <= 'z' || 'A' <= ch && ch <= 'Z' || ch == '_';
+        return ch.is_ascii_alphabetic() || ch == '_';
     }
 
     fn is_digit(ch: char) -> bool {
-        return '0' <= ch && ch <= '9';
+        return ch.is_ascii_digit();
     }
 
     fn read_identifier(&mut self) -> String {
@@ -64,9 +82,25 @@ impl<'a> Lexer<'a> {
     }
 
     fn skip_whitespace(&mut self) {
-        while self.ch == ' ' || self.ch == '\t' || self.ch == '\n' || self.ch == '\r' {
+        loop {
+            while self.ch == ' ' || self.ch == '\t' || self.ch == '\n' || self.ch == '\r' {
+                self.read_char();
+            }
+            if self.ch != '/' || self.peek() != '/' {
+                return;
+            }
+            self.read_comment();
+        }
+    }
+
+    fn read_comment(&mut self) {
+        let span = Span{ line: self.line, column: self.column };
+        let mut text = String::new();
+        while self.ch != '\n' && self.ch != '\u{0000}' {
+            text.push(self.ch);
             self.read_char();
         }
+        self.comments.push(Comment{ text: text.trim_end().to_string(), span });
     }
 
     fn read_numer(&mut self) -> i64 {
@@ -78,15 +112,15 @@ impl<'a> Lexer<'a> {
     }
 
     fn peek(&self) -> char {
-        if self.read_position >= self.input.len() {
-            return 0 as char;
-        } else {
-            if let Some(ch) = self.input.chars().nth(self.read_position) {
-                return ch;
-            } else {
-                panic!("peeked out of range character")
-            }
-        }
+        return self.char_at(self.read_position);
+    }
+
+    // The character starting at byte offset position, NUL past the end
+    fn char_at(&self, position: usize) -> char {
+        return match self.input.get(position..).and_then(|x| x.chars().next()) {
+            Some(c) => c,
+            None => 0 as char
+        };
     }
 
     fn read_str(&mut self) -> String {
@@ -100,8 +134,14 @@ impl<'a> Lexer<'a> {
 
     }
 
+    // The position of the token last returned by next_token
+    pub fn span(&self) -> Span {
+        return self.token_start;
+    }
+
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
+        self.token_start = Span{ line: self.line, column: self.column };
 
         let tok: Token = match self.ch {
             '=' =>
@@ -46,4 +46,19 @@ pub enum Token {
     Else,
     Return
 
-}
\ No newline at end of file
+}
+
+// Where a token starts in the source, both counted from 1
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize
+}
+
+// A `//` comment, running to the end of its line. The parser never sees
+// comments; the lexer keeps them aside for tools that print source back.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span
+}
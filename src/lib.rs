@@ -3,4 +3,7 @@ pub mod parser;
 pub mod evaluator;
 pub mod code;
 pub mod compiler;
-pub mod vm;
\ No newline at end of file
+pub mod vm;
+pub mod regvm;
+pub mod editor;
+pub mod lint;
@@ -0,0 +1,286 @@
+use crate::evaluator::builtins::BUILTINS;
+use crate::lexer::Lexer;
+use crate::lexer::token::{Comment, Span};
+use crate::parser::Parser;
+use crate::parser::ast::*;
+use std::fmt;
+
+// Finds mistakes in a program without running it. Names are resolved the way
+// the compiler does it: a `let` is seen from the statement after it on, a
+// function is a scope of its own but an `if` block is not, and a function
+// bound by `let` can call itself.
+//
+// Each finding names its rule, so that a comment like
+//
+//     let unused = 1; // lint: allow(unused-binding)
+//
+// on the statement's first line, or on the line above it, silences it there.
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Rule {
+    UnusedBinding,
+    ShadowedBuiltin,
+    UnreachableCode,
+    WrongArity,
+    UndefinedIdentifier
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::UnusedBinding,
+        Rule::ShadowedBuiltin,
+        Rule::UnreachableCode,
+        Rule::WrongArity,
+        Rule::UndefinedIdentifier
+    ];
+
+    pub fn id(self) -> &'static str {
+        return match self {
+            Rule::UnusedBinding => "unused-binding",
+            Rule::ShadowedBuiltin => "shadowed-builtin",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::WrongArity => "wrong-arity",
+            Rule::UndefinedIdentifier => "undefined-identifier"
+        };
+    }
+
+    pub fn from_id(id: &str) -> Option<Rule> {
+        return Rule::ALL.iter().copied().find(|x| x.id() == id);
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.id());
+    }
+}
+
+// A finding, at the start of the statement it was found in
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub span: Span,
+    pub message: String
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}:{}: {} [{}]", self.span.line, self.span.column, self.message, self.rule);
+    }
+}
+
+// Parses source and lints it, leaving out what its comments allow, or gives the parser's errors
+pub fn check(source: &str) -> Result<Vec<Diagnostic>, Vec<(Span, String)>> {
+    let mut l = Lexer::new(source);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            return Err(p.error_spans.iter().cloned().zip(errors).collect());
+        }
+    };
+
+    let lines: Vec<&str> = source.lines().collect();
+    let allowed: Vec<(usize, Rule)> = p.comments().iter().flat_map(|x| allowed_rules(x, &lines)).collect();
+    let mut diagnostics = lint(&program);
+    diagnostics.retain(|x| !allowed.contains(&(x.span.line, x.rule)));
+    return Ok(diagnostics);
+}
+
+// The rules a `// lint: allow(rule, ...)` comment names, with the line they
+// are allowed on: its own, or the next one for a comment on a line by itself
+fn allowed_rules(comment: &Comment, lines: &[&str]) -> Vec<(usize, Rule)> {
+    let alone = lines[comment.span.line - 1].chars().take(comment.span.column - 1).all(char::is_whitespace);
+    let line = if alone { comment.span.line + 1 } else { comment.span.line };
+    let rules = comment.text.trim_start_matches('/').trim()
+        .strip_prefix("lint:")
+        .and_then(|x| x.trim().strip_prefix("allow("))
+        .and_then(|x| x.trim_end().strip_suffix(')'));
+    return match rules {
+        Some(rules) => rules.split(',').filter_map(|x| Rule::from_id(x.trim())).map(|x| (line, x)).collect(),
+        None => vec![]
+    };
+}
+
+// Lints a program. Findings in statements that were not parsed are at 0:0.
+pub fn lint(program: &Program) -> Vec<Diagnostic> {
+    let mut linter = Linter{ span: Span::default(), scopes: vec![vec![]], function: None, diagnostics: vec![] };
+    linter.visit_block(program);
+    linter.leave_scope();
+    linter.diagnostics.sort_by_key(|x| (x.span.line, x.span.column));
+    return linter.diagnostics;
+}
+
+
+// A name some statement brought in
+struct Binding<'a> {
+    name: &'a str,
+    kind: Kind,
+    span: Span,
+    used: bool,
+    // How many arguments it takes, when it is known to be a function
+    arity: Option<usize>
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Let,
+    Param,
+    // A function's own name, inside its body
+    Function
+}
+
+// What a name refers to
+enum Resolved {
+    Binding(Option<usize>),
+    Builtin(Option<usize>),
+    Undefined
+}
+
+struct Linter<'a> {
+    // Where the statement being linted starts
+    span: Span,
+    // One for the program, and one more for each function it is in
+    scopes: Vec<Vec<Binding<'a>>>,
+    // The name the function about to be visited is bound to
+    function: Option<&'a Ident>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, rule: Rule, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic{ rule, span, message });
+    }
+
+    fn define(&mut self, name: &'a Ident, kind: Kind, arity: Option<usize>) {
+        if kind != Kind::Function && builtin_arity(&name.0).is_some() {
+            self.report(Rule::ShadowedBuiltin, self.span, format!("{} shadows the builtin of the same name", name.0));
+        }
+        let binding = Binding{ name: &name.0, kind, span: self.span, used: false, arity };
+        self.scopes.last_mut().unwrap().push(binding);
+    }
+
+    // The newest binding of name, from the innermost scope out
+    fn resolve(&mut self, name: &Ident) -> Resolved {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope.iter_mut().rev().find(|x| x.name == name.0) {
+                binding.used = true;
+                return Resolved::Binding(binding.arity);
+            }
+        }
+        return match builtin_arity(&name.0) {
+            Some(arity) => Resolved::Builtin(arity),
+            None => Resolved::Undefined
+        };
+    }
+
+    // Lets that nothing read are reported as their scope ends. Names starting
+    // with `_` are taken to be unused on purpose.
+    fn leave_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        for binding in scope {
+            if binding.kind == Kind::Let && !binding.used && !binding.name.starts_with('_') {
+                self.report(Rule::UnusedBinding, binding.span, format!("{} is never used", binding.name));
+            }
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for Linter<'a> {
+    fn visit_block(&mut self, block: &'a Block) {
+        let outer = self.span;
+        let mut returned = false;
+        for stmt in block {
+            self.span = stmt.location().start;
+            // Only the first statement that cannot run is reported
+            if returned {
+                self.report(Rule::UnreachableCode, self.span, "unreachable statement after return".to_string());
+                returned = false;
+            }
+            self.visit_stmt(stmt);
+            if let Stmt::ReturnStmt(..) = stmt {
+                returned = true;
+            }
+        }
+        self.span = outer;
+    }
+
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::LetStmt(name, Expr::Fn(params, body), _) => {
+                self.function = Some(name);
+                self.visit_function(params, body);
+                self.define(name, Kind::Let, Some(params.len()));
+            },
+            Stmt::LetStmt(name, x, _) => {
+                // Another name for a function takes as many arguments
+                let arity = match x {
+                    Expr::IdentExpr(other) => match self.resolve(other) {
+                        Resolved::Binding(arity) => arity,
+                        _ => None
+                    },
+                    _ => None
+                };
+                self.visit_expr(x);
+                self.define(name, Kind::Let, arity);
+            },
+            _ => visit::walk_stmt(self, stmt)
+        }
+    }
+
+    fn visit_function(&mut self, params: &'a Params, body: &'a Block) {
+        self.scopes.push(vec![]);
+        if let Some(name) = self.function.take() {
+            self.define(name, Kind::Function, Some(params.len()));
+        }
+        visit::walk_function(self, params, body);
+        self.leave_scope();
+    }
+
+    fn visit_binding(&mut self, ident: &'a Ident) {
+        self.define(ident, Kind::Param, None);
+    }
+
+    fn visit_ident(&mut self, ident: &'a Ident) {
+        if let Resolved::Undefined = self.resolve(ident) {
+            self.report(Rule::UndefinedIdentifier, self.span, format!("{} is not defined", ident.0));
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        if let Expr::Call(function, args) = expr {
+            let known = match &**function {
+                Expr::IdentExpr(name) => match self.resolve(name) {
+                    Resolved::Binding(arity) | Resolved::Builtin(arity) => arity.map(|x| (name.0.as_str(), x)),
+                    Resolved::Undefined => None
+                },
+                Expr::Fn(params, _) => Some(("the function", params.len())),
+                _ => None
+            };
+            if let Some((name, arity)) = known {
+                if arity != args.len() {
+                    let message = format!("{} takes {} {}, but is given {}", name, arity, plural(arity), args.len());
+                    self.report(Rule::WrongArity, self.span, message);
+                }
+            }
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+// How many arguments the builtin name takes: Some(None) for any number, None if there is no such builtin
+fn builtin_arity(name: &str) -> Option<Option<usize>> {
+    if !BUILTINS.iter().any(|(x, _)| *x == name) {
+        return None;
+    }
+    return match name {
+        "push" => Some(Some(2)),
+        "puts" => Some(None),
+        _ => Some(Some(1))
+    };
+}
+
+fn plural(n: usize) -> &'static str {
+    return if n == 1 { "argument" } else { "arguments" };
+}
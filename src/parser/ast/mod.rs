@@ -1,6 +1,9 @@
-use crate::lexer::token::{Token};
+pub mod visit;
+
+pub use crate::lexer::token::Span;
+pub use self::visit::{Folder, Visitor};
 use std::fmt;
-use std::{ rc::Rc, collections::HashMap};
+use std::rc::Rc;
 
 
 #[derive(PartialEq, Debug, Eq, Clone, Hash)]
@@ -59,11 +62,36 @@ pub enum Expr {
 }
 
 
+// Where a statement was in its source: where it starts, and where its last
+// token starts. Statements built rather than parsed are at line 0. It is left
+// out when statements are compared, so the same code is equal wherever it was.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Location {
+    pub start: Span,
+    pub end: Span
+}
+
+impl PartialEq for Location {
+    fn eq(&self, _other: &Location) -> bool {
+        return true;
+    }
+}
+
+impl Eq for Location {}
+
 #[derive(PartialEq, Debug, Eq, Clone)]
 pub enum Stmt {
-    LetStmt(Ident, Expr),
-    ReturnStmt(Expr),
-    ExprStmt(Expr),
+    LetStmt(Ident, Expr, Location),
+    ReturnStmt(Expr, Location),
+    ExprStmt(Expr, Location),
+}
+
+impl Stmt {
+    pub fn location(&self) -> Location {
+        return match self {
+            Stmt::LetStmt(_, _, x) | Stmt::ReturnStmt(_, x) | Stmt::ExprStmt(_, x) => *x
+        };
+    }
 }
 
 pub type Args = Vec<Expr>;
@@ -0,0 +1,195 @@
+use super::*;
+
+// One traversal of the syntax tree for every analysis to share. A visitor
+// overrides the methods for the nodes it cares about and calls the matching
+// `walk_` function from them to carry on into the children; the defaults do
+// nothing but that. A `Folder` does the same for passes that rebuild the tree,
+// taking each node and giving back the one to put in its place.
+//
+// Children are walked in the order their source comes in, except that a `let`
+// walks its value before the name it binds: the name cannot be seen from there.
+
+pub trait Visitor<'a> {
+    // A program is a block too
+    fn visit_block(&mut self, block: &'a Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_literal(&mut self, literal: &'a Literal) {
+        walk_literal(self, literal);
+    }
+
+    fn visit_function(&mut self, params: &'a Params, body: &'a Block) {
+        walk_function(self, params, body);
+    }
+
+    // A name being read
+    fn visit_ident(&mut self, _ident: &'a Ident) {}
+
+    // A name being bound, by a `let` or as a parameter
+    fn visit_binding(&mut self, _ident: &'a Ident) {}
+}
+
+pub fn walk_block<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, block: &'a Block) {
+    for stmt in block {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, stmt: &'a Stmt) {
+    match stmt {
+        Stmt::LetStmt(name, value, _) => {
+            visitor.visit_expr(value);
+            visitor.visit_binding(name);
+        },
+        Stmt::ReturnStmt(x, _) | Stmt::ExprStmt(x, _) => visitor.visit_expr(x)
+    }
+}
+
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &'a Expr) {
+    match expr {
+        Expr::IdentExpr(name) => visitor.visit_ident(name),
+        Expr::LiteralExpr(literal) => visitor.visit_literal(literal),
+        Expr::Prefix(_, right) => visitor.visit_expr(right),
+        Expr::Infix(left, _, right) | Expr::IndexExpr(left, right) => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        },
+        Expr::If(condition, consequence, alternative) => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(consequence);
+            if let Some(alternative) = alternative {
+                visitor.visit_block(alternative);
+            }
+        },
+        Expr::Fn(params, body) => visitor.visit_function(params, body),
+        Expr::Call(function, args) => {
+            visitor.visit_expr(function);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        },
+        Expr::Array(items) => {
+            for item in items {
+                visitor.visit_expr(item);
+            }
+        }
+    }
+}
+
+pub fn walk_literal<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, literal: &'a Literal) {
+    if let Literal::Hash(pairs) = literal {
+        for (key, value) in pairs {
+            visitor.visit_expr(key);
+            visitor.visit_expr(value);
+        }
+    }
+}
+
+pub fn walk_function<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, params: &'a Params, body: &'a Block) {
+    for param in params {
+        visitor.visit_binding(param);
+    }
+    visitor.visit_block(body);
+}
+
+
+pub trait Folder {
+    fn fold_block(&mut self, block: Block) -> Block {
+        return rebuild_block(self, block);
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+        return rebuild_stmt(self, stmt);
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        return rebuild_expr(self, expr);
+    }
+
+    fn fold_literal(&mut self, literal: Literal) -> Literal {
+        return rebuild_literal(self, literal);
+    }
+
+    // Gives back the function expression to put in place of this one
+    fn fold_function(&mut self, params: Rc<Params>, body: Rc<Block>) -> Expr {
+        return rebuild_function(self, params, body);
+    }
+
+    fn fold_ident(&mut self, ident: Ident) -> Ident {
+        return ident;
+    }
+
+    fn fold_binding(&mut self, ident: Ident) -> Ident {
+        return ident;
+    }
+}
+
+pub fn rebuild_block<F: Folder + ?Sized>(folder: &mut F, block: Block) -> Block {
+    return block.into_iter().map(|x| folder.fold_stmt(x)).collect();
+}
+
+pub fn rebuild_stmt<F: Folder + ?Sized>(folder: &mut F, stmt: Stmt) -> Stmt {
+    return match stmt {
+        Stmt::LetStmt(name, value, location) => {
+            let value = folder.fold_expr(value);
+            Stmt::LetStmt(folder.fold_binding(name), value, location)
+        },
+        Stmt::ReturnStmt(x, location) => Stmt::ReturnStmt(folder.fold_expr(x), location),
+        Stmt::ExprStmt(x, location) => Stmt::ExprStmt(folder.fold_expr(x), location)
+    };
+}
+
+pub fn rebuild_expr<F: Folder + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    return match expr {
+        Expr::IdentExpr(name) => Expr::IdentExpr(folder.fold_ident(name)),
+        Expr::LiteralExpr(literal) => Expr::LiteralExpr(folder.fold_literal(literal)),
+        Expr::Prefix(op, right) => Expr::Prefix(op, Box::new(folder.fold_expr(*right))),
+        Expr::Infix(left, op, right) => {
+            let left = folder.fold_expr(*left);
+            Expr::Infix(Box::new(left), op, Box::new(folder.fold_expr(*right)))
+        },
+        Expr::IndexExpr(left, index) => {
+            let left = folder.fold_expr(*left);
+            Expr::IndexExpr(Box::new(left), Box::new(folder.fold_expr(*index)))
+        },
+        Expr::If(condition, consequence, alternative) => {
+            let condition = folder.fold_expr(*condition);
+            let consequence = folder.fold_block(consequence);
+            Expr::If(Box::new(condition), consequence, alternative.map(|x| folder.fold_block(x)))
+        },
+        Expr::Fn(params, body) => folder.fold_function(params, body),
+        Expr::Call(function, args) => {
+            let function = folder.fold_expr(*function);
+            Expr::Call(Box::new(function), args.into_iter().map(|x| folder.fold_expr(x)).collect())
+        },
+        Expr::Array(items) => Expr::Array(items.into_iter().map(|x| folder.fold_expr(x)).collect())
+    };
+}
+
+pub fn rebuild_literal<F: Folder + ?Sized>(folder: &mut F, literal: Literal) -> Literal {
+    return match literal {
+        Literal::Hash(pairs) => {
+            Literal::Hash(pairs.into_iter().map(|(key, value)| {
+                let key = folder.fold_expr(key);
+                (key, folder.fold_expr(value))
+            }).collect())
+        },
+        x => x
+    };
+}
+
+// The parameters and body are taken out of their Rc, or copied when they are shared
+pub fn rebuild_function<F: Folder + ?Sized>(folder: &mut F, params: Rc<Params>, body: Rc<Block>) -> Expr {
+    let params = Rc::unwrap_or_clone(params).into_iter().map(|x| folder.fold_binding(x)).collect();
+    let body = folder.fold_block(Rc::unwrap_or_clone(body));
+    return Expr::Fn(Rc::new(params), Rc::new(body));
+}
@@ -0,0 +1,658 @@
+use super::ast::*;
+use super::Parser;
+use crate::lexer::Lexer;
+use crate::lexer::token::Span;
+use std::error::Error;
+use std::rc::Rc;
+
+// Syntax trees as JSON, for tools outside the crate. A program is written as
+//
+//   {"version": 1, "body": [statement, ...]}
+//
+// and every node as an object whose "type" says what it is. Fields are always
+// written in the order given here; readers must not rely on that, and must
+// ignore fields they do not know, which later versions may add. Anything else
+// changing bumps the version.
+//
+// Statements carry where they were in the source, or null when that is not
+// known, as for trees built rather than parsed. Lines and columns both count
+// from 1, and columns count characters.
+//
+//   {"type": "Let", "span": span, "name": "x", "value": expression}
+//   {"type": "Return", "span": span, "value": expression}
+//   {"type": "Expression", "span": span, "expression": expression}
+//
+//   span  {"start": {"line": 1, "column": 1}, "end": {"line": 1, "column": 9}}
+//         where end is the start of the statement's last token
+//
+// Expressions:
+//
+//   {"type": "Identifier", "name": "x"}
+//   {"type": "Integer", "value": 1}
+//   {"type": "Boolean", "value": true}
+//   {"type": "String", "value": "monkey"}
+//   {"type": "Hash", "pairs": [{"key": expression, "value": expression}, ...]}
+//   {"type": "Prefix", "operator": "-", "right": expression}
+//   {"type": "Infix", "operator": "+", "left": expression, "right": expression}
+//   {"type": "If", "condition": expression, "consequence": [statement, ...], "alternative": [statement, ...] or null}
+//   {"type": "Function", "parameters": ["a", ...], "body": [statement, ...]}
+//   {"type": "Call", "function": expression, "arguments": [expression, ...]}
+//   {"type": "Array", "elements": [expression, ...]}
+//   {"type": "Index", "left": expression, "index": expression}
+//
+// Prefix operators are "+", "-" and "!", infix ones "+", "-", "*", "/", "<",
+// ">", "==" and "!=". Integers are 64 bits, more than a double holds exactly,
+// and are written out in full.
+pub const VERSION: u32 = 1;
+
+// JSON nested deeper than this is refused rather than read
+const MAX_DEPTH: usize = 512;
+
+// Parses source and writes its tree with spans, or gives the parser's errors
+pub fn dump(source: &str) -> Result<String, Vec<(Span, String)>> {
+    let mut l = Lexer::new(source);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            return Err(p.error_spans.iter().cloned().zip(errors).collect());
+        }
+    };
+    return Ok(to_json(&program));
+}
+
+// Writes program. Statements at line 0, which were not parsed, get a null span.
+pub fn to_json(program: &Program) -> String {
+    let mut writer = Writer{ out: String::new() };
+    writer.out.push_str(&format!("{{\"version\":{},\"body\":", VERSION));
+    writer.block(program);
+    writer.out.push('}');
+    return writer.out;
+}
+
+// Reads a program back. Statements with a null span are put at line 0.
+pub fn from_json(json: &str) -> Result<Program, Box<dyn Error>> {
+    let mut reader = Reader{ text: json, pos: 0, depth: 0 };
+    let value = reader.value()?;
+    reader.skip_whitespace();
+    if reader.pos < json.len() {
+        return Err(reader.error("expected the end of the input"));
+    }
+
+    let object = as_object(&value, "program")?;
+    match field(object, "version", "program")? {
+        Value::Number(x) if x == &VERSION.to_string() => {},
+        _ => return Err(format!("unsupported version, expected {}", VERSION).into())
+    }
+    return Builder.block(field(object, "body", "program")?);
+}
+
+
+struct Writer {
+    out: String
+}
+
+impl Writer {
+    fn block(&mut self, block: &Block) {
+        self.out.push('[');
+        for (i, stmt) in block.iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.statement(stmt);
+        }
+        self.out.push(']');
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let kind = match stmt {
+            Stmt::LetStmt(..) => "Let",
+            Stmt::ReturnStmt(..) => "Return",
+            Stmt::ExprStmt(..) => "Expression"
+        };
+        self.out.push_str(&format!("{{\"type\":\"{}\",\"span\":", kind));
+        let Location{ start, end } = stmt.location();
+        if start.line == 0 {
+            self.out.push_str("null");
+        } else {
+            self.out.push_str(&format!(
+                "{{\"start\":{{\"line\":{},\"column\":{}}},\"end\":{{\"line\":{},\"column\":{}}}}}",
+                start.line, start.column, end.line, end.column
+            ));
+        }
+
+        match stmt {
+            Stmt::LetStmt(name, value, _) => {
+                self.key("name");
+                self.string(&name.0);
+                self.key("value");
+                self.expression(value);
+            },
+            Stmt::ReturnStmt(value, _) => {
+                self.key("value");
+                self.expression(value);
+            },
+            Stmt::ExprStmt(expression, _) => {
+                self.key("expression");
+                self.expression(expression);
+            }
+        }
+        self.out.push('}');
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::IdentExpr(name) => {
+                self.kind("Identifier");
+                self.key("name");
+                self.string(&name.0);
+            },
+            Expr::LiteralExpr(Literal::Int(x)) => {
+                self.kind("Integer");
+                self.key("value");
+                self.out.push_str(&x.to_string());
+            },
+            Expr::LiteralExpr(Literal::Bool(x)) => {
+                self.kind("Boolean");
+                self.key("value");
+                self.out.push_str(&x.to_string());
+            },
+            Expr::LiteralExpr(Literal::Str(x)) => {
+                self.kind("String");
+                self.key("value");
+                self.string(x);
+            },
+            Expr::LiteralExpr(Literal::Hash(pairs)) => {
+                self.kind("Hash");
+                self.key("pairs");
+                self.list(pairs, |w, (key, value)| {
+                    w.out.push_str("{\"key\":");
+                    w.expression(key);
+                    w.key("value");
+                    w.expression(value);
+                    w.out.push('}');
+                });
+            },
+            Expr::Prefix(op, right) => {
+                self.kind("Prefix");
+                self.key("operator");
+                self.string(&op.to_string());
+                self.key("right");
+                self.expression(right);
+            },
+            Expr::Infix(left, op, right) => {
+                self.kind("Infix");
+                self.key("operator");
+                self.string(&op.to_string());
+                self.key("left");
+                self.expression(left);
+                self.key("right");
+                self.expression(right);
+            },
+            Expr::If(condition, consequence, alternative) => {
+                self.kind("If");
+                self.key("condition");
+                self.expression(condition);
+                self.key("consequence");
+                self.block(consequence);
+                self.key("alternative");
+                match alternative {
+                    Some(alternative) => self.block(alternative),
+                    None => self.out.push_str("null")
+                }
+            },
+            Expr::Fn(params, body) => {
+                self.kind("Function");
+                self.key("parameters");
+                self.list(params, |w, x| w.string(&x.0));
+                self.key("body");
+                self.block(body);
+            },
+            Expr::Call(function, args) => {
+                self.kind("Call");
+                self.key("function");
+                self.expression(function);
+                self.key("arguments");
+                self.list(args, |w, x| w.expression(x));
+            },
+            Expr::Array(items) => {
+                self.kind("Array");
+                self.key("elements");
+                self.list(items, |w, x| w.expression(x));
+            },
+            Expr::IndexExpr(left, index) => {
+                self.kind("Index");
+                self.key("left");
+                self.expression(left);
+                self.key("index");
+                self.expression(index);
+            }
+        }
+        self.out.push('}');
+    }
+
+    // Opens the object for a node
+    fn kind(&mut self, kind: &str) {
+        self.out.push_str(&format!("{{\"type\":\"{}\"", kind));
+    }
+
+    fn key(&mut self, key: &str) {
+        self.out.push_str(&format!(",\"{}\":", key));
+    }
+
+    fn list<T>(&mut self, items: &[T], item: impl Fn(&mut Self, &T)) {
+        self.out.push('[');
+        for (i, x) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            item(self, x);
+        }
+        self.out.push(']');
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if (c as u32) < 0x20 => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.out.push(c)
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+
+// JSON as read, before it is known to be a tree. Numbers are kept as written
+// so that integers of any size can be read exactly.
+#[derive(Debug)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    Str(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, msg: &str) -> Box<dyn Error> {
+        let before = &self.text[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|x| *x != '\n').count() + 1;
+        return format!("{} at {}:{}", msg, line, column).into();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        return self.text.as_bytes().get(self.pos).copied();
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), Box<dyn Error>> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c as char)));
+        }
+        self.pos += 1;
+        return Ok(());
+    }
+
+    fn value(&mut self) -> Result<Value, Box<dyn Error>> {
+        self.skip_whitespace();
+        return match self.peek() {
+            Some(b'{') => self.nested(|r| r.object()),
+            Some(b'[') => self.nested(|r| r.array()),
+            Some(b'"') => Ok(Value::Str(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) if self.text[self.pos..].starts_with("null") => self.word("null", Value::Null),
+            Some(_) if self.text[self.pos..].starts_with("true") => self.word("true", Value::Bool(true)),
+            Some(_) if self.text[self.pos..].starts_with("false") => self.word("false", Value::Bool(false)),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input"))
+        };
+    }
+
+    fn nested(&mut self, read: impl Fn(&mut Self) -> Result<Value, Box<dyn Error>>) -> Result<Value, Box<dyn Error>> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.depth += 1;
+        let value = read(self);
+        self.depth -= 1;
+        return value;
+    }
+
+    fn word(&mut self, word: &str, value: Value) -> Result<Value, Box<dyn Error>> {
+        self.pos += word.len();
+        return Ok(value);
+    }
+
+    fn object(&mut self) -> Result<Value, Box<dyn Error>> {
+        self.expect(b'{')?;
+        let mut fields = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a field name"));
+            }
+            let name = self.string()?;
+            self.expect(b':')?;
+            fields.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                },
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, Box<dyn Error>> {
+        self.expect(b'[')?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                },
+                _ => return Err(self.error("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Box<dyn Error>> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        let digits = self.digits();
+        if digits == 0 {
+            return Err(self.error("expected a digit"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if self.digits() == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        return Ok(Value::Number(self.text[start..self.pos].to_string()));
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        return self.pos - start;
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let end = match rest.find(['"', '\\']) {
+                Some(end) => end,
+                None => {
+                    self.pos = self.text.len();
+                    return Err(self.error("unterminated string"));
+                }
+            };
+            if let Some(offset) = rest[..end].find(|x: char| (x as u32) < 0x20) {
+                self.pos += offset;
+                return Err(self.error("control character in string"));
+            }
+            s.push_str(&rest[..end]);
+            self.pos += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(s);
+            }
+
+            let start = self.pos - 1;
+            let escape = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match escape {
+                b'"' => s.push('"'),
+                b'\\' => s.push('\\'),
+                b'/' => s.push('/'),
+                b'b' => s.push('\u{8}'),
+                b'f' => s.push('\u{c}'),
+                b'n' => s.push('\n'),
+                b'r' => s.push('\r'),
+                b't' => s.push('\t'),
+                b'u' => {
+                    let unit = self.hex()?;
+                    // Characters past the first plane come as a surrogate pair
+                    let code = if (0xd800..0xdc00).contains(&unit) && self.text[self.pos..].starts_with("\\u") {
+                        self.pos += 2;
+                        let low = self.hex()?;
+                        if !(0xdc00..0xe000).contains(&low) {
+                            self.pos = start;
+                            return Err(self.error("invalid surrogate pair"));
+                        }
+                        0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)
+                    } else {
+                        unit
+                    };
+                    match char::from_u32(code) {
+                        Some(c) => s.push(c),
+                        None => {
+                            self.pos = start;
+                            return Err(self.error("invalid character escape"));
+                        }
+                    }
+                },
+                _ => {
+                    self.pos = start;
+                    return Err(self.error("invalid escape"));
+                }
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, Box<dyn Error>> {
+        let digits = self.text.get(self.pos..self.pos + 4).filter(|x| x.bytes().all(|b| b.is_ascii_hexdigit()));
+        let digits = digits.ok_or_else(|| self.error("expected four hex digits"))?;
+        self.pos += 4;
+        return Ok(u32::from_str_radix(digits, 16)?);
+    }
+}
+
+
+// Turns JSON values into the tree
+struct Builder;
+
+impl Builder {
+    fn block(&mut self, value: &Value) -> Result<Block, Box<dyn Error>> {
+        return as_array(value, "block")?.iter().map(|x| self.statement(x)).collect();
+    }
+
+    fn statement(&mut self, value: &Value) -> Result<Stmt, Box<dyn Error>> {
+        let object = as_object(value, "statement")?;
+        let kind = as_str(field(object, "type", "statement")?, "statement type")?;
+
+        let location = match optional(object, "span") {
+            None => Location::default(),
+            Some(span) => {
+                let span = as_object(span, "span")?;
+                Location{ start: position(field(span, "start", "span")?)?, end: position(field(span, "end", "span")?)? }
+            }
+        };
+
+        return match kind {
+            "Let" => {
+                let name = as_str(field(object, "name", kind)?, "name")?;
+                Ok(Stmt::LetStmt(Ident(name.to_string()), self.expression(field(object, "value", kind)?)?, location))
+            },
+            "Return" => Ok(Stmt::ReturnStmt(self.expression(field(object, "value", kind)?)?, location)),
+            "Expression" => Ok(Stmt::ExprStmt(self.expression(field(object, "expression", kind)?)?, location)),
+            x => Err(format!("unknown statement type {}", x).into())
+        };
+    }
+
+    fn expression(&mut self, value: &Value) -> Result<Expr, Box<dyn Error>> {
+        let object = as_object(value, "expression")?;
+        let kind = as_str(field(object, "type", "expression")?, "expression type")?;
+        let get = |name: &str| field(object, name, kind);
+
+        return Ok(match kind {
+            "Identifier" => Expr::IdentExpr(Ident(as_str(get("name")?, "name")?.to_string())),
+            "Integer" => match get("value")? {
+                Value::Number(x) => Expr::LiteralExpr(Literal::Int(x.parse().map_err(|_| format!("{} is not a 64-bit integer", x))?)),
+                _ => return Err("expected an integer value".into())
+            },
+            "Boolean" => match get("value")? {
+                Value::Bool(x) => Expr::LiteralExpr(Literal::Bool(*x)),
+                _ => return Err("expected a boolean value".into())
+            },
+            "String" => Expr::LiteralExpr(Literal::Str(Rc::new(as_str(get("value")?, "value")?.to_string()))),
+            "Hash" => {
+                let mut pairs = vec![];
+                for pair in as_array(get("pairs")?, "pairs")? {
+                    let pair = as_object(pair, "pair")?;
+                    let key = self.expression(field(pair, "key", "pair")?)?;
+                    pairs.push((key, self.expression(field(pair, "value", "pair")?)?));
+                }
+                Expr::LiteralExpr(Literal::Hash(pairs))
+            },
+            "Prefix" => {
+                let op = match as_str(get("operator")?, "operator")? {
+                    "+" => Prefix::Plus,
+                    "-" => Prefix::Minus,
+                    "!" => Prefix::Not,
+                    x => return Err(format!("unknown prefix operator {}", x).into())
+                };
+                Expr::Prefix(op, Box::new(self.expression(get("right")?)?))
+            },
+            "Infix" => {
+                let op = match as_str(get("operator")?, "operator")? {
+                    "+" => Infix::Plus,
+                    "-" => Infix::Minus,
+                    "/" => Infix::Divide,
+                    "*" => Infix::Multiply,
+                    ">" => Infix::GT,
+                    "<" => Infix::LT,
+                    "==" => Infix::Equal,
+                    "!=" => Infix::NotEqual,
+                    "(" => Infix::Call,
+                    "[" => Infix::Index,
+                    x => return Err(format!("unknown infix operator {}", x).into())
+                };
+                let left = self.expression(get("left")?)?;
+                Expr::Infix(Box::new(left), op, Box::new(self.expression(get("right")?)?))
+            },
+            "If" => {
+                let condition = self.expression(get("condition")?)?;
+                let consequence = self.block(get("consequence")?)?;
+                let alternative = match optional(object, "alternative") {
+                    Some(x) => Some(self.block(x)?),
+                    None => None
+                };
+                Expr::If(Box::new(condition), consequence, alternative)
+            },
+            "Function" => {
+                let params = as_array(get("parameters")?, "parameters")?.iter()
+                    .map(|x| Ok(Ident(as_str(x, "parameter")?.to_string())))
+                    .collect::<Result<Params, Box<dyn Error>>>()?;
+                Expr::Fn(Rc::new(params), Rc::new(self.block(get("body")?)?))
+            },
+            "Call" => {
+                let function = self.expression(get("function")?)?;
+                Expr::Call(Box::new(function), self.expressions(get("arguments")?)?)
+            },
+            "Array" => Expr::Array(self.expressions(get("elements")?)?),
+            "Index" => {
+                let left = self.expression(get("left")?)?;
+                Expr::IndexExpr(Box::new(left), Box::new(self.expression(get("index")?)?))
+            },
+            x => return Err(format!("unknown expression type {}", x).into())
+        });
+    }
+
+    fn expressions(&mut self, value: &Value) -> Result<Vec<Expr>, Box<dyn Error>> {
+        return as_array(value, "list")?.iter().map(|x| self.expression(x)).collect();
+    }
+}
+
+fn field<'v>(object: &'v [(String, Value)], name: &str, of: &str) -> Result<&'v Value, Box<dyn Error>> {
+    return object.iter().find(|(key, _)| key == name).map(|(_, x)| x).ok_or_else(|| format!("{} is missing {}", of, name).into());
+}
+
+// A field that may be left out or null
+fn optional<'v>(object: &'v [(String, Value)], name: &str) -> Option<&'v Value> {
+    return object.iter().find(|(key, _)| key == name).map(|(_, x)| x).filter(|x| !matches!(x, Value::Null));
+}
+
+fn as_object<'v>(value: &'v Value, what: &str) -> Result<&'v [(String, Value)], Box<dyn Error>> {
+    match value {
+        Value::Object(x) => return Ok(x),
+        _ => return Err(format!("expected {} to be an object", what).into())
+    }
+}
+
+fn as_array<'v>(value: &'v Value, what: &str) -> Result<&'v [Value], Box<dyn Error>> {
+    match value {
+        Value::Array(x) => return Ok(x),
+        _ => return Err(format!("expected {} to be an array", what).into())
+    }
+}
+
+fn as_str<'v>(value: &'v Value, what: &str) -> Result<&'v str, Box<dyn Error>> {
+    match value {
+        Value::Str(x) => return Ok(x),
+        _ => return Err(format!("expected {} to be a string", what).into())
+    }
+}
+
+fn position(value: &Value) -> Result<Span, Box<dyn Error>> {
+    let object = as_object(value, "position")?;
+    let number = |name: &str| match field(object, name, "position")? {
+        Value::Number(x) => x.parse::<usize>().map_err(|_| format!("invalid {} {}", name, x).into()),
+        _ => Err::<usize, Box<dyn Error>>(format!("expected {} to be a number", name).into())
+    };
+    return Ok(Span{ line: number("line")?, column: number("column")? });
+}
@@ -1,6 +1,8 @@
 pub mod ast;
+pub mod printer;
+pub mod json;
 use crate::lexer::{Lexer};
-use crate::lexer::token::{Token};
+use crate::lexer::token::{Token, Span, Comment};
 use ast::*;
 use std::cmp::Ordering;
 use std::error::Error;
@@ -11,7 +13,15 @@ pub struct Parser<'a> {
     l: &'a mut Lexer<'a>,
     curr: Token,
     peek: Token,
-    pub errors: Vec<Box<Error>>
+    curr_span: Span,
+    peek_span: Span,
+    pub errors: Vec<Box<dyn Error>>,
+    // Where the token each error is about starts
+    pub error_spans: Vec<Span>,
+    // The token the error being returned is about, if it is not the current one
+    error_span: Option<Span>,
+    // Where each block's closing brace is, in the order the blocks close
+    pub block_ends: Vec<Span>
 }
 #[derive(PartialEq, Debug, Eq, Clone)]
 enum Precedence {
@@ -37,7 +47,17 @@ impl PartialOrd for Precedence {
 impl<'a> Parser<'a> {
 
     pub fn new(l: &'a mut Lexer<'a>) -> Self {
-        let mut p = Parser{l: l, curr: Token::EOF, peek: Token::EOF, errors: vec![]};
+        let mut p = Parser{
+            l,
+            curr: Token::EOF,
+            peek: Token::EOF,
+            curr_span: Span::default(),
+            peek_span: Span::default(),
+            errors: vec![],
+            error_spans: vec![],
+            error_span: None,
+            block_ends: vec![]
+        };
         p.next_token();
         p.next_token();
         return p;
@@ -45,7 +65,9 @@ impl<'a> Parser<'a> {
 
     pub fn next_token(&mut self) {
         self.curr = self.peek.clone();
+        self.curr_span = self.peek_span;
         self.peek = self.l.next_token();
+        self.peek_span = self.l.span();
     }
 
     fn curr_is(&self, t: Token) -> bool {
@@ -53,10 +75,10 @@ impl<'a> Parser<'a> {
     }
 
     fn peek_is(&self, t: &Token) -> bool {
-        return std::mem::discriminant(&self.peek) == std::mem::discriminant(&t);
+        return std::mem::discriminant(&self.peek) == std::mem::discriminant(t);
     }
 
-    fn expect_peek(&mut self, token: &Token) -> Result<(), Box<Error>> {
+    fn expect_peek(&mut self, token: &Token) -> Result<(), Box<dyn Error>> {
         if self.peek_is(token) {
             self.next_token();
             return Ok(())
@@ -67,10 +89,11 @@ impl<'a> Parser<'a> {
 
     fn peek_error(&mut self, t: &Token) -> Result<(), Box<dyn Error>> {
         let msg = format!("expected next token to be {:?}, got {:?} instead", t, self.peek);
+        self.error_span = Some(self.peek_span);
         return Err(msg.into());
     }
 
-    fn parse_let_statement(&mut self) -> Result<Stmt, Box<Error>> {
+    fn parse_let_statement(&mut self, start: Span) -> Result<Stmt, Box<dyn Error>> {
 
         self.expect_peek(&Token::Ident("".to_string()))?;
 
@@ -92,12 +115,12 @@ impl<'a> Parser<'a> {
         }
 
         return Ok(
-            Stmt::LetStmt(Ident(name), value)
+            Stmt::LetStmt(Ident(name), value, self.location(start))
         );
 
     }
 
-    fn parse_return_statement(&mut self) -> Result<Stmt, Box<Error>> {
+    fn parse_return_statement(&mut self, start: Span) -> Result<Stmt, Box<dyn Error>> {
         self.next_token();
 
         let value = self.parse_expression(Precedence::Lowest)?;
@@ -106,10 +129,10 @@ impl<'a> Parser<'a> {
             self.next_token();
         }
 
-        return Ok(Stmt::ReturnStmt(value));
+        return Ok(Stmt::ReturnStmt(value, self.location(start)));
     }
 
-    fn parse_prefix(&mut self, p: Prefix) -> Result<Expr, Box<Error>> {
+    fn parse_prefix(&mut self, p: Prefix) -> Result<Expr, Box<dyn Error>> {
         self.next_token();
         let y = self.parse_expression(Precedence::Prefix)?;
         return Ok(Expr::Prefix(p, Box::new(y)));
@@ -142,7 +165,7 @@ impl<'a> Parser<'a> {
         return Parser::get_precedence(&self.curr);
     }
 
-    fn get_infix(token: &Token) -> Result<Infix, Box<Error>> {
+    fn get_infix(token: &Token) -> Result<Infix, Box<dyn Error>> {
         return match token {
             Token::Minus => Ok(Infix::Minus),
             Token::Plus => Ok(Infix::Plus),
@@ -158,7 +181,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_call_args(&mut self) -> Result<Args, Box<Error>> {
+    fn parse_call_args(&mut self) -> Result<Args, Box<dyn Error>> {
         let mut args = vec![];
         if self.peek_is(&Token::RParen) {
             self.next_token();
@@ -183,21 +206,21 @@ impl<'a> Parser<'a> {
 
     }
 
-    fn parse_call_expr(&mut self, function: &Expr) -> Result<Expr, Box<Error>> {
+    fn parse_call_expr(&mut self, function: Expr) -> Result<Expr, Box<dyn Error>> {
         let args = self.parse_call_args()?;
-        return Ok(Expr::Call(Box::new(function.clone()), args));
+        return Ok(Expr::Call(Box::new(function), args));
     }
 
-    fn parse_index_expr(&mut self, left: &Expr) -> Result<Expr, Box<Error>> {
+    fn parse_index_expr(&mut self, left: Expr) -> Result<Expr, Box<dyn Error>> {
         self.next_token();
         let index = self.parse_expression(Precedence::Lowest)?;
         self.expect_peek(&Token::RBracket)?;
-        return Ok(Expr::IndexExpr(Box::new(left.clone()), Box::new(index)));
+        return Ok(Expr::IndexExpr(Box::new(left), Box::new(index)));
     }
 
 
 
-    fn parse_infix(&mut self, left: &Expr, operator: Infix) -> Result<Expr, Box<Error>>{
+    fn parse_infix(&mut self, left: Expr, operator: Infix) -> Result<Expr, Box<dyn Error>>{
         if Infix::Call == operator {
             return self.parse_call_expr(left);
         }
@@ -210,17 +233,17 @@ impl<'a> Parser<'a> {
 
         self.next_token();
         let right = self.parse_expression(precedence)?;
-        return Ok(Expr::Infix(Box::new(left.clone()), operator, Box::new(right)));
+        return Ok(Expr::Infix(Box::new(left), operator, Box::new(right)));
     }
 
-    fn parse_grouped(&mut self) -> Result<Expr, Box<Error>> {
+    fn parse_grouped(&mut self) -> Result<Expr, Box<dyn Error>> {
         self.next_token();
         let exp = self.parse_expression(Precedence::Lowest)?;
         self.expect_peek(&Token::RParen)?;
         return Ok(exp); 
     }
 
-    fn parse_block(&mut self) -> Result<Block, Box<Error>> {
+    fn parse_block(&mut self) -> Result<Block, Box<dyn Error>> {
         let mut block = vec![];
         self.next_token();
 
@@ -228,12 +251,13 @@ impl<'a> Parser<'a> {
             block.push(self.parse_statement()?);
             self.next_token();
         }
+        self.block_ends.push(self.curr_span);
 
         return Ok(block);
 
     }
 
-    fn parse_if(&mut self) -> Result<Expr, Box<Error>> {
+    fn parse_if(&mut self) -> Result<Expr, Box<dyn Error>> {
 
 
         self.expect_peek(&Token::LParen)?;
@@ -260,7 +284,7 @@ impl<'a> Parser<'a> {
         return Ok(Expr::If(Box::new(condition), consequence, alternative)); 
     }
 
-    fn parse_params(&mut self) -> Result<Params, Box<Error>> {
+    fn parse_params(&mut self) -> Result<Params, Box<dyn Error>> {
         let mut identifiers = vec![];
 
         if self.peek_is(&Token::RParen) {
@@ -289,7 +313,7 @@ impl<'a> Parser<'a> {
     }
 
 
-    fn parse_function(&mut self) -> Result<Expr, Box<Error>> {
+    fn parse_function(&mut self) -> Result<Expr, Box<dyn Error>> {
         self.expect_peek(&Token::LParen)?;
         let parameters = self.parse_params()?;
         self.expect_peek(&Token::LBrace)?;
@@ -297,7 +321,7 @@ impl<'a> Parser<'a> {
         return Ok(Expr::Fn(Rc::new(parameters), Rc::new(block))); 
     }
 
-    fn get_prefix(&mut self, token: &Token) -> Result<Expr, Box<Error>> {
+    fn get_prefix(&mut self, token: &Token) -> Result<Expr, Box<dyn Error>> {
         match token {
             Token::Ident(x) => Ok(Expr::IdentExpr(Ident(x.to_string()))),
             Token::Int(x) => Ok(Expr::LiteralExpr(Literal::Int(*x))),
@@ -318,7 +342,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_hash_literal(&mut self) -> Result<Expr, Box<Error>> {
+    fn parse_hash_literal(&mut self) -> Result<Expr, Box<dyn Error>> {
         let mut list = vec![];
         while !self.peek_is(&Token::RBrace) {
             self.next_token();
@@ -339,14 +363,14 @@ impl<'a> Parser<'a> {
 
     }
 
-    fn parse_array(&mut self) -> Result<Expr, Box<Error>> {
+    fn parse_array(&mut self) -> Result<Expr, Box<dyn Error>> {
         let elements = self.parse_expression_list(&Token::RBracket)?;
         return Ok(Expr::Array(elements))
     }
 
 
 
-    fn parse_expression_list(&mut self, end: &Token) -> Result<Vec<Expr>, Box<Error>> {
+    fn parse_expression_list(&mut self, end: &Token) -> Result<Vec<Expr>, Box<dyn Error>> {
         let mut list = vec![];
         if self.peek_is(end) {
             self.next_token();
@@ -366,48 +390,63 @@ impl<'a> Parser<'a> {
         return Ok(list);
     }
 
-    fn parse_expression(&mut self, precedece: Precedence) -> Result<Expr, Box<Error>>  {
+    fn parse_expression(&mut self, precedece: Precedence) -> Result<Expr, Box<dyn Error>>  {
 
         let prefix = self.get_prefix(&self.curr.clone())?;
         let mut left = prefix;
         while !self.peek_is(&Token::Semicolon) && precedece < self.peek_precedence() {
             let infix = Parser::get_infix(&self.peek)?;
             self.next_token();
-            left = self.parse_infix(&left,infix)?;
+            left = self.parse_infix(left,infix)?;
         }
         return Ok(left)
     }
 
-    fn parse_expression_statement(&mut self) -> Result<Stmt, Box<Error>> {
+    fn parse_expression_statement(&mut self, start: Span) -> Result<Stmt, Box<dyn Error>> {
         let exp = self.parse_expression(Precedence::Lowest)?;
 
         if self.peek_is(&Token::Semicolon) {
             self.next_token();
         }
 
-        return Ok(Stmt::ExprStmt(exp))
+        return Ok(Stmt::ExprStmt(exp, self.location(start)))
     }
 
 
-    fn parse_statement(&mut self) -> Result<Stmt, Box<Error>> {
+    fn parse_statement(&mut self) -> Result<Stmt, Box<dyn Error>> {
+        let start = self.curr_span;
         return match self.curr {
-            Token::Let => Ok(self.parse_let_statement()?),
-            Token::Return => Ok(self.parse_return_statement()?),
-            _ => Ok(self.parse_expression_statement()?)
-        }
+            Token::Let => self.parse_let_statement(start),
+            Token::Return => self.parse_return_statement(start),
+            _ => self.parse_expression_statement(start)
+        };
+    }
+
+    // From start to the token the statement just parsed ends with
+    fn location(&self, start: Span) -> Location {
+        return Location{ start, end: self.curr_span };
+    }
+
+    // The comments the lexer has skipped so far
+    pub fn comments(&self) -> &[Comment] {
+        return &self.l.comments;
     }
 
-    pub fn parse_program(&mut self) -> Result<Program, &Vec<Box<Error>>> {
+    pub fn parse_program(&mut self) -> Result<Program, &Vec<Box<dyn Error>>> {
         let mut program: Program = Vec::new();
         while self.curr != Token::EOF {
             match self.parse_statement() {
                 Ok(x) => program.push(x),
-                Err(x) => self.errors.push(x)
+                Err(x) => {
+                    self.errors.push(x);
+                    let span = self.error_span.take().unwrap_or(self.curr_span);
+                    self.error_spans.push(span);
+                }
             }
             self.next_token();
         }
 
-        if self.errors.len() != 0 {
+        if !self.errors.is_empty() {
             return Err(&self.errors)
         } else {
             return Ok(program);
@@ -0,0 +1,437 @@
+use super::ast::*;
+use super::{Parser, Precedence};
+use crate::lexer::Lexer;
+use crate::lexer::token::{Comment, Span};
+use std::fmt;
+
+// Turns a syntax tree back into Monkey source, laid out the same way whatever
+// it was parsed from: blocks on their own lines and indented, a semicolon
+// after every statement but the last expression of a block, and only the
+// parentheses the parser needs to build the same tree again. Calls, arrays
+// and hashes too long for a line get one element per line.
+//
+// Given where the tree was in its source, comments are put back too, and
+// blank lines between statements are kept, though never more than one.
+
+// How the source is laid out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    // Spaces for each level of nesting
+    pub indent: usize,
+    // The longest a line should get
+    pub width: usize
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        return Style{ indent: 4, width: 100 };
+    }
+}
+
+// Where the blocks and comments of a program were, as the parser found them.
+// Statements carry their own locations.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout<'a> {
+    pub block_ends: &'a [Span],
+    pub comments: &'a [Comment]
+}
+
+impl<'a> Layout<'a> {
+    pub fn of(parser: &'a Parser) -> Self {
+        return Layout{
+            block_ends: &parser.block_ends,
+            comments: parser.comments()
+        };
+    }
+}
+
+pub fn print(program: &Program) -> String {
+    return Printer::new(Style::default()).program(program);
+}
+
+// Parses source and prints it in style with its comments, or gives the parser's errors
+pub fn format(source: &str, style: Style) -> Result<String, Vec<(Span, String)>> {
+    let mut l = Lexer::new(source);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            let errors: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            return Err(p.error_spans.iter().cloned().zip(errors).collect());
+        }
+    };
+    return Ok(Printer::with_layout(style, Layout::of(&p)).program(&program));
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = Printer::new(Style::default());
+        printer.statement(self);
+        return f.write_str(&printer.out);
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = Printer::new(Style::default());
+        printer.expression(self);
+        return f.write_str(&printer.out);
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prefix::Plus => write!(f, "+"),
+            Prefix::Minus => write!(f, "-"),
+            Prefix::Not => write!(f, "!")
+        }
+    }
+}
+
+impl fmt::Display for Infix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Infix::Plus => write!(f, "+"),
+            Infix::Minus => write!(f, "-"),
+            Infix::Divide => write!(f, "/"),
+            Infix::Multiply => write!(f, "*"),
+            Infix::GT => write!(f, ">"),
+            Infix::LT => write!(f, "<"),
+            Infix::Equal => write!(f, "=="),
+            Infix::NotEqual => write!(f, "!="),
+            Infix::Call => write!(f, "("),
+            Infix::Index => write!(f, "[")
+        }
+    }
+}
+
+// How tightly an expression holds together, as the parser sees it
+fn precedence(expr: &Expr) -> Precedence {
+    return match expr {
+        Expr::Infix(_, Infix::Equal | Infix::NotEqual, _) => Precedence::Equals,
+        Expr::Infix(_, Infix::LT | Infix::GT, _) => Precedence::LessGreater,
+        Expr::Infix(_, Infix::Plus | Infix::Minus, _) => Precedence::Sum,
+        Expr::Infix(_, Infix::Multiply | Infix::Divide, _) => Precedence::Product,
+        Expr::Infix(_, Infix::Call, _) | Expr::Call(..) => Precedence::Call,
+        Expr::Infix(_, Infix::Index, _) | Expr::IndexExpr(..) => Precedence::Index,
+        // Only folding makes negative literals, they print like the prefix they came from
+        Expr::Prefix(..) | Expr::LiteralExpr(Literal::Int(i64::MIN..=-1)) => Precedence::Prefix,
+        _ => Precedence::Index
+    }
+}
+
+// How far the printer has got, to go back to when a list does not fit on its line
+#[derive(Clone, Copy)]
+struct Mark {
+    len: usize,
+    block: usize,
+    comment: usize,
+    last_line: Option<usize>
+}
+
+pub struct Printer<'a> {
+    style: Style,
+    layout: Option<Layout<'a>>,
+    out: String,
+    depth: usize,
+    // The block and comment of the layout that come next
+    block: usize,
+    comment: usize,
+    // The source line of what was printed last in the current block, None at its start
+    last_line: Option<usize>,
+    // Whether a list in the current block was put on several lines
+    broken: bool
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(style: Style) -> Self {
+        return Printer{ style, layout: None, out: String::new(), depth: 0, block: 0, comment: 0, last_line: None, broken: false };
+    }
+
+    pub fn with_layout(style: Style, layout: Layout<'a>) -> Self {
+        return Printer{ layout: Some(layout), ..Printer::new(style) };
+    }
+
+    pub fn program(mut self, program: &Program) -> String {
+        self.statements(program);
+        self.leading_comments(usize::MAX);
+        return self.out;
+    }
+
+    fn mark(&self) -> Mark {
+        return Mark{ len: self.out.len(), block: self.block, comment: self.comment, last_line: self.last_line };
+    }
+
+    fn reset(&mut self, mark: Mark) {
+        self.out.truncate(mark.len);
+        self.block = mark.block;
+        self.comment = mark.comment;
+        self.last_line = mark.last_line;
+    }
+
+    fn indent(&mut self) {
+        self.out.push_str(&" ".repeat(self.style.indent * self.depth));
+    }
+
+    fn next_comment(&self) -> Option<&'a Comment> {
+        return self.layout.and_then(|x| x.comments.get(self.comment));
+    }
+
+    fn comment_before(&self, line: usize) -> bool {
+        return self.next_comment().is_some_and(|x| x.span.line < line);
+    }
+
+    // The next comment if it is on line `end` or before, and before the brace
+    // that closes the block around, where it would be the block's instead
+    fn comment_within(&self, end: usize) -> Option<&'a Comment> {
+        let comment = self.next_comment().filter(|x| x.span.line <= end)?;
+        let close = self.layout.and_then(|x| x.block_ends.get(self.block));
+        if close.is_some_and(|x| (x.line, x.column) < (comment.span.line, comment.span.column)) {
+            return None;
+        }
+        return Some(comment);
+    }
+
+    fn blank_line_before(&mut self, line: usize) {
+        if self.last_line.is_some_and(|last| line > last + 1) {
+            self.out.push('\n');
+        }
+    }
+
+    // The comments above line, each on a line of its own
+    fn leading_comments(&mut self, line: usize) {
+        while let Some(comment) = self.next_comment().filter(|x| x.span.line < line) {
+            self.blank_line_before(comment.span.line);
+            self.indent();
+            self.out.push_str(&comment.text);
+            self.out.push('\n');
+            self.last_line = Some(comment.span.line);
+            self.comment += 1;
+        }
+    }
+
+    // Each statement on its own line. The last one keeps its value without a
+    // semicolon; the others need one, or `f` and `(1)` on the next line would
+    // run together into a call. An `if` only needs one before a statement that
+    // could go on with it, so it gets one then.
+    fn statements(&mut self, statements: &[Stmt]) {
+        let mut open_if: Option<usize> = None;
+        for (i, statement) in statements.iter().enumerate() {
+            let location = statement.location();
+            let span = self.layout.map(|_| (location.start.line, location.end.line));
+            if let Some((start, _)) = span {
+                self.leading_comments(start);
+                self.blank_line_before(start);
+            }
+
+            self.indent();
+            let start = self.out.len();
+            self.statement(statement);
+            if let Some(end) = open_if.take() {
+                if self.out[start..].starts_with(['(', '[', '-', '+']) {
+                    self.out.insert(end, ';');
+                }
+            }
+            if i + 1 < statements.len() {
+                match statement {
+                    Stmt::ExprStmt(Expr::If(..), _) => open_if = Some(self.out.len()),
+                    Stmt::ExprStmt(..) => self.out.push(';'),
+                    _ => {}
+                }
+            }
+
+            let end = match span {
+                Some((_, end)) => end,
+                None => {
+                    self.out.push('\n');
+                    continue;
+                }
+            };
+            // A comment on the statement's last line stays there. Comments inside
+            // the statement but in none of its blocks go after it, in order.
+            match self.comment_within(end) {
+                Some(comment) if comment.span.line == end => {
+                    self.out.push(' ');
+                    self.out.push_str(&comment.text);
+                    self.out.push('\n');
+                    self.comment += 1;
+                },
+                _ => {
+                    self.out.push('\n');
+                    while let Some(comment) = self.comment_within(end) {
+                        self.indent();
+                        self.out.push_str(&comment.text);
+                        self.out.push('\n');
+                        self.comment += 1;
+                    }
+                }
+            }
+            self.last_line = Some(end);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        let close = self.layout.map_or(0, |x| x.block_ends[self.block].line);
+        if block.is_empty() && !self.comment_before(close) {
+            self.block += 1;
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push_str("{\n");
+        self.depth += 1;
+        let outer = self.last_line.take();
+        let broken = std::mem::replace(&mut self.broken, false);
+        self.statements(block);
+        // Nested blocks close first
+        let close = self.layout.map_or(0, |x| x.block_ends[self.block].line);
+        self.block += 1;
+        self.leading_comments(close);
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+        self.last_line = outer;
+        self.broken = broken;
+    }
+
+    // Whether the line the text from `from` starts on fits in the width
+    fn fits(&self, from: usize) -> bool {
+        let start = self.out[..from].rfind('\n').map_or(0, |x| x + 1);
+        let end = self.out[from..].find('\n').map_or(self.out.len(), |x| from + x);
+        return self.out[start..end].chars().count() <= self.style.width;
+    }
+
+    // Items between open and close on one line if they fit, each on its own
+    // otherwise. A list holding one that did not fit does not fit either.
+    fn list<T>(&mut self, open: char, items: &[T], close: char, item: impl Fn(&mut Self, &T)) {
+        let mark = self.mark();
+        let broken = std::mem::replace(&mut self.broken, false);
+        self.out.push(open);
+        for (i, x) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            item(self, x);
+        }
+        self.out.push(close);
+        if items.is_empty() || (!self.broken && self.fits(mark.len)) {
+            self.broken = broken;
+            return;
+        }
+
+        self.reset(mark);
+        self.out.push(open);
+        self.out.push('\n');
+        self.depth += 1;
+        for (i, x) in items.iter().enumerate() {
+            self.indent();
+            item(self, x);
+            if i + 1 < items.len() {
+                self.out.push(',');
+            }
+            self.out.push('\n');
+        }
+        self.depth -= 1;
+        self.indent();
+        self.out.push(close);
+        self.broken = true;
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::LetStmt(name, value, _) => {
+                self.out.push_str(&format!("let {} = ", name));
+                self.expression(value);
+                self.out.push(';');
+            },
+            Stmt::ReturnStmt(value, _) => {
+                self.out.push_str("return ");
+                self.expression(value);
+                self.out.push(';');
+            },
+            Stmt::ExprStmt(value, _) => self.expression(value)
+        }
+    }
+
+    // Writes expr, in parentheses when it holds together less tightly than min
+    fn operand(&mut self, expr: &Expr, min: Precedence) {
+        if precedence(expr) < min {
+            self.out.push('(');
+            self.expression(expr);
+            self.out.push(')');
+        } else {
+            self.expression(expr);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::IdentExpr(name) => self.out.push_str(&name.0),
+            Expr::LiteralExpr(Literal::Int(x)) => self.out.push_str(&x.to_string()),
+            Expr::LiteralExpr(Literal::Bool(x)) => self.out.push_str(&x.to_string()),
+            Expr::LiteralExpr(Literal::Str(x)) => self.out.push_str(&format!("\"{}\"", x)),
+            Expr::LiteralExpr(Literal::Hash(pairs)) => self.list('{', pairs, '}', |printer, (key, value)| {
+                printer.expression(key);
+                printer.out.push_str(": ");
+                printer.expression(value);
+            }),
+            Expr::Prefix(operator, right) => {
+                self.out.push_str(&operator.to_string());
+                self.operand(right, Precedence::Prefix);
+            },
+            // Calls and indexing are kept as infix operators by nothing the parser
+            // makes, but they print all the same
+            Expr::Infix(left, Infix::Call, right) => {
+                self.operand(left, Precedence::Call);
+                self.list('(', std::slice::from_ref(&**right), ')', |printer, x| printer.expression(x));
+            },
+            Expr::Infix(left, Infix::Index, right) => {
+                self.operand(left, Precedence::Call);
+                self.out.push('[');
+                self.expression(right);
+                self.out.push(']');
+            },
+            // Operators group to the left, so an operand on the right with the
+            // same precedence needs parentheses
+            Expr::Infix(left, operator, right) => {
+                let own = precedence(expr);
+                self.operand(left, own.clone());
+                self.out.push_str(&format!(" {} ", operator));
+                if precedence(right) <= own {
+                    self.out.push('(');
+                    self.expression(right);
+                    self.out.push(')');
+                } else {
+                    self.expression(right);
+                }
+            },
+            Expr::If(condition, consequence, alternative) => {
+                self.out.push_str("if (");
+                self.expression(condition);
+                self.out.push_str(") ");
+                self.block(consequence);
+                if let Some(alternative) = alternative {
+                    self.out.push_str(" else ");
+                    self.block(alternative);
+                }
+            },
+            Expr::Fn(params, body) => {
+                let params: Vec<String> = params.iter().map(|x| x.to_string()).collect();
+                self.out.push_str(&format!("fn({}) ", params.join(", ")));
+                self.block(body);
+            },
+            Expr::Call(function, args) => {
+                self.operand(function, Precedence::Call);
+                self.list('(', args, ')', |printer, x| printer.expression(x));
+            },
+            Expr::Array(elements) => self.list('[', elements, ']', |printer, x| printer.expression(x)),
+            Expr::IndexExpr(left, index) => {
+                self.operand(left, Precedence::Call);
+                self.out.push('[');
+                self.expression(index);
+                self.out.push(']');
+            }
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use super::instruction::{Instruction, Register};
+use crate::code::Opcode;
+use crate::compiler::symbol_table::{SymbolTable, Symbol, SymbolScope};
+use crate::evaluator::builtins::BUILTINS;
+use crate::evaluator::object::{Object, RegisterFunction};
+use crate::parser::ast::*;
+use std::error::Error;
+use std::rc::Rc;
+
+// While a function is compiled its temporaries are numbered from here, and
+// once the number of locals is known they are moved down to follow them
+const TEMP: Register = 1 << 24;
+
+#[derive(Default)]
+struct Scope {
+    instructions: Vec<Instruction>,
+    next_temp: usize,
+    max_temps: usize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledProgram {
+    pub main: Rc<RegisterFunction>,
+    // Referenced by index from `Instruction::Closure`
+    pub functions: Vec<Rc<RegisterFunction>>,
+    pub constants: Vec<Rc<Object>>
+}
+
+pub struct Compiler {
+    constants: Vec<Rc<Object>>,
+    functions: Vec<Rc<RegisterFunction>>,
+    symbol_table: SymbolTable,
+    // One scope per function being compiled, the main program at the bottom
+    scopes: Vec<Scope>
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        let mut symbol_table = SymbolTable::new();
+        for (i, (name, _)) in BUILTINS.iter().enumerate() {
+            symbol_table.define_builtin(i, name);
+        }
+
+        return Compiler{
+            constants: vec![],
+            functions: vec![],
+            symbol_table,
+            scopes: vec![Scope::default()]
+        }
+    }
+
+    // A compiler for the next input of a REPL session, which sees the globals,
+    // constants and functions of the inputs before it and starts an empty main program
+    pub fn continuation(&self) -> Self {
+        return Compiler{
+            constants: self.constants.clone(),
+            functions: self.functions.clone(),
+            symbol_table: self.symbol_table.clone(),
+            scopes: vec![Scope::default()]
+        }
+    }
+
+    pub fn symbol_table(&self) -> &SymbolTable {
+        return &self.symbol_table;
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        return self.scopes.last_mut().expect("compiler has no scope");
+    }
+
+    fn emit(&mut self, ins: Instruction) -> usize {
+        let scope = self.scope();
+        scope.instructions.push(ins);
+        return scope.instructions.len() - 1;
+    }
+
+    fn current_position(&mut self) -> usize {
+        return self.scope().instructions.len();
+    }
+
+    // Points an already emitted jump at `target`
+    fn set_target(&mut self, position: usize, target: usize) {
+        match &mut self.scope().instructions[position] {
+            Instruction::Jump{ target: x } | Instruction::JumpIfFalse{ target: x, .. } => *x = target,
+            _ => unreachable!("not a jump")
+        }
+    }
+
+    // Allocates `n` consecutive temporaries, returning the first
+    fn temps(&mut self, n: usize) -> Register {
+        let scope = self.scope();
+        let first = TEMP + scope.next_temp;
+        scope.next_temp += n;
+        scope.max_temps = scope.max_temps.max(scope.next_temp);
+        return first;
+    }
+
+    fn temp(&mut self) -> Register {
+        return self.temps(1);
+    }
+
+    // Temporaries allocated after the mark are free again once it is released
+    fn mark(&mut self) -> usize {
+        return self.scope().next_temp;
+    }
+
+    fn release(&mut self, mark: usize) {
+        self.scope().next_temp = mark;
+    }
+
+    fn add_constant(&mut self, obj: Object) -> usize {
+        self.constants.push(Rc::new(obj));
+        return self.constants.len() - 1;
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol, dst: Register) {
+        match symbol.scope {
+            SymbolScope::Global => { self.emit(Instruction::GetGlobal{ dst, idx: symbol.index }); },
+            SymbolScope::Local if symbol.index == dst => {},
+            SymbolScope::Local => { self.emit(Instruction::Move{ dst, src: symbol.index }); },
+            SymbolScope::Free => { self.emit(Instruction::GetFree{ dst, idx: symbol.index }); },
+            SymbolScope::Function => { self.emit(Instruction::CurrentClosure{ dst }); },
+            SymbolScope::Builtin => { self.emit(Instruction::GetBuiltin{ dst, idx: symbol.index }); }
+        }
+    }
+
+    fn store_symbol(&mut self, symbol: &Symbol, src: Register) {
+        match symbol.scope {
+            SymbolScope::Global => { self.emit(Instruction::SetGlobal{ idx: symbol.index, src }); },
+            _ if symbol.index == src => {},
+            _ => { self.emit(Instruction::Move{ dst: symbol.index, src }); }
+        }
+    }
+
+    fn resolve(&mut self, ident: &Ident) -> Result<Symbol, Box<dyn Error>> {
+        return match self.symbol_table.resolve(&ident.0) {
+            Some(symbol) => Ok(symbol),
+            None => Err(format!("unknown identifier: {}", ident).into())
+        }
+    }
+
+    // Compiles an expression, returning the register holding its value. Locals
+    // are used where they live, anything else goes to a new temporary.
+    fn compile_expr(&mut self, expr: &Expr) -> Result<Register, Box<dyn Error>> {
+        if let Expr::IdentExpr(ident) = expr {
+            let symbol = self.resolve(ident)?;
+            if symbol.scope == SymbolScope::Local {
+                return Ok(symbol.index);
+            }
+        }
+
+        let dst = self.temp();
+        self.compile_expr_into(expr, dst)?;
+        return Ok(dst);
+    }
+
+    fn compile_expr_into(&mut self, expr: &Expr, dst: Register) -> Result<(), Box<dyn Error>> {
+        let mark = self.mark();
+        match expr {
+            Expr::LiteralExpr(Literal::Int(x)) => {
+                let idx = self.add_constant(Object::Integer(*x));
+                self.emit(Instruction::LoadConst{ dst, idx });
+            },
+            Expr::LiteralExpr(Literal::Bool(value)) => { self.emit(Instruction::LoadBool{ dst, value: *value }); },
+            Expr::LiteralExpr(Literal::Str(x)) => {
+                let idx = self.add_constant(Object::Str(Rc::clone(x)));
+                self.emit(Instruction::LoadConst{ dst, idx });
+            },
+            Expr::LiteralExpr(Literal::Hash(pairs)) => {
+                let start = self.temps(2 * pairs.len());
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    self.compile_expr_into(key, start + 2 * i)?;
+                    self.compile_expr_into(value, start + 2 * i + 1)?;
+                }
+                self.emit(Instruction::Hash{ dst, start, len: pairs.len() });
+            },
+            Expr::Prefix(Prefix::Plus, right) => self.compile_expr_into(right, dst)?,
+            Expr::Prefix(op, right) => {
+                let src = self.compile_expr(right)?;
+                match op {
+                    Prefix::Minus => self.emit(Instruction::Minus{ dst, src }),
+                    _ => self.emit(Instruction::Not{ dst, src })
+                };
+            },
+            Expr::Infix(left, op, right) => {
+                let left = self.compile_expr(left)?;
+                let right = self.compile_expr(right)?;
+                let ins = match op {
+                    Infix::Plus => Instruction::Binary{ op: Opcode::OpAdd, dst, left, right },
+                    Infix::Minus => Instruction::Binary{ op: Opcode::OpSubtract, dst, left, right },
+                    Infix::Multiply => Instruction::Binary{ op: Opcode::OpMultiply, dst, left, right },
+                    Infix::Divide => Instruction::Binary{ op: Opcode::OpDivide, dst, left, right },
+                    Infix::Equal => Instruction::Binary{ op: Opcode::OpEqual, dst, left, right },
+                    Infix::NotEqual => Instruction::Binary{ op: Opcode::OpNotEqual, dst, left, right },
+                    Infix::GT => Instruction::Binary{ op: Opcode::OpGreaterThan, dst, left, right },
+                    Infix::LT => Instruction::Binary{ op: Opcode::OpGreaterThan, dst, left: right, right: left },
+                    _ => return Err("Not supported yet!".into())
+                };
+                self.emit(ins);
+            },
+            Expr::If(condition, consequence, alternative) => {
+                let cond = self.compile_expr(condition)?;
+                let jump_if_false = self.emit(Instruction::JumpIfFalse{ cond, target: 0 });
+                self.release(mark);
+
+                self.compile_block_into(consequence, dst)?;
+                let jump = self.emit(Instruction::Jump{ target: 0 });
+
+                let after_consequence = self.current_position();
+                self.set_target(jump_if_false, after_consequence);
+
+                match alternative {
+                    Some(block) => self.compile_block_into(block, dst)?,
+                    None => { self.emit(Instruction::LoadNull{ dst }); }
+                }
+
+                let after_alternative = self.current_position();
+                self.set_target(jump, after_alternative);
+            },
+            Expr::IdentExpr(ident) => {
+                let symbol = self.resolve(ident)?;
+                self.load_symbol(&symbol, dst);
+            },
+            Expr::Array(elements) => {
+                let start = self.temps(elements.len());
+                for (i, element) in elements.iter().enumerate() {
+                    self.compile_expr_into(element, start + i)?;
+                }
+                self.emit(Instruction::Array{ dst, start, len: elements.len() });
+            },
+            Expr::IndexExpr(left, index) => {
+                let left = self.compile_expr(left)?;
+                let index = self.compile_expr(index)?;
+                self.emit(Instruction::Index{ dst, left, index });
+            },
+            Expr::Fn(params, body) => self.compile_function(None, params, body, dst)?,
+            Expr::Call(function, args) => {
+                let func = self.temps(1 + args.len());
+                self.compile_expr_into(function, func)?;
+                for (i, arg) in args.iter().enumerate() {
+                    self.compile_expr_into(arg, func + 1 + i)?;
+                }
+                self.emit(Instruction::Call{ dst, func, argc: args.len() });
+            }
+        }
+        self.release(mark);
+        Ok(())
+    }
+
+    // Leaves the value of the block in `dst`, as needed by conditionals
+    fn compile_block_into(&mut self, block: &Block, dst: Register) -> Result<(), Box<dyn Error>> {
+        match block.split_last() {
+            Some((Stmt::ExprStmt(last, _), rest)) => {
+                for stmt in rest {
+                    self.compile_statement(stmt)?;
+                }
+                self.compile_expr_into(last, dst)?;
+            },
+            _ => {
+                for stmt in block {
+                    self.compile_statement(stmt)?;
+                }
+                self.emit(Instruction::LoadNull{ dst });
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_function(&mut self, name: Option<&Ident>, params: &Params, body: &Block, dst: Register) -> Result<(), Box<dyn Error>> {
+        self.scopes.push(Scope::default());
+        let outer = std::mem::take(&mut self.symbol_table);
+        self.symbol_table = SymbolTable::new_enclosed(outer);
+
+        if let Some(name) = name {
+            self.symbol_table.define_function_name(&name.0);
+        }
+
+        for param in params {
+            self.symbol_table.define(&param.0);
+        }
+
+        match body.split_last() {
+            Some((Stmt::ExprStmt(last, _), rest)) => {
+                for stmt in rest {
+                    self.compile_statement(stmt)?;
+                }
+                let src = self.compile_expr(last)?;
+                self.emit(Instruction::Return{ src });
+            },
+            Some((Stmt::ReturnStmt(..), _)) => {
+                for stmt in body {
+                    self.compile_statement(stmt)?;
+                }
+            },
+            _ => {
+                for stmt in body {
+                    self.compile_statement(stmt)?;
+                }
+                let src = self.temp();
+                self.emit(Instruction::LoadNull{ dst: src });
+                self.emit(Instruction::Return{ src });
+            }
+        }
+
+        let scope = self.scopes.pop().expect("compiler has no scope");
+        let outer = self.symbol_table.outer.take().expect("left the global scope");
+        let symbol_table = std::mem::replace(&mut self.symbol_table, *outer);
+
+        let num_locals = symbol_table.num_definitions;
+        self.functions.push(Rc::new(RegisterFunction{
+            instructions: relocate(scope.instructions, num_locals),
+            num_registers: num_locals + scope.max_temps,
+            num_params: params.len()
+        }));
+        let func = self.functions.len() - 1;
+
+        let start = self.temps(symbol_table.free_symbols.len());
+        for (i, free) in symbol_table.free_symbols.iter().enumerate() {
+            self.load_symbol(free, start + i);
+        }
+        self.emit(Instruction::Closure{ dst, func, start, num_free: symbol_table.free_symbols.len() });
+        Ok(())
+    }
+
+    fn compile_statement(&mut self, stmt: &Stmt) -> Result<(), Box<dyn Error>> {
+        let mark = self.mark();
+        match stmt {
+            Stmt::ExprStmt(x, _) => {
+                let src = self.compile_expr(x)?;
+                self.emit(Instruction::Pop{ src });
+            },
+            Stmt::LetStmt(ident, Expr::Fn(params, body), _) => {
+                let src = self.temp();
+                self.compile_function(Some(ident), params, body, src)?;
+                let symbol = self.symbol_table.define(&ident.0);
+                self.store_symbol(&symbol, src);
+            },
+            Stmt::LetStmt(ident, x, _) => {
+                let src = self.compile_expr(x)?;
+                let symbol = self.symbol_table.define(&ident.0);
+                self.store_symbol(&symbol, src);
+            },
+            Stmt::ReturnStmt(x, _) => {
+                let src = self.compile_expr(x)?;
+                self.emit(Instruction::Return{ src });
+            }
+        }
+        self.release(mark);
+        Ok(())
+    }
+
+    pub fn compile(&mut self, program: Program) -> Result<(), Box<dyn Error>> {
+        for stmt in &program {
+            self.compile_statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    pub fn program(&self) -> CompiledProgram {
+        // The main program keeps its variables in globals, so it only has temporaries
+        let main = &self.scopes[0];
+        let main = RegisterFunction{
+            instructions: relocate(main.instructions.clone(), 0),
+            num_registers: main.max_temps,
+            num_params: 0
+        };
+
+        return CompiledProgram{
+            main: Rc::new(main),
+            functions: self.functions.clone(),
+            constants: self.constants.clone()
+        }
+    }
+}
+
+fn relocate(mut instructions: Vec<Instruction>, num_locals: usize) -> Vec<Instruction> {
+    for ins in instructions.iter_mut() {
+        ins.map_registers(|r| if r >= TEMP { r - TEMP + num_locals } else { r });
+    }
+    return instructions;
+}
@@ -0,0 +1,98 @@
+use crate::code::Opcode;
+use std::fmt;
+
+// Index into the register file of the current frame. The parameters come
+// first, then the other locals, then the temporaries of the function.
+pub type Register = usize;
+
+// Three-address instructions. Jump targets are instruction indices within the
+// enclosing function. Values passed as a group, such as call arguments or array
+// elements, are read from consecutive registers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    LoadConst{ dst: Register, idx: usize },
+    LoadBool{ dst: Register, value: bool },
+    LoadNull{ dst: Register },
+    Move{ dst: Register, src: Register },
+    GetGlobal{ dst: Register, idx: usize },
+    SetGlobal{ idx: usize, src: Register },
+    GetFree{ dst: Register, idx: usize },
+    GetBuiltin{ dst: Register, idx: usize },
+    CurrentClosure{ dst: Register },
+    // One of the stack VM's binary opcodes, such as OpAdd or OpGreaterThan
+    Binary{ op: Opcode, dst: Register, left: Register, right: Register },
+    Minus{ dst: Register, src: Register },
+    Not{ dst: Register, src: Register },
+    Jump{ target: usize },
+    JumpIfFalse{ cond: Register, target: usize },
+    Array{ dst: Register, start: Register, len: usize },
+    // Keys and values alternate, starting at `start`
+    Hash{ dst: Register, start: Register, len: usize },
+    Index{ dst: Register, left: Register, index: Register },
+    // The arguments follow the callee in `func + 1 ..= func + argc`
+    Call{ dst: Register, func: Register, argc: usize },
+    Closure{ dst: Register, func: usize, start: Register, num_free: usize },
+    Return{ src: Register },
+    // Discards the value of an expression statement, remembering it as the last value
+    Pop{ src: Register }
+}
+
+impl Instruction {
+    // Applies `f` to every register operand
+    pub fn map_registers<F: Fn(Register) -> Register>(&mut self, f: F) {
+        match self {
+            Instruction::LoadConst{ dst, .. } | Instruction::LoadBool{ dst, .. } | Instruction::LoadNull{ dst } |
+            Instruction::GetGlobal{ dst, .. } | Instruction::GetFree{ dst, .. } | Instruction::GetBuiltin{ dst, .. } |
+            Instruction::CurrentClosure{ dst } => *dst = f(*dst),
+            Instruction::Move{ dst, src } | Instruction::Minus{ dst, src } | Instruction::Not{ dst, src } => {
+                *dst = f(*dst);
+                *src = f(*src);
+            },
+            Instruction::SetGlobal{ src, .. } | Instruction::Return{ src } | Instruction::Pop{ src } => *src = f(*src),
+            Instruction::Binary{ dst, left, right, .. } | Instruction::Index{ dst, left, index: right } => {
+                *dst = f(*dst);
+                *left = f(*left);
+                *right = f(*right);
+            },
+            Instruction::Jump{ .. } => {},
+            Instruction::JumpIfFalse{ cond, .. } => *cond = f(*cond),
+            Instruction::Array{ dst, start, .. } | Instruction::Hash{ dst, start, .. } |
+            Instruction::Closure{ dst, start, .. } => {
+                *dst = f(*dst);
+                *start = f(*start);
+            },
+            Instruction::Call{ dst, func, .. } => {
+                *dst = f(*dst);
+                *func = f(*func);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::LoadConst{ dst, idx } => write!(f, "r{} = const {}", dst, idx),
+            Instruction::LoadBool{ dst, value } => write!(f, "r{} = {}", dst, value),
+            Instruction::LoadNull{ dst } => write!(f, "r{} = null", dst),
+            Instruction::Move{ dst, src } => write!(f, "r{} = r{}", dst, src),
+            Instruction::GetGlobal{ dst, idx } => write!(f, "r{} = global {}", dst, idx),
+            Instruction::SetGlobal{ idx, src } => write!(f, "global {} = r{}", idx, src),
+            Instruction::GetFree{ dst, idx } => write!(f, "r{} = free {}", dst, idx),
+            Instruction::GetBuiltin{ dst, idx } => write!(f, "r{} = builtin {}", dst, idx),
+            Instruction::CurrentClosure{ dst } => write!(f, "r{} = current closure", dst),
+            Instruction::Binary{ op, dst, left, right } => write!(f, "r{} = {:?} r{} r{}", dst, op, left, right),
+            Instruction::Minus{ dst, src } => write!(f, "r{} = -r{}", dst, src),
+            Instruction::Not{ dst, src } => write!(f, "r{} = !r{}", dst, src),
+            Instruction::Jump{ target } => write!(f, "jump {}", target),
+            Instruction::JumpIfFalse{ cond, target } => write!(f, "jump {} unless r{}", target, cond),
+            Instruction::Array{ dst, start, len } => write!(f, "r{} = array r{} len {}", dst, start, len),
+            Instruction::Hash{ dst, start, len } => write!(f, "r{} = hash r{} len {}", dst, start, len),
+            Instruction::Index{ dst, left, index } => write!(f, "r{} = r{}[r{}]", dst, left, index),
+            Instruction::Call{ dst, func, argc } => write!(f, "r{} = call r{} argc {}", dst, func, argc),
+            Instruction::Closure{ dst, func, start, num_free } => write!(f, "r{} = closure fn {} free r{} len {}", dst, func, start, num_free),
+            Instruction::Return{ src } => write!(f, "return r{}", src),
+            Instruction::Pop{ src } => write!(f, "pop r{}", src)
+        }
+    }
+}
@@ -0,0 +1,270 @@
+pub mod instruction;
+pub mod compiler;
+
+use crate::evaluator::object::{Object, RegisterFunction, RegisterClosure};
+use crate::evaluator::builtins::BUILTINS;
+use crate::evaluator::{memory, MAX_CALL_DEPTH};
+use crate::evaluator::interrupt::{CancelToken, Watchdog};
+use crate::vm::{binary_operation, index_operation, build_hash, builtin_error, is_truthy, take_fuel};
+use compiler::CompiledProgram;
+use instruction::{Instruction, Register};
+use std::error::Error;
+use std::rc::Rc;
+use std::time::Instant;
+
+// A register machine running the output of `regvm::compiler`. All frames share
+// one register file, each frame owning the window starting at its base.
+
+struct Frame {
+    cl: Rc<RegisterClosure>,
+    ip: usize,
+    base: usize,
+    // Where the caller wants the return value, as an index into the register file
+    ret: usize
+}
+
+pub struct VM {
+    constants: Vec<Rc<Object>>,
+    functions: Vec<Rc<RegisterFunction>>,
+    registers: Vec<Rc<Object>>,
+    globals: Vec<Rc<Object>>,
+    frames: Vec<Frame>,
+    last_popped: Option<Rc<Object>>,
+    null: Rc<Object>,
+    booleans: [Rc<Object>; 2],
+    // Instructions left to execute, None when unmetered
+    fuel: Option<u64>,
+    memory_limit: Option<usize>,
+    memory_allocated: usize,
+    watchdog: Watchdog
+}
+
+impl VM {
+    pub fn new(program: CompiledProgram) -> Self {
+        let null = Rc::new(Object::Null);
+        let main = RegisterClosure{ func: program.main, free: vec![] };
+        return VM{
+            constants: program.constants,
+            functions: program.functions,
+            registers: vec![Rc::clone(&null); main.func.num_registers],
+            globals: vec![],
+            frames: vec![Frame{ cl: Rc::new(main), ip: 0, base: 0, ret: 0 }],
+            last_popped: None,
+            null,
+            booleans: [Rc::new(Object::Boolean(false)), Rc::new(Object::Boolean(true))],
+            fuel: None,
+            memory_limit: None,
+            memory_allocated: 0,
+            watchdog: Watchdog::default()
+        }
+    }
+
+    // Limits the run to this many more instructions. A run that used it all up
+    // carries on from where it stopped when given more and run again.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        return self.fuel;
+    }
+
+    // Caps the bytes of arrays, strings and hashes this VM may still create,
+    // counting all it has created so far whether or not they are still held
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = Some(bytes);
+    }
+
+    // The bytes of every array, string and hash this VM has created
+    pub fn memory_allocated(&self) -> usize {
+        return self.memory_allocated;
+    }
+
+    // Stops the run with Cancelled once the token is cancelled
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.watchdog.token = Some(token);
+    }
+
+    // Stops the run with Timeout once the deadline has passed
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.watchdog.deadline = Some(deadline);
+    }
+
+    // Carries the globals of one run over to the next, as the REPL does line by line
+    pub fn set_globals(&mut self, globals: Vec<Rc<Object>>) {
+        self.globals = globals;
+    }
+
+    pub fn into_globals(self) -> Vec<Rc<Object>> {
+        return self.globals;
+    }
+
+    fn current_frame(&mut self) -> &mut Frame {
+        return self.frames.last_mut().expect("vm has no frame");
+    }
+
+    fn next_instruction(&mut self) -> Option<(Instruction, usize)> {
+        let frame = self.frames.last_mut()?;
+        let ins = *frame.cl.func.instructions.get(frame.ip)?;
+        frame.ip += 1;
+        return Some((ins, frame.base));
+    }
+
+    fn boolean(&self, value: bool) -> Rc<Object> {
+        return Rc::clone(&self.booleans[value as usize]);
+    }
+
+    fn call_function(&mut self, base: usize, dst: Register, func: Register, argc: usize) -> Result<(), Box<dyn Error>> {
+        let callee = Rc::clone(&self.registers[base + func]);
+        let args = base + func + 1..base + func + 1 + argc;
+        match callee.as_ref() {
+            Object::RegisterClosure(cl) => {
+                if cl.func.num_params != argc {
+                    return Err(format!("wrong number of arguments: want={}, got={}", cl.func.num_params, argc).into());
+                }
+
+                // The main program has a frame too, but is no call
+                if self.frames.len() > MAX_CALL_DEPTH {
+                    return Err("stack overflow".into());
+                }
+
+                let new_base = self.registers.len();
+                self.registers.extend_from_within(args);
+                self.registers.resize(new_base + cl.func.num_registers, Rc::clone(&self.null));
+                self.frames.push(Frame{ cl: Rc::clone(cl), ip: 0, base: new_base, ret: base + dst });
+            },
+            Object::Builtin(builtin) => {
+                let args = self.registers[args].iter().map(|x| x.as_ref().clone()).collect();
+                let res = builtin(args);
+                if let Object::Error(msg) = res {
+                    return Err(builtin_error(msg));
+                }
+                self.registers[base + dst] = Rc::new(res);
+            },
+            _ => return Err(format!("Not callable: {}", callee).into())
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        let limit = match self.memory_limit {
+            Some(limit) => limit.saturating_sub(self.memory_allocated),
+            None => return self.execute()
+        };
+        let (result, allocated) = memory::with_limit(limit, || self.execute());
+        self.memory_allocated += allocated;
+        return result;
+    }
+
+    fn execute(&mut self) -> Result<(), Box<dyn Error>> {
+        loop {
+            // Checked before fetching, so that an interrupted run can be resumed
+            self.watchdog.check()?;
+            let (ins, base) = match self.next_instruction() {
+                Some(next) => next,
+                None => break
+            };
+            if let Err(x) = take_fuel(&mut self.fuel) {
+                // Put the instruction back, so that the run goes on from it once refueled
+                self.current_frame().ip -= 1;
+                return Err(x);
+            }
+            match ins {
+                Instruction::LoadConst{ dst, idx } => self.registers[base + dst] = Rc::clone(&self.constants[idx]),
+                Instruction::LoadBool{ dst, value } => self.registers[base + dst] = self.boolean(value),
+                Instruction::LoadNull{ dst } => self.registers[base + dst] = Rc::clone(&self.null),
+                Instruction::Move{ dst, src } => self.registers[base + dst] = Rc::clone(&self.registers[base + src]),
+                Instruction::GetGlobal{ dst, idx } => {
+                    self.registers[base + dst] = match self.globals.get(idx) {
+                        Some(obj) => Rc::clone(obj),
+                        None => return Err(format!("undefined global {}", idx).into())
+                    };
+                },
+                Instruction::SetGlobal{ idx, src } => {
+                    if idx >= self.globals.len() {
+                        self.globals.resize(idx + 1, Rc::clone(&self.null));
+                    }
+                    self.globals[idx] = Rc::clone(&self.registers[base + src]);
+                },
+                Instruction::GetFree{ dst, idx } => {
+                    let obj = Rc::clone(&self.current_frame().cl.free[idx]);
+                    self.registers[base + dst] = obj;
+                },
+                Instruction::GetBuiltin{ dst, idx } => {
+                    let (_, builtin) = BUILTINS[idx];
+                    self.registers[base + dst] = Rc::new(Object::Builtin(builtin));
+                },
+                Instruction::CurrentClosure{ dst } => {
+                    let cl = Rc::clone(&self.current_frame().cl);
+                    self.registers[base + dst] = Rc::new(Object::RegisterClosure(cl));
+                },
+                Instruction::Binary{ op, dst, left, right } => {
+                    let res = binary_operation(op, &self.registers[base + left], &self.registers[base + right])?;
+                    self.registers[base + dst] = match res {
+                        Object::Boolean(value) => self.boolean(value),
+                        res => Rc::new(res)
+                    };
+                },
+                Instruction::Minus{ dst, src } => {
+                    let res = match self.registers[base + src].as_ref() {
+                        Object::Integer(x) => Object::Integer(x.wrapping_neg()),
+                        _ => return Err("unknown operator".into())
+                    };
+                    self.registers[base + dst] = Rc::new(res);
+                },
+                Instruction::Not{ dst, src } => {
+                    let res = match self.registers[base + src].as_ref() {
+                        Object::Boolean(b) => !b,
+                        Object::Null => true,
+                        _ => false
+                    };
+                    self.registers[base + dst] = self.boolean(res);
+                },
+                Instruction::Jump{ target } => self.current_frame().ip = target,
+                Instruction::JumpIfFalse{ cond, target } => {
+                    if !is_truthy(&self.registers[base + cond]) {
+                        self.current_frame().ip = target;
+                    }
+                },
+                Instruction::Array{ dst, start, len } => {
+                    memory::allocate(memory::array_size(len))?;
+                    let arr = self.registers[base + start..base + start + len].iter().map(|x| x.as_ref().clone()).collect();
+                    self.registers[base + dst] = Rc::new(Object::Array(Rc::new(arr)));
+                },
+                Instruction::Hash{ dst, start, len } => {
+                    let pairs: Vec<Object> = self.registers[base + start..base + start + 2 * len].iter().map(|x| x.as_ref().clone()).collect();
+                    let h = build_hash(&pairs)?;
+                    self.registers[base + dst] = Rc::new(h);
+                },
+                Instruction::Index{ dst, left, index } => {
+                    let res = index_operation(&self.registers[base + left], &self.registers[base + index])?;
+                    self.registers[base + dst] = Rc::new(res);
+                },
+                Instruction::Call{ dst, func, argc } => self.call_function(base, dst, func, argc)?,
+                Instruction::Closure{ dst, func, start, num_free } => {
+                    let free = self.registers[base + start..base + start + num_free].to_vec();
+                    let cl = RegisterClosure{ func: Rc::clone(&self.functions[func]), free };
+                    self.registers[base + dst] = Rc::new(Object::RegisterClosure(Rc::new(cl)));
+                },
+                Instruction::Return{ src } => {
+                    let value = Rc::clone(&self.registers[base + src]);
+                    if self.frames.len() == 1 {
+                        // A top level return ends the program with its value
+                        self.last_popped = Some(value);
+                        return Ok(());
+                    }
+                    let frame = self.frames.pop().expect("vm has no frame");
+                    self.registers.truncate(frame.base);
+                    self.registers[frame.ret] = value;
+                },
+                Instruction::Pop{ src } => self.last_popped = Some(Rc::clone(&self.registers[base + src]))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn last_popped(&self) -> Option<&Object> {
+        let rc = self.last_popped.as_ref()?;
+        return Some(rc.as_ref());
+    }
+}
@@ -0,0 +1,15 @@
+use crate::evaluator::object::CompiledClosure;
+use std::{rc::Rc};
+
+pub struct Frame {
+    pub cl: Rc<CompiledClosure>,
+    pub ip: usize,
+    // Stack index of the first local, the closure itself sits just below it
+    pub base_pointer: usize
+}
+
+impl Frame {
+    pub fn new(cl: Rc<CompiledClosure>, base_pointer: usize) -> Self {
+        return Frame{ cl, ip: 0, base_pointer }
+    }
+}
@@ -1,129 +1,587 @@
+pub mod frame;
+pub mod verifier;
+pub mod value;
 
-use crate::evaluator::object::Object;
+use crate::evaluator::object::{Object, CompiledFunction, CompiledClosure};
+use crate::evaluator::builtins::{Builtin, BUILTINS};
+use crate::evaluator::{memory, BudgetExhausted, MAX_CALL_DEPTH};
+use crate::evaluator::interrupt::{CancelToken, Watchdog};
 use crate::{code::*, compiler};
+use frame::Frame;
+use value::Value;
+use std::collections::HashMap;
 use std::error::Error;
-use std::{rc::Rc};
+use std::{mem, rc::Rc};
+use std::time::Instant;
+
+// Number of stack slots a VM gets unless another size is asked for
+pub const STACK_SIZE: usize = 2048;
 
 pub struct VM {
-    constants: Vec<Rc<Object>>,
-    instructions: Instructions,
-    stack: Vec<Rc<Object>>,
-    last_popped: Option<Rc<Object>>
+    constants: Vec<Value>,
+    // Allocated once, sp is the next free slot
+    stack: Vec<Value>,
+    sp: usize,
+    globals: Vec<Value>,
+    frames: Vec<Frame>,
+    // Instructions left to execute, None when unmetered
+    fuel: Option<u64>,
+    memory_limit: Option<usize>,
+    memory_allocated: usize,
+    watchdog: Watchdog,
+    // Why the bytecode was rejected, given back by run without executing any of it
+    invalid: Option<verifier::VerifyError>
 }
 
 impl VM {
     pub fn new(bytecode: compiler::ByteCode ) -> Self {
+        return VM::with_stack_size(bytecode, STACK_SIZE);
+    }
+
+    // The bytecode is verified here, and run fails if it is not well formed.
+    // Globals are checked as they are read, so any of them may have been set.
+    pub fn with_stack_size(bytecode: compiler::ByteCode, stack_size: usize) -> Self {
+        let invalid = verifier::verify_continuation(&bytecode, usize::MAX).err();
+        let main_fn = CompiledFunction{ instructions: bytecode.instructions, lines: bytecode.lines, ..CompiledFunction::default() };
+        let main_closure = CompiledClosure{ func: Rc::new(main_fn), free: vec![] };
         return VM{
-            instructions: bytecode.instructions,
-            constants: bytecode.constants,
-            stack: vec![],
-            last_popped: None
+            constants: bytecode.constants.iter().map(Value::from).collect(),
+            stack: vec![Value::Null; stack_size],
+            sp: 0,
+            globals: vec![],
+            frames: vec![Frame::new(Rc::new(main_closure), 0)],
+            fuel: None,
+            memory_limit: None,
+            memory_allocated: 0,
+            watchdog: Watchdog::default(),
+            invalid
+        }
+    }
+
+    // Limits the run to this many more instructions. A run that used it all up
+    // carries on from where it stopped when given more and run again.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        return self.fuel;
+    }
+
+    // Caps the bytes of arrays, strings and hashes this VM may still create,
+    // counting all it has created so far whether or not they are still held
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = Some(bytes);
+    }
+
+    // The bytes of every array, string and hash this VM has created
+    pub fn memory_allocated(&self) -> usize {
+        return self.memory_allocated;
+    }
+
+    // Stops the run with Cancelled once the token is cancelled
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.watchdog.token = Some(token);
+    }
+
+    // Stops the run with Timeout once the deadline has passed
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.watchdog.deadline = Some(deadline);
+    }
+
+    // Carries the globals of one run over to the next, as the REPL does line by line
+    pub fn set_globals(&mut self, globals: Vec<Value>) {
+        self.globals = globals;
+    }
+
+    pub fn into_globals(self) -> Vec<Value> {
+        return self.globals;
+    }
+
+    // Verifies the bytecode first, for programs that did not come straight from
+    // the compiler, also rejecting reads of globals the program never sets
+    pub fn new_verified(bytecode: compiler::ByteCode) -> Result<Self, verifier::VerifyError> {
+        verifier::verify(&bytecode)?;
+        return Ok(VM::new(bytecode));
+    }
+
+    fn current_frame(&mut self) -> &mut Frame {
+        return self.frames.last_mut().expect("vm has no frame");
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        let frame = self.frames.last_mut()?;
+        let byte = *frame.cl.func.instructions.get(frame.ip)?;
+        frame.ip += 1;
+        return Some(byte);
+    }
+
+    fn read_u8_operand(&mut self) -> usize {
+        let frame = self.current_frame();
+        let operand = frame.cl.func.instructions[frame.ip];
+        frame.ip += 1;
+        return operand as usize;
+    }
+
+    fn read_u16_operand(&mut self) -> usize {
+        let frame = self.current_frame();
+        let operand = read_u16(&frame.cl.func.instructions[frame.ip..]);
+        frame.ip += 2;
+        return operand as usize;
+    }
+
+    fn push(&mut self, obj: Value) -> Result<(), Box<dyn Error>> {
+        if self.sp >= self.stack.len() {
+            return Err("stack overflow".into());
+        }
+        self.stack[self.sp] = obj;
+        self.sp += 1;
+        Ok(())
+    }
+
+    // Moves the value out, so that dead slots do not keep heap objects alive
+    fn pop(&mut self) -> Result<Value, Box<dyn Error>> {
+        if self.sp == 0 {
+            return Err("Not enough operands on stack".into());
+        }
+        self.sp -= 1;
+        return Ok(mem::replace(&mut self.stack[self.sp], Value::Null));
+    }
+
+    // Removes the top n values, oldest first
+    fn pop_n(&mut self, n: usize) -> Result<Vec<Value>, Box<dyn Error>> {
+        if self.sp < n {
+            return Err("Not enough operands on stack".into());
+        }
+        self.sp -= n;
+        return Ok(self.stack[self.sp..self.sp + n].iter_mut().map(|x| mem::replace(x, Value::Null)).collect());
+    }
+
+    fn execute_binary_operation(&mut self, op: Opcode) -> Result<(), Box<dyn Error>> {
+        let right = self.pop()?;
+        let left = self.pop()?;
+        let res = match (left, right) {
+            (Value::Integer(x), Value::Integer(y)) => integer_operation(op, x, y)?,
+            (left, right) => Value::from(binary_operation(op, &left.as_object(), &right.as_object())?)
+        };
+        self.push(res)?;
+        Ok(())
+    }
+
+    // Applies a binary operator with a constant as its right operand
+    fn execute_constant_operation(&mut self, op: Opcode) -> Result<(), Box<dyn Error>> {
+        let idx = self.read_u16_operand();
+        let left = self.pop()?;
+        let res = match (left, &self.constants[idx]) {
+            (Value::Integer(x), Value::Integer(y)) => integer_operation(op, x, *y)?,
+            (left, right) => Value::from(binary_operation(op, &left.as_object(), &right.as_object())?)
+        };
+        self.push(res)?;
+        Ok(())
+    }
+
+    fn execute_index(&mut self) -> Result<(), Box<dyn Error>> {
+        let index = self.pop()?;
+        let left = self.pop()?;
+        let res = index_operation(&left.as_object(), &index.as_object())?;
+        self.push(Value::from(res))?;
+        Ok(())
+    }
+
+    fn call_function(&mut self, num_args: usize) -> Result<(), Box<dyn Error>> {
+        if self.sp < num_args + 1 {
+            return Err("Not enough operands on stack".into());
+        }
+        let cl = match &self.stack[self.sp - 1 - num_args] {
+            Value::Closure(cl) => Rc::clone(cl),
+            Value::Object(obj) => match obj.as_ref() {
+                Object::Builtin(builtin) => return self.call_builtin(*builtin, num_args),
+                _ => return Err(format!("Not callable: {}", obj).into())
+            },
+            callee => return Err(format!("Not callable: {}", callee).into())
+        };
+
+        if cl.func.num_params != num_args {
+            return Err(format!("wrong number of arguments: want={}, got={}", cl.func.num_params, num_args).into());
+        }
+
+        // The main program has a frame too, but is no call
+        if self.frames.len() > MAX_CALL_DEPTH {
+            return Err("stack overflow".into());
+        }
+
+        let base_pointer = self.sp - num_args;
+        let sp = base_pointer + cl.func.num_locals;
+        if sp > self.stack.len() {
+            return Err("stack overflow".into());
+        }
+        for slot in &mut self.stack[self.sp..sp] {
+            *slot = Value::Null;
+        }
+        self.sp = sp;
+        self.frames.push(Frame::new(cl, base_pointer));
+        Ok(())
+    }
+
+    fn call_builtin(&mut self, builtin: Builtin, num_args: usize) -> Result<(), Box<dyn Error>> {
+        let args = self.pop_n(num_args)?;
+        let args = args.iter().map(Value::to_object).collect();
+        let res = builtin(args);
+        if let Object::Error(msg) = res {
+            return Err(builtin_error(msg));
+        }
+        self.pop()?;
+        self.push(Value::from(res))?;
+        Ok(())
+    }
+
+    fn return_from_function(&mut self, value: Value) {
+        let frame = self.frames.pop().expect("vm has no frame");
+        // The value replaces the closure that was called
+        self.sp = frame.base_pointer;
+        self.stack[self.sp - 1] = value;
+    }
+
+    fn push_closure(&mut self, const_idx: usize, num_free: usize) -> Result<(), Box<dyn Error>> {
+        let func = match self.constants.get(const_idx) {
+            Some(Value::Object(obj)) => match obj.as_ref() {
+                Object::CompiledFunction(func) => Rc::clone(func),
+                _ => return Err(format!("not a function: constant {}", const_idx).into())
+            },
+            _ => return Err(format!("not a function: constant {}", const_idx).into())
+        };
+
+        let free = self.pop_n(num_free)?;
+        self.push(Value::Closure(Rc::new(CompiledClosure{ func, free })))?;
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(x) = &self.invalid {
+            return Err(Box::new(x.clone()));
         }
+        let limit = match self.memory_limit {
+            Some(limit) => limit.saturating_sub(self.memory_allocated),
+            None => return self.execute()
+        };
+        let (result, allocated) = memory::with_limit(limit, || self.execute());
+        self.memory_allocated += allocated;
+        return result;
     }
 
-    pub fn run(&mut self) -> Result<(), Box<Error>> {
-        for instruction in &self.instructions {
-            match instruction {
-                Instruction::OpConstant(x) => self.stack.push(Rc::clone(&self.constants[*x])),
-                Instruction::OpTrue => self.stack.push(Rc::new(Object::Boolean(true))),
-                Instruction::OpFalse => self.stack.push(Rc::new(Object::Boolean(true))),
-                Instruction::OpPop => {
-                    self.last_popped = self.stack.pop();
+    fn execute(&mut self) -> Result<(), Box<dyn Error>> {
+        loop {
+            // Checked before fetching, so that an interrupted run can be resumed
+            self.watchdog.check()?;
+            let byte = match self.next_byte() {
+                Some(byte) => byte,
+                None => break
+            };
+            if let Err(x) = take_fuel(&mut self.fuel) {
+                // Put the instruction back, so that the run goes on from it once refueled
+                self.current_frame().ip -= 1;
+                return Err(x);
+            }
+            let op = match Opcode::from_byte(byte) {
+                Some(op) => op,
+                None => return Err(format!("unknown opcode {}", byte).into())
+            };
+
+            match op {
+                Opcode::OpConstant => {
+                    let idx = self.read_u16_operand();
+                    self.push(self.constants[idx].clone())?;
                 },
-                Instruction::OpAdd => {
-                    let a = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
-                    };
-            
-                    let b = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
-                    };
-            
-                    let res = match (a.as_ref(),b.as_ref()) {
-                        (Object::Integer(x),Object::Integer(y)) => Object::Integer(x+y),
-                        _ => return Err("Add not supported for these operands".into())
-                    };
-            
-            
-                    self.stack.push(Rc::new(res));
-                },
-                Instruction::OpSubtract => {
-                    let a = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
-                    };
-            
-                    let b = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
-                    };
-            
-                    let res = match (a.as_ref(),b.as_ref()) {
-                        (Object::Integer(x),Object::Integer(y)) => Object::Integer(y-x),
-                        _ => return Err("Add not supported for these operands".into())
-                    };
-            
-            
-                    self.stack.push(Rc::new(res));
-                },
-                Instruction::OpMultiply => {
-                    let a = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
-                    };
-            
-                    let b = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
-                    };
-            
-                    let res = match (a.as_ref(),b.as_ref()) {
-                        (Object::Integer(x),Object::Integer(y)) => Object::Integer(x*y),
-                        _ => return Err("Add not supported for these operands".into())
+                Opcode::OpTrue => self.push(Value::Boolean(true))?,
+                Opcode::OpFalse => self.push(Value::Boolean(false))?,
+                Opcode::OpNull => self.push(Value::Null)?,
+                Opcode::OpPop => {
+                    // The value stays in its slot for last_popped_stack_elem
+                    if self.sp == 0 {
+                        return Err("Not enough operands on stack".into());
+                    }
+                    self.sp -= 1;
+                },
+                Opcode::OpAdd | Opcode::OpSubtract | Opcode::OpMultiply | Opcode::OpDivide |
+                Opcode::OpEqual | Opcode::OpNotEqual | Opcode::OpGreaterThan | Opcode::OpLessThan => {
+                    self.execute_binary_operation(op)?
+                },
+                Opcode::OpMinus => {
+                    let res = match self.pop()? {
+                        Value::Integer(x) => Value::Integer(x.wrapping_neg()),
+                        _ => return Err("unknown operator".into())
                     };
-            
-            
-                    self.stack.push(Rc::new(res));
-                },
-                Instruction::OpDivide => {
-                    let a = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
+                    self.push(res)?;
+                },
+                Opcode::OpBang => {
+                    let res = match self.pop()? {
+                        Value::Boolean(b) => !b,
+                        Value::Null => true,
+                        _ => false
                     };
-            
-                    let b = match self.stack.pop() {
-                        Some(x) => x,
-                        None => return Err("Not enough operands on stack".into())
+                    self.push(Value::Boolean(res))?;
+                },
+                Opcode::OpJump => {
+                    let target = self.read_u16_operand();
+                    self.current_frame().ip = target;
+                },
+                Opcode::OpJumpNotGreater | Opcode::OpJumpNotLess => {
+                    let target = self.read_u16_operand();
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    let compare = if op == Opcode::OpJumpNotGreater { Opcode::OpGreaterThan } else { Opcode::OpLessThan };
+                    let holds = match (left, right) {
+                        (Value::Integer(x), Value::Integer(y)) => value_is_truthy(&integer_operation(compare, x, y)?),
+                        (left, right) => is_truthy(&binary_operation(compare, &left.as_object(), &right.as_object())?)
                     };
-            
-                    let res = match (a.as_ref(),b.as_ref()) {
-                        (Object::Integer(x),Object::Integer(y)) => Object::Integer(y/x),
-                        _ => return Err("Add not supported for these operands".into())
+                    if !holds {
+                        self.current_frame().ip = target;
+                    }
+                },
+                Opcode::OpJumpNotTruthy => {
+                    let target = self.read_u16_operand();
+                    let condition = self.pop()?;
+                    if !value_is_truthy(&condition) {
+                        self.current_frame().ip = target;
+                    }
+                },
+                Opcode::OpSetGlobal => {
+                    let idx = self.read_u16_operand();
+                    let obj = self.pop()?;
+                    if idx >= self.globals.len() {
+                        self.globals.resize(idx + 1, Value::Null);
+                    }
+                    self.globals[idx] = obj;
+                },
+                Opcode::OpGetGlobal => {
+                    let idx = self.read_u16_operand();
+                    let obj = match self.globals.get(idx) {
+                        Some(obj) => obj.clone(),
+                        None => return Err(format!("undefined global {}", idx).into())
                     };
-            
-            
-                    self.stack.push(Rc::new(res));
-                }
-
+                    self.push(obj)?;
+                },
+                Opcode::OpSetLocal => {
+                    let idx = self.read_u8_operand();
+                    let obj = self.pop()?;
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.stack[base_pointer + idx] = obj;
+                },
+                Opcode::OpGetLocal => {
+                    let idx = self.read_u8_operand();
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.push(self.stack[base_pointer + idx].clone())?;
+                },
+                Opcode::OpGetLocalGetLocal => {
+                    let first = self.read_u8_operand();
+                    let second = self.read_u8_operand();
+                    let base_pointer = self.current_frame().base_pointer;
+                    self.push(self.stack[base_pointer + first].clone())?;
+                    self.push(self.stack[base_pointer + second].clone())?;
+                },
+                Opcode::OpAddConst => self.execute_constant_operation(Opcode::OpAdd)?,
+                Opcode::OpSubConst => self.execute_constant_operation(Opcode::OpSubtract)?,
+                Opcode::OpConstantPop => {
+                    let idx = self.read_u16_operand();
+                    if self.sp >= self.stack.len() {
+                        return Err("stack overflow".into());
+                    }
+                    self.stack[self.sp] = self.constants[idx].clone();
+                },
+                Opcode::OpGetFree => {
+                    let idx = self.read_u8_operand();
+                    let obj = self.current_frame().cl.free[idx].clone();
+                    self.push(obj)?;
+                },
+                Opcode::OpCurrentClosure => {
+                    let cl = Rc::clone(&self.current_frame().cl);
+                    self.push(Value::Closure(cl))?;
+                },
+                Opcode::OpArray => {
+                    let len = self.read_u16_operand();
+                    memory::allocate(memory::array_size(len))?;
+                    let elements = self.pop_n(len)?;
+                    let arr = elements.iter().map(Value::to_object).collect();
+                    self.push(Value::from(Object::Array(Rc::new(arr))))?;
+                },
+                Opcode::OpHash => {
+                    let len = self.read_u16_operand();
+                    let pairs = self.pop_n(2 * len)?;
+                    let pairs: Vec<Object> = pairs.iter().map(Value::to_object).collect();
+                    let h = build_hash(&pairs)?;
+                    self.push(Value::from(h))?;
+                },
+                Opcode::OpGetBuiltin => {
+                    let idx = self.read_u8_operand();
+                    let (_, builtin) = BUILTINS[idx];
+                    self.push(Value::from(Object::Builtin(builtin)))?;
+                },
+                Opcode::OpIndex => self.execute_index()?,
+                Opcode::OpClosure => {
+                    let const_idx = self.read_u16_operand();
+                    let num_free = self.read_u8_operand();
+                    self.push_closure(const_idx, num_free)?
+                },
+                Opcode::OpCall => {
+                    let num_args = self.read_u8_operand();
+                    self.call_function(num_args)?
+                },
+                Opcode::OpReturnValue => {
+                    let value = self.pop()?;
+                    if self.frames.len() == 1 {
+                        // A top level return ends the program with its value
+                        self.stack[self.sp] = value;
+                        return Ok(());
+                    }
+                    self.return_from_function(value);
+                },
+                Opcode::OpReturn => self.return_from_function(Value::Null)
             }
         }
         Ok(())
     }
 
 
-    pub fn stack_top(&self) -> Option<&Object> {
-        let rc = self.stack.last()?;
-        return Some(rc.as_ref());
+    // The source line of the instruction running in the innermost frame, for
+    // reporting where an error happened. Programs that were not parsed have none.
+    pub fn current_line(&self) -> Option<usize> {
+        let frame = self.frames.last()?;
+        let (_, line) = frame.cl.func.lines.iter().take_while(|(offset, _)| *offset < frame.ip).last()?;
+        return Some(*line);
+    }
+
+    pub fn stack_top(&self) -> Option<Object> {
+        if self.sp == 0 {
+            return None;
+        }
+        return Some(self.stack[self.sp - 1].to_object());
+    }
+
+
+
+    // The slot just above the stack pointer, which holds the value of the last expression statement
+    pub fn last_popped_stack_elem(&self) -> Object {
+        return match self.stack.get(self.sp) {
+            Some(value) => value.to_object(),
+            None => Object::Null
+        }
+    }
+
+}
+
+// Shared with the register VM, so that both machines agree on every result and error
+pub(crate) fn take_fuel(fuel: &mut Option<u64>) -> Result<(), Box<dyn Error>> {
+    match fuel {
+        Some(0) => return Err(Box::new(BudgetExhausted)),
+        Some(n) => *n -= 1,
+        None => {}
+    }
+    Ok(())
+}
+
+// Builtins report errors as objects, this keeps the typed ones distinct
+pub(crate) fn builtin_error(msg: String) -> Box<dyn Error> {
+    if msg == memory::MemoryLimitExceeded.to_string() {
+        return Box::new(memory::MemoryLimitExceeded);
     }
+    return msg.into();
+}
 
+pub(crate) fn is_truthy(obj: &Object) -> bool {
+    match obj {
+        Object::Null => false,
+        Object::Boolean(x) => *x,
+        _ => true
+    }
+}
 
-    
-    pub fn last_popped(&self) -> Option<&Object> {
-        let rc = self.last_popped.as_ref()?;
-        return Some(rc.as_ref());
+fn value_is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Boolean(x) => *x,
+        _ => true
     }
+}
+
+// The unboxed fast path of binary_operation for two integers
+fn integer_operation(op: Opcode, x: i64, y: i64) -> Result<Value, Box<dyn Error>> {
+    let res = match op {
+        Opcode::OpAdd => Value::Integer(x.wrapping_add(y)),
+        Opcode::OpSubtract => Value::Integer(x.wrapping_sub(y)),
+        Opcode::OpMultiply => Value::Integer(x.wrapping_mul(y)),
+        Opcode::OpDivide if y == 0 => return Err("division by zero".into()),
+        Opcode::OpDivide => Value::Integer(x.wrapping_div(y)),
+        Opcode::OpEqual => Value::Boolean(x == y),
+        Opcode::OpNotEqual => Value::Boolean(x != y),
+        Opcode::OpGreaterThan => Value::Boolean(x > y),
+        Opcode::OpLessThan => Value::Boolean(x < y),
+        _ => return Err("unknown operator".into())
+    };
+    return Ok(res);
+}
+
+pub(crate) fn binary_operation(op: Opcode, left: &Object, right: &Object) -> Result<Object, Box<dyn Error>> {
+    let res = match (left, right) {
+        (Object::Integer(x), Object::Integer(y)) => match op {
+            Opcode::OpAdd => Object::Integer(x.wrapping_add(*y)),
+            Opcode::OpSubtract => Object::Integer(x.wrapping_sub(*y)),
+            Opcode::OpMultiply => Object::Integer(x.wrapping_mul(*y)),
+            Opcode::OpDivide if *y == 0 => return Err("division by zero".into()),
+            Opcode::OpDivide => Object::Integer(x.wrapping_div(*y)),
+            Opcode::OpEqual => Object::Boolean(x == y),
+            Opcode::OpNotEqual => Object::Boolean(x != y),
+            Opcode::OpGreaterThan => Object::Boolean(x > y),
+            Opcode::OpLessThan => Object::Boolean(x < y),
+            _ => return Err("unknown operator".into())
+        },
+        (Object::Boolean(x), Object::Boolean(y)) => match op {
+            Opcode::OpEqual => Object::Boolean(x == y),
+            Opcode::OpNotEqual => Object::Boolean(x != y),
+            _ => return Err("unknown operator".into())
+        },
+        (Object::Str(x), Object::Str(y)) => match op {
+            Opcode::OpAdd => {
+                memory::allocate(memory::string_size(x.len() + y.len()))?;
+                Object::Str(Rc::new(format!("{}{}", x, y)))
+            },
+            Opcode::OpEqual => Object::Boolean(x == y),
+            Opcode::OpNotEqual => Object::Boolean(x != y),
+            _ => return Err("unknown operator".into())
+        },
+        _ => return Err("type mismatch".into())
+    };
+    return Ok(res);
+}
 
+pub(crate) fn index_operation(left: &Object, index: &Object) -> Result<Object, Box<dyn Error>> {
+    let res = match (left, index) {
+        (Object::Array(arr), Object::Integer(idx)) => {
+            match arr.get(*idx as usize) {
+                Some(obj) if *idx >= 0 => obj.clone(),
+                _ => Object::Null
+            }
+        },
+        (Object::Array(_), _) => return Err("Index is not an integer".into()),
+        (Object::Hash(h), key) => match h.get(key) {
+            Some(obj) => obj.clone(),
+            None => return Err("Not found in map".into())
+        },
+        _ => return Err("Object is not indexable".into())
+    };
+    return Ok(res);
 }
 
+// Builds a hash from alternating keys and values
+#[allow(clippy::mutable_key_type)]
+pub(crate) fn build_hash(pairs: &[Object]) -> Result<Object, Box<dyn Error>> {
+    memory::allocate(memory::hash_size(pairs.len() / 2))?;
+    let mut h = HashMap::new();
+    for pair in pairs.chunks(2) {
+        let key = match &pair[0] {
+            Object::Integer(_) | Object::Boolean(_) | Object::Str(_) => pair[0].clone(),
+            _ => return Err("invalid key object for hash".into())
+        };
+        h.insert(key, pair[1].clone());
+    }
+    return Ok(Object::Hash(h));
+}
@@ -0,0 +1,63 @@
+use crate::evaluator::object::{Object, CompiledClosure};
+use std::borrow::Cow;
+use std::fmt;
+use std::rc::Rc;
+
+// What the VM keeps on its stack and in globals. Integers, booleans and null
+// are stored inline, so arithmetic and comparisons never allocate; closures
+// and all other objects are shared behind a reference count.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Boolean(bool),
+    Null,
+    Closure(Rc<CompiledClosure>),
+    Object(Rc<Object>)
+}
+
+impl Value {
+    // Borrows heap objects, only building the immediates
+    pub fn as_object(&self) -> Cow<'_, Object> {
+        return match self {
+            Value::Integer(x) => Cow::Owned(Object::Integer(*x)),
+            Value::Boolean(x) => Cow::Owned(Object::Boolean(*x)),
+            Value::Null => Cow::Owned(Object::Null),
+            Value::Closure(cl) => Cow::Owned(Object::CompiledClosure(Rc::clone(cl))),
+            Value::Object(obj) => Cow::Borrowed(obj.as_ref())
+        }
+    }
+
+    pub fn to_object(&self) -> Object {
+        return self.as_object().into_owned();
+    }
+}
+
+impl From<Object> for Value {
+    fn from(obj: Object) -> Self {
+        return match obj {
+            Object::Integer(x) => Value::Integer(x),
+            Object::Boolean(x) => Value::Boolean(x),
+            Object::Null => Value::Null,
+            Object::CompiledClosure(cl) => Value::Closure(cl),
+            obj => Value::Object(Rc::new(obj))
+        }
+    }
+}
+
+impl From<&Rc<Object>> for Value {
+    fn from(obj: &Rc<Object>) -> Self {
+        return match obj.as_ref() {
+            Object::Integer(x) => Value::Integer(*x),
+            Object::Boolean(x) => Value::Boolean(*x),
+            Object::Null => Value::Null,
+            Object::CompiledClosure(cl) => Value::Closure(Rc::clone(cl)),
+            _ => Value::Object(Rc::clone(obj))
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_object())
+    }
+}
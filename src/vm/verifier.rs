@@ -0,0 +1,270 @@
+use crate::code::{self, Opcode};
+use crate::compiler::ByteCode;
+use crate::evaluator::builtins::BUILTINS;
+use crate::evaluator::object::Object;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// Checks that bytecode is well formed before the VM runs it, so that a
+// corrupted or hand written program fails with an error instead of a panic.
+// The main program and every compiled function in the constant pool are
+// checked for:
+//  - instructions that decode, with operands in range for the constant pool,
+//    builtins, locals, free variables and globals
+//  - jumps that land on an instruction boundary
+//  - a stack depth that never underflows and agrees wherever control flow meets
+//  - functions that end every path with a return
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    // Constant index of the function the error is in, None for the main program
+    pub function: Option<usize>,
+    pub offset: usize,
+    pub kind: VerifyErrorKind
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    UnknownOpcode(u8),
+    TruncatedInstruction(Opcode),
+    ConstantOutOfRange(usize),
+    NotAFunction(usize),
+    BuiltinOutOfRange(usize),
+    LocalOutOfRange(usize),
+    FreeOutOfRange(usize),
+    GlobalNeverSet(usize),
+    JumpOutOfRange(usize),
+    JumpIntoInstruction(usize),
+    StackUnderflow(Opcode),
+    StackMismatch{ expected: usize, found: usize },
+    // The same function is closed over different numbers of free variables
+    FreeCountMismatch{ function: usize, expected: usize, found: usize },
+    MoreParamsThanLocals{ params: usize, locals: usize },
+    ReturnOutsideFunction,
+    MissingReturn
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.function {
+            Some(idx) => write!(f, "invalid bytecode in fn {:04} at {:04}: ", idx, self.offset)?,
+            None => write!(f, "invalid bytecode in main at {:04}: ", self.offset)?
+        }
+
+        match &self.kind {
+            VerifyErrorKind::UnknownOpcode(byte) => write!(f, "unknown opcode {}", byte),
+            VerifyErrorKind::TruncatedInstruction(op) => write!(f, "{:?} is missing operands", op),
+            VerifyErrorKind::ConstantOutOfRange(idx) => write!(f, "constant {} out of range", idx),
+            VerifyErrorKind::NotAFunction(idx) => write!(f, "constant {} is not a function", idx),
+            VerifyErrorKind::BuiltinOutOfRange(idx) => write!(f, "builtin {} out of range", idx),
+            VerifyErrorKind::LocalOutOfRange(idx) => write!(f, "local {} out of range", idx),
+            VerifyErrorKind::FreeOutOfRange(idx) => write!(f, "free variable {} out of range", idx),
+            VerifyErrorKind::GlobalNeverSet(idx) => write!(f, "global {} is never set", idx),
+            VerifyErrorKind::JumpOutOfRange(target) => write!(f, "jump target {} out of range", target),
+            VerifyErrorKind::JumpIntoInstruction(target) => write!(f, "jump target {} is inside an instruction", target),
+            VerifyErrorKind::StackUnderflow(op) => write!(f, "{:?} would underflow the stack", op),
+            VerifyErrorKind::StackMismatch{ expected, found } => write!(f, "stack depth {} where {} was expected", found, expected),
+            VerifyErrorKind::FreeCountMismatch{ function, expected, found } =>
+                write!(f, "fn {:04} closed over {} free variables, elsewhere {}", function, found, expected),
+            VerifyErrorKind::MoreParamsThanLocals{ params, locals } => write!(f, "{} parameters but only {} locals", params, locals),
+            VerifyErrorKind::ReturnOutsideFunction => write!(f, "return without a value outside of a function"),
+            VerifyErrorKind::MissingReturn => write!(f, "function can end without returning")
+        }
+    }
+}
+
+impl Error for VerifyError {}
+
+struct Instruction {
+    offset: usize,
+    op: Opcode,
+    operands: Vec<usize>
+}
+
+// The main program or one of the compiled functions
+struct Unit<'a> {
+    function: Option<usize>,
+    instructions: &'a [u8],
+    num_locals: usize,
+    decoded: Vec<Instruction>
+}
+
+pub fn verify(bytecode: &ByteCode) -> Result<(), VerifyError> {
+    return verify_continuation(bytecode, 0);
+}
+
+// Verifies bytecode that runs after num_globals globals were set by earlier
+// input, as each line of a REPL session does
+pub fn verify_continuation(bytecode: &ByteCode, num_globals: usize) -> Result<(), VerifyError> {
+    let mut units = vec![Unit{ function: None, instructions: &bytecode.instructions, num_locals: 0, decoded: vec![] }];
+    for (idx, constant) in bytecode.constants.iter().enumerate() {
+        if let Object::CompiledFunction(func) = constant.as_ref() {
+            if func.num_params > func.num_locals {
+                let kind = VerifyErrorKind::MoreParamsThanLocals{ params: func.num_params, locals: func.num_locals };
+                return Err(VerifyError{ function: Some(idx), offset: 0, kind });
+            }
+            units.push(Unit{ function: Some(idx), instructions: &func.instructions, num_locals: func.num_locals, decoded: vec![] });
+        }
+    }
+
+    for unit in units.iter_mut() {
+        unit.decoded = decode(unit)?;
+    }
+
+    // Free variable counts come from the OpClosure instructions creating each function
+    let mut free_counts: HashMap<usize, usize> = HashMap::new();
+    let mut globals = num_globals;
+    for unit in &units {
+        for ins in &unit.decoded {
+            let err = |kind| VerifyError{ function: unit.function, offset: ins.offset, kind };
+            match ins.op {
+                Opcode::OpClosure => {
+                    let (idx, num_free) = (ins.operands[0], ins.operands[1]);
+                    match bytecode.constants.get(idx).map(|x| x.as_ref()) {
+                        Some(Object::CompiledFunction(_)) => {},
+                        Some(_) => return Err(err(VerifyErrorKind::NotAFunction(idx))),
+                        None => return Err(err(VerifyErrorKind::ConstantOutOfRange(idx)))
+                    }
+                    let expected = *free_counts.entry(idx).or_insert(num_free);
+                    if expected != num_free {
+                        return Err(err(VerifyErrorKind::FreeCountMismatch{ function: idx, expected, found: num_free }));
+                    }
+                },
+                Opcode::OpSetGlobal => globals = globals.max(ins.operands[0] + 1),
+                _ => {}
+            }
+        }
+    }
+
+    for unit in &units {
+        let num_free = unit.function.and_then(|idx| free_counts.get(&idx)).copied().unwrap_or(0);
+        check_operands(unit, bytecode.constants.len(), num_free, globals)?;
+        check_stack(unit)?;
+    }
+    Ok(())
+}
+
+fn decode(unit: &Unit) -> Result<Vec<Instruction>, VerifyError> {
+    let ins = unit.instructions;
+    let mut decoded = vec![];
+    let mut offset = 0;
+    while offset < ins.len() {
+        let err = |kind| VerifyError{ function: unit.function, offset, kind };
+        let op = match Opcode::from_byte(ins[offset]) {
+            Some(op) => op,
+            None => return Err(err(VerifyErrorKind::UnknownOpcode(ins[offset])))
+        };
+
+        let def = op.definition();
+        let width: usize = def.operand_widths.iter().sum();
+        if offset + 1 + width > ins.len() {
+            return Err(err(VerifyErrorKind::TruncatedInstruction(op)));
+        }
+
+        let (operands, read) = code::read_operands(def, &ins[offset + 1..]);
+        decoded.push(Instruction{ offset, op, operands });
+        offset += 1 + read;
+    }
+    return Ok(decoded);
+}
+
+fn check_operands(unit: &Unit, num_constants: usize, num_free: usize, num_globals: usize) -> Result<(), VerifyError> {
+    let boundaries: Vec<usize> = unit.decoded.iter().map(|ins| ins.offset).collect();
+    for ins in &unit.decoded {
+        let err = |kind| VerifyError{ function: unit.function, offset: ins.offset, kind };
+        let operand = ins.operands.first().copied().unwrap_or(0);
+        match ins.op {
+            Opcode::OpConstant | Opcode::OpAddConst | Opcode::OpSubConst | Opcode::OpConstantPop
+                if operand >= num_constants => return Err(err(VerifyErrorKind::ConstantOutOfRange(operand))),
+            Opcode::OpGetBuiltin if operand >= BUILTINS.len() => return Err(err(VerifyErrorKind::BuiltinOutOfRange(operand))),
+            Opcode::OpGetLocal | Opcode::OpSetLocal if operand >= unit.num_locals => return Err(err(VerifyErrorKind::LocalOutOfRange(operand))),
+            Opcode::OpGetLocalGetLocal => {
+                if let Some(idx) = ins.operands.iter().find(|idx| **idx >= unit.num_locals) {
+                    return Err(err(VerifyErrorKind::LocalOutOfRange(*idx)));
+                }
+            },
+            Opcode::OpGetFree if operand >= num_free => return Err(err(VerifyErrorKind::FreeOutOfRange(operand))),
+            Opcode::OpGetGlobal if operand >= num_globals => return Err(err(VerifyErrorKind::GlobalNeverSet(operand))),
+            Opcode::OpReturn if unit.function.is_none() => return Err(err(VerifyErrorKind::ReturnOutsideFunction)),
+            Opcode::OpJump | Opcode::OpJumpNotTruthy | Opcode::OpJumpNotGreater | Opcode::OpJumpNotLess => {
+                // The main program may jump to its very end, which halts the VM
+                let at_end = unit.function.is_none() && operand == unit.instructions.len();
+                if operand >= unit.instructions.len() && !at_end {
+                    return Err(err(VerifyErrorKind::JumpOutOfRange(operand)));
+                }
+                if !at_end && boundaries.binary_search(&operand).is_err() {
+                    return Err(err(VerifyErrorKind::JumpIntoInstruction(operand)));
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Values popped and pushed by an instruction
+fn stack_effect(ins: &Instruction) -> (usize, usize) {
+    let operand = ins.operands.first().copied().unwrap_or(0);
+    return match ins.op {
+        Opcode::OpConstant | Opcode::OpTrue | Opcode::OpFalse | Opcode::OpNull |
+        Opcode::OpGetGlobal | Opcode::OpGetLocal | Opcode::OpGetFree |
+        Opcode::OpCurrentClosure | Opcode::OpGetBuiltin => (0, 1),
+        Opcode::OpAdd | Opcode::OpSubtract | Opcode::OpMultiply | Opcode::OpDivide |
+        Opcode::OpEqual | Opcode::OpNotEqual | Opcode::OpGreaterThan | Opcode::OpLessThan | Opcode::OpIndex => (2, 1),
+        Opcode::OpMinus | Opcode::OpBang | Opcode::OpAddConst | Opcode::OpSubConst => (1, 1),
+        Opcode::OpGetLocalGetLocal => (0, 2),
+        Opcode::OpConstantPop => (0, 0),
+        Opcode::OpJumpNotGreater | Opcode::OpJumpNotLess => (2, 0),
+        Opcode::OpPop | Opcode::OpSetGlobal | Opcode::OpSetLocal |
+        Opcode::OpJumpNotTruthy | Opcode::OpReturnValue => (1, 0),
+        Opcode::OpJump | Opcode::OpReturn => (0, 0),
+        Opcode::OpArray => (operand, 1),
+        Opcode::OpHash => (2 * operand, 1),
+        Opcode::OpCall => (operand + 1, 1),
+        Opcode::OpClosure => (ins.operands[1], 1)
+    }
+}
+
+// Walks every path through the unit, tracking the stack depth at each instruction
+fn check_stack(unit: &Unit) -> Result<(), VerifyError> {
+    let index: HashMap<usize, usize> = unit.decoded.iter().enumerate().map(|(i, ins)| (ins.offset, i)).collect();
+    let mut depths: Vec<Option<usize>> = vec![None; unit.decoded.len()];
+    let mut pending = vec![(0, 0)];
+
+    while let Some((i, depth)) = pending.pop() {
+        if i == unit.decoded.len() {
+            if unit.function.is_some() {
+                let offset = unit.instructions.len();
+                return Err(VerifyError{ function: unit.function, offset, kind: VerifyErrorKind::MissingReturn });
+            }
+            continue;
+        }
+
+        let ins = &unit.decoded[i];
+        let err = |kind| VerifyError{ function: unit.function, offset: ins.offset, kind };
+        match depths[i] {
+            Some(expected) if expected != depth => return Err(err(VerifyErrorKind::StackMismatch{ expected, found: depth })),
+            Some(_) => continue,
+            None => depths[i] = Some(depth)
+        }
+
+        let (pops, pushes) = stack_effect(ins);
+        if pops > depth {
+            return Err(err(VerifyErrorKind::StackUnderflow(ins.op)));
+        }
+        let depth = depth - pops + pushes;
+
+        let target = || index.get(&ins.operands[0]).copied().unwrap_or(unit.decoded.len());
+        match ins.op {
+            Opcode::OpReturnValue | Opcode::OpReturn => {},
+            Opcode::OpJump => pending.push((target(), depth)),
+            Opcode::OpJumpNotTruthy | Opcode::OpJumpNotGreater | Opcode::OpJumpNotLess => {
+                pending.push((target(), depth));
+                pending.push((i + 1, depth));
+            },
+            _ => pending.push((i + 1, depth))
+        }
+    }
+    Ok(())
+}
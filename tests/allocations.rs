@@ -0,0 +1,80 @@
+extern crate interpreter;
+use interpreter::compiler::{Compiler, OptLevel};
+use interpreter::parser::Parser;
+use interpreter::lexer::Lexer;
+use interpreter::evaluator::object::Object;
+use interpreter::vm::VM;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// Counts the allocations made by the current thread, so tests running in parallel do not interfere
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|x| x.set(x.get() + 1));
+        return System.alloc(layout);
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_integer_arithmetic_does_not_allocate() {
+    let programs = [
+        // A loop through tail recursion
+        "let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + n) } }; sum({n}, 0)",
+        // Comparisons, booleans and null
+        "let count = fn(n) { if (n > 0) { let x = !(n == 3) == true; if (x) { count(n - 1) } } }; count({n})",
+        // Arithmetic on globals
+        "let double = fn(x) { x * 2 - x + x / 1 }; let loop = fn(n) { if (n > 0) { double(n); loop(n - 1) } }; loop({n})"
+    ];
+
+    for opt_level in [OptLevel::O0, OptLevel::O2] {
+        for program in programs.iter() {
+            let small = count_allocations(&program.replace("{n}", "500"), opt_level);
+            let large = count_allocations(&program.replace("{n}", "1000"), opt_level);
+            // Only the stacks grow, so five hundred more calls cost a handful of reallocations
+            assert!(large - small < 50, "{} at {:?}: {} allocations for 500 iterations, {} for 1000", program, opt_level, small, large);
+        }
+    }
+}
+
+#[test]
+fn test_heap_objects_still_work() {
+    let input = r#"let xs = [1, "two", fn(x) { x }]; let h = {1: xs, true: "yes"}; [h[1][2](len(xs[1])), h[true]]"#;
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = p.parse_program().unwrap();
+    let mut compiler = Compiler::new();
+    compiler.compile(program).unwrap();
+    let mut vm = VM::new(compiler.bytecode());
+    vm.run().unwrap();
+    assert_eq!(vm.last_popped_stack_elem().to_string(), "[3, yes]");
+}
+
+
+fn count_allocations(input: &str, opt_level: OptLevel) -> usize {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = p.parse_program().unwrap();
+    let mut compiler = Compiler::with_opt_level(opt_level);
+    compiler.compile(program).unwrap();
+    // Deep enough for a few thousand nested calls
+    let mut vm = VM::with_stack_size(compiler.bytecode(), 1 << 16);
+
+    let before = ALLOCATIONS.with(|x| x.get());
+    vm.run().unwrap();
+    let after = ALLOCATIONS.with(|x| x.get());
+    assert!(!matches!(vm.last_popped_stack_elem(), Object::Error(_)));
+    return after - before;
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::PathBuf;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+#[test]
+fn test_run_file() {
+    let path = script("run_file.mk", "let add = fn(a, b) { a + b };\nputs(add(1, 2));\nadd(3, 4)\n");
+    for args in [vec![], vec!["--run"], vec!["--vm=register"], vec!["-O0"]] {
+        let output = repl(&args, &path);
+        assert_eq!(output.status.code(), Some(0), "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n7\n", "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "", "{:?}", args);
+    }
+
+    // Nothing is printed for a script that ends without a value
+    let path = script("run_file_let.mk", "let x = 1;");
+    assert_eq!(String::from_utf8_lossy(&repl(&[], &path).stdout), "");
+}
+
+#[test]
+fn test_eval_option() {
+    let output = run(&["-e", "puts(\"hello\"); 1 + 2"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n3\n");
+
+    let output = run(&["-e", "1 +"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "-e:1:4: No prefix defined for EOF\n");
+}
+
+#[test]
+fn test_errors_exit_with_failure() {
+    let tests = vec![
+        ("parse_error.mk", "let x = 1;\nlet y = x +;\n", "parse_error.mk:2:12: No prefix defined for Semicolon\n"),
+        ("runtime_error.mk", "let f = fn(a) {\n  a + true\n};\nf(1);\n", "runtime_error.mk:2: type mismatch\n"),
+        ("unknown_identifier.mk", "\n\nfoo\n", "unknown_identifier.mk: unknown identifier: foo\n")
+    ];
+
+    for (name, source, expected) in tests {
+        let path = script(name, source);
+        let output = repl(&[], &path);
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.ends_with(expected), "{}: {}", name, stderr);
+    }
+
+    // The evaluator reports the innermost statement too
+    let path = script("eval_runtime_error.mk", "let f = fn(a) {\n  a + true\n};\nf(1);\n");
+    let output = repl(&["--engine=eval"], &path);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("eval_runtime_error.mk:2: type mismatch\n"), "{}", stderr);
+
+    let output = run(&["no_such_file.mk"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--no-such-option"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_limits_apply_to_scripts() {
+    let path = script("endless.mk", "let f = fn(n) { f(n + 1) }; f(0)");
+    let output = repl(&["--fuel=1000"], &path);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with("execution budget exhausted\n"));
+
+    let output = repl(&[], &path);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with("stack overflow\n"));
+}
+
+
+#[test]
+fn test_engine_option() {
+    let path = script("engine.mk", "let f = fn(n) { if (n < 2) { n } else { f(n - 1) + f(n - 2) } };\nputs(f(10));\nf(12)\n");
+    for args in [vec!["--engine=eval"], vec!["--engine=vm"], vec!["--engine=vm", "--vm=register"]] {
+        let output = repl(&args, &path);
+        assert_eq!(output.status.code(), Some(0), "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "55\n144\n", "{:?}", args);
+    }
+
+    let output = run(&["--engine=eval", "-e", "let x = 1;\nx + true"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "-e:2: type mismatch\n");
+
+    // A statement inside an expression has a line of its own
+    let source = "let b = if (true) {\n  1 + \"a\"\n} else { 2 } + 1;";
+    for engine in ["--engine=eval", "--engine=vm"] {
+        let output = run(&[engine, "-e", source]);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "-e:2: type mismatch\n", "{}", engine);
+    }
+
+    let output = run(&["--engine=eval", "--fuel=100", "-e", "let f = fn(n) { f(n + 1) }; f(0)"]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "-e:1: execution budget exhausted\n");
+
+    // Deep recursion is an error well before a generous budget runs out
+    let output = run(&["--engine=eval", "--fuel=1000000", "-e", "let f = fn(n) { f(n + 1) }; f(0)"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "-e:1: stack overflow\n");
+}
+
+#[test]
+fn test_multi_line_input() {
+    let tests = vec![
+        // Open braces and parentheses
+        ("let add = fn(a,\nb) {\n  a + b\n};\nadd(1,\n2)\n", ">> ... ... ... >> ... 3\n>> \n"),
+        // Trailing operators
+        ("let x =\n1 +\n2;\nx\n", ">> ... ... >> 3\n>> \n"),
+        // Strings left open
+        ("\"a\nb\"\n", ">> ... a\nb\n>> \n"),
+        // Quotes in comments open nothing
+        ("let x = 1; // \"one\nx\n", ">> >> 1\n>> \n"),
+        // Input cut off at the end is handed to the parser as it is
+        ("[1, 2", "expected next token to be RBracket, got EOF instead\n")
+    ];
+
+    for (input, expected) in tests {
+        let output = session(&[], input);
+        assert_eq!(output.status.code(), Some(0), "{:?}", input);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.ends_with(expected), "{:?}: {:?}", input, stdout);
+    }
+
+    // An empty line gives up on the input
+    let output = session(&[], "1 +\n\n2\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("No prefix defined for EOF"), "{}", stdout);
+    assert!(stdout.ends_with(">> 2\n>> \n"), "{}", stdout);
+}
+
+#[test]
+fn test_end_of_input_exits() {
+    for args in [vec![], vec!["--engine=eval"], vec!["--vm=register"]] {
+        let output = session(&args, "let x = 5;\nx * 2\n");
+        assert_eq!(output.status.code(), Some(0), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stdout).ends_with(">> >> 10\n>> \n"), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_meta_commands() {
+    let output = session(&[], ":tokens let x = 1;\n:ast x\n:bytecode 1 + 2\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1:1\tLet\n1:5\tIdent(\"x\")\n1:7\tAssign\n1:9\tInt(1)\n1:10\tSemicolon\n"), "{}", stdout);
+    assert!(stdout.contains("IdentExpr(\n"), "{}", stdout);
+    assert!(stdout.contains("OpConstant"), "{}", stdout);
+
+    // The session's bindings survive a load and go away on a reset, in every engine
+    let path = script("load.mk", "let x = 2;\nlet double = fn(a) { a * x };\n");
+    let input = format!(":load {}\nlet y = double(3);\n:env\n:reset\n:env\ny\n", path);
+    for args in [vec![], vec!["--engine=eval"], vec!["--vm=register"]] {
+        let output = session(&args, &input);
+        assert_eq!(output.status.code(), Some(0), "{:?}", args);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("x = 2\n"), "{:?}: {}", args, stdout);
+        assert!(stdout.contains("y = 6\n"), "{:?}: {}", args, stdout);
+        assert!(stdout.contains("double = "), "{:?}: {}", args, stdout);
+        assert!(stdout.ends_with(">> >> >> unknown identifier: y\n>> \n"), "{:?}: {}", args, stdout);
+    }
+
+    let output = session(&[], ":load no_such_file.mk\n:frobnicate\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("could not read no_such_file.mk"), "{}", stdout);
+    assert!(stdout.contains("unknown command :frobnicate"), "{}", stdout);
+}
+
+#[test]
+fn test_dump_ast() {
+    let path = script("dump_ast.mk", "let x = 1;\nputs(x)\n");
+    let output = repl(&["--dump-ast=json"], &path);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), concat!(
+        "{\"version\":1,\"body\":[",
+        "{\"type\":\"Let\",\"span\":{\"start\":{\"line\":1,\"column\":1},\"end\":{\"line\":1,\"column\":10}},",
+        "\"name\":\"x\",\"value\":{\"type\":\"Integer\",\"value\":1}},",
+        "{\"type\":\"Expression\",\"span\":{\"start\":{\"line\":2,\"column\":1},\"end\":{\"line\":2,\"column\":7}},",
+        "\"expression\":{\"type\":\"Call\",\"function\":{\"type\":\"Identifier\",\"name\":\"puts\"},",
+        "\"arguments\":[{\"type\":\"Identifier\",\"name\":\"x\"}]}}]}\n"
+    ));
+
+    // Nothing is run
+    let output = run(&["--dump-ast=json", "-e", "puts(1)"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("{\"version\":1,"));
+
+    let output = run(&["--dump-ast=json", "-e", "1 +"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "-e:1:4: No prefix defined for EOF\n");
+
+    let output = run(&["--dump-ast=xml", &path]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--dump-ast=json"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&["--dump-ast=json", "--disassemble", &path]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+fn script(name: &str, source: &str) -> String {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push(name);
+    fs::write(&path, source).unwrap();
+    return path.to_str().unwrap().to_string();
+}
+
+fn repl(args: &[&str], path: &str) -> Output {
+    let mut args = args.to_vec();
+    args.push(path);
+    return run(&args);
+}
+
+// Runs the REPL with input typed in, up to the end of it
+fn session(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_repl")).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    return child.wait_with_output().unwrap();
+}
+
+fn run(args: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_repl")).args(args).output().unwrap();
+}
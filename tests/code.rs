@@ -0,0 +1,191 @@
+extern crate interpreter;
+use interpreter::code::{Opcode, Instructions, make, read_operands, lookup, disassemble, peephole, DEFINITIONS};
+
+#[test]
+fn test_make() {
+    let tests: Vec<(Opcode, Vec<usize>, Vec<u8>)> = vec![
+        (Opcode::OpConstant, vec![65534], vec![Opcode::OpConstant as u8, 255, 254]),
+        (Opcode::OpAdd, vec![], vec![Opcode::OpAdd as u8]),
+        (Opcode::OpGetLocal, vec![255], vec![Opcode::OpGetLocal as u8, 255]),
+        (Opcode::OpClosure, vec![65534, 255], vec![Opcode::OpClosure as u8, 255, 254, 255])
+    ];
+
+    for (op, operands, expected) in tests {
+        assert_eq!(expected, make(op, &operands));
+    }
+}
+
+#[test]
+fn test_read_operands() {
+    let tests: Vec<(Opcode, Vec<usize>, usize)> = vec![
+        (Opcode::OpConstant, vec![65535], 2),
+        (Opcode::OpGetLocal, vec![255], 1),
+        (Opcode::OpClosure, vec![65535, 255], 3)
+    ];
+
+    for (op, operands, bytes_read) in tests {
+        let instruction = make(op, &operands);
+        let def = lookup(instruction[0]).unwrap();
+        assert_eq!((operands, bytes_read), read_operands(def, &instruction[1..]));
+    }
+}
+
+#[test]
+fn test_definitions() {
+    for (byte, def) in DEFINITIONS.iter().enumerate() {
+        let op = Opcode::from_byte(byte as u8).unwrap();
+        assert_eq!(format!("{:?}", op), def.name);
+        assert_eq!(op.definition().operand_widths, def.operand_widths);
+    }
+    assert_eq!(None, Opcode::from_byte(DEFINITIONS.len() as u8));
+}
+
+#[test]
+fn test_disassemble() {
+    let instructions = concat(vec![
+        make(Opcode::OpAdd, &[]),
+        make(Opcode::OpGetLocal, &[1]),
+        make(Opcode::OpConstant, &[2]),
+        make(Opcode::OpConstant, &[65535]),
+        make(Opcode::OpClosure, &[65535, 255])
+    ]);
+
+    let expected = "0000 OpAdd
+0001 OpGetLocal 1
+0003 OpConstant 2
+0006 OpConstant 65535
+0009 OpClosure 65535 255
+";
+    assert_eq!(expected, disassemble(&instructions));
+}
+
+#[test]
+fn test_disassemble_malformed() {
+    assert_eq!("0000 ERROR: unknown opcode 255\n", disassemble(&[255]));
+    assert_eq!("0000 ERROR: OpConstant is missing operands\n", disassemble(&[Opcode::OpConstant as u8, 1]));
+}
+
+#[test]
+fn test_peephole_fusion() {
+    let tests = vec![
+        (vec![make(Opcode::OpGetLocal, &[0]), make(Opcode::OpGetLocal, &[2])], vec![make(Opcode::OpGetLocalGetLocal, &[0, 2])]),
+        (vec![make(Opcode::OpConstant, &[7]), make(Opcode::OpAdd, &[])], vec![make(Opcode::OpAddConst, &[7])]),
+        (vec![make(Opcode::OpConstant, &[7]), make(Opcode::OpSubtract, &[])], vec![make(Opcode::OpSubConst, &[7])]),
+        (vec![make(Opcode::OpConstant, &[7]), make(Opcode::OpPop, &[])], vec![make(Opcode::OpConstantPop, &[7])]),
+        (vec![make(Opcode::OpLessThan, &[]), make(Opcode::OpJumpNotTruthy, &[4])], vec![make(Opcode::OpJumpNotLess, &[3])]),
+        (
+            vec![make(Opcode::OpGetLocal, &[0]), make(Opcode::OpGetLocal, &[1]), make(Opcode::OpGetLocal, &[2])],
+            vec![make(Opcode::OpGetLocalGetLocal, &[0, 1]), make(Opcode::OpGetLocal, &[2])]
+        ),
+        (
+            vec![make(Opcode::OpConstant, &[1]), make(Opcode::OpMultiply, &[])],
+            vec![make(Opcode::OpConstant, &[1]), make(Opcode::OpMultiply, &[])]
+        )
+    ];
+
+    for (input, expected) in tests {
+        let (output, _) = peephole::optimize(&concat(input), &vec![]);
+        assert_eq!(disassemble(&concat(expected)), disassemble(&output));
+    }
+}
+
+#[test]
+fn test_peephole_jumps() {
+    let input = concat(vec![
+        // 0000
+        make(Opcode::OpGetLocal, &[0]),
+        // 0002
+        make(Opcode::OpConstant, &[0]),
+        // 0005
+        make(Opcode::OpGreaterThan, &[]),
+        // 0006
+        make(Opcode::OpJumpNotTruthy, &[18]),
+        // 0009
+        make(Opcode::OpGetLocal, &[0]),
+        // 0011
+        make(Opcode::OpConstant, &[1]),
+        // 0014
+        make(Opcode::OpAdd, &[]),
+        // 0015
+        make(Opcode::OpJump, &[20]),
+        // 0018
+        make(Opcode::OpGetLocal, &[1]),
+        // 0020
+        make(Opcode::OpReturnValue, &[])
+    ]);
+    let expected = concat(vec![
+        make(Opcode::OpGetLocal, &[0]),
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpJumpNotGreater, &[16]),
+        make(Opcode::OpGetLocal, &[0]),
+        make(Opcode::OpAddConst, &[1]),
+        make(Opcode::OpJump, &[18]),
+        make(Opcode::OpGetLocal, &[1]),
+        make(Opcode::OpReturnValue, &[])
+    ]);
+
+    let (output, lines) = peephole::optimize(&input, &vec![(0, 1), (9, 2), (18, 3)]);
+    assert_eq!(disassemble(&expected), disassemble(&output));
+    assert_eq!(vec![(0, 1), (8, 2), (16, 3)], lines);
+}
+
+#[test]
+fn test_peephole_lines_of_fused_pairs() {
+    // The first OpGetLocal ends line 1 and the second starts line 2
+    let input = concat(vec![
+        // 0000
+        make(Opcode::OpGetLocal, &[0]),
+        // 0002
+        make(Opcode::OpGetLocal, &[0]),
+        // 0004
+        make(Opcode::OpAdd, &[]),
+        // 0005
+        make(Opcode::OpReturnValue, &[])
+    ]);
+
+    let expected = concat(vec![
+        make(Opcode::OpGetLocalGetLocal, &[0, 0]),
+        make(Opcode::OpAdd, &[]),
+        make(Opcode::OpReturnValue, &[])
+    ]);
+
+    let (output, lines) = peephole::optimize(&input, &vec![(0, 1), (2, 2)]);
+    assert_eq!(disassemble(&expected), disassemble(&output));
+    assert_eq!(vec![(0, 1), (3, 2)], lines);
+
+    // Nothing is left for the second line when the pair ends the code
+    let input = concat(vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]);
+    let (_, lines) = peephole::optimize(&input, &vec![(0, 1), (3, 2)]);
+    assert_eq!(vec![(0, 1)], lines);
+}
+
+#[test]
+fn test_peephole_keeps_jump_targets() {
+    // The second OpGetLocal is a jump target, so the pair must stay apart
+    let input = concat(vec![
+        // 0000
+        make(Opcode::OpJump, &[5]),
+        // 0003
+        make(Opcode::OpGetLocal, &[0]),
+        // 0005
+        make(Opcode::OpGetLocal, &[1]),
+        // 0007
+        make(Opcode::OpConstant, &[0]),
+        // 0010
+        make(Opcode::OpPop, &[])
+    ]);
+    let expected = concat(vec![
+        make(Opcode::OpJump, &[5]),
+        make(Opcode::OpGetLocal, &[0]),
+        make(Opcode::OpGetLocal, &[1]),
+        make(Opcode::OpConstantPop, &[0])
+    ]);
+
+    let (output, _) = peephole::optimize(&input, &vec![]);
+    assert_eq!(disassemble(&expected), disassemble(&output));
+}
+
+
+fn concat(instructions: Vec<Instructions>) -> Instructions {
+    return instructions.into_iter().flatten().collect();
+}
@@ -1,18 +1,19 @@
 extern crate interpreter;
-use interpreter::compiler::Compiler;
-use interpreter::code::Instruction;
-use interpreter::parser::ast::Program;
+use interpreter::compiler::{Compiler, ByteCode, disassembler};
+use interpreter::code::{Opcode, Instructions, make};
+use interpreter::parser::ast::*;
 use interpreter::parser::Parser;
 use interpreter::lexer::Lexer;
-use interpreter::evaluator::object::Object;
+use interpreter::evaluator::object::{Object, CompiledFunction};
+use std::rc::Rc;
 
 #[test]
 fn test_integer_arithmetic() {
     let input = "1+2";
     let expected_constants = vec![Object::Integer(1),Object::Integer(2)];
     let expected_instructions = vec![
-        Instruction::OpConstant(0),
-        Instruction::OpConstant(1)
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpConstant, &[1])
     ];
 
     
@@ -22,7 +23,223 @@ fn test_integer_arithmetic() {
 
 }
 
-fn test_run_vm(input: &str, expected_constants: Vec<Object>, expected_instructions: Vec<Instruction>) {
+#[test]
+fn test_conditionals() {
+    let input = "if (true) { 10 }; 3333;";
+    let expected_constants = vec![Object::Integer(10), Object::Integer(3333)];
+    let expected_instructions = vec![
+        // 0000
+        make(Opcode::OpTrue, &[]),
+        // 0001
+        make(Opcode::OpJumpNotTruthy, &[10]),
+        // 0004
+        make(Opcode::OpConstant, &[0]),
+        // 0007
+        make(Opcode::OpJump, &[11]),
+        // 0010
+        make(Opcode::OpNull, &[]),
+        // 0011
+        make(Opcode::OpPop, &[]),
+        // 0012
+        make(Opcode::OpConstant, &[1]),
+        // 0015
+        make(Opcode::OpPop, &[])
+    ];
+
+    test_run_vm(input, expected_constants, expected_instructions);
+}
+
+#[test]
+fn test_comparisons() {
+    let input = "let a = 1; let b = 2; a < b";
+    let expected_constants = vec![Object::Integer(1), Object::Integer(2)];
+    let expected_instructions = vec![
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpSetGlobal, &[0]),
+        make(Opcode::OpConstant, &[1]),
+        make(Opcode::OpSetGlobal, &[1]),
+        make(Opcode::OpGetGlobal, &[0]),
+        make(Opcode::OpGetGlobal, &[1]),
+        make(Opcode::OpLessThan, &[]),
+        make(Opcode::OpPop, &[])
+    ];
+
+    test_run_vm(input, expected_constants, expected_instructions);
+}
+
+#[test]
+fn test_closures() {
+    let input = "fn(a) { fn(b) { a + b } }";
+    let inner = CompiledFunction{
+        instructions: concat(vec![
+            make(Opcode::OpGetFree, &[0]),
+            make(Opcode::OpGetLocal, &[0]),
+            make(Opcode::OpAdd, &[]),
+            make(Opcode::OpReturnValue, &[])
+        ]),
+        num_locals: 1,
+        num_params: 1,
+        lines: vec![(0, 1)]
+    };
+    let outer = CompiledFunction{
+        instructions: concat(vec![
+            make(Opcode::OpGetLocal, &[0]),
+            make(Opcode::OpClosure, &[0, 1]),
+            make(Opcode::OpReturnValue, &[])
+        ]),
+        num_locals: 1,
+        num_params: 1,
+        lines: vec![(0, 1)]
+    };
+    let expected_constants = vec![
+        Object::CompiledFunction(Rc::new(inner)),
+        Object::CompiledFunction(Rc::new(outer))
+    ];
+    let expected_instructions = vec![
+        make(Opcode::OpClosure, &[1, 0]),
+        make(Opcode::OpPop, &[])
+    ];
+
+    test_run_vm(input, expected_constants, expected_instructions);
+}
+
+#[test]
+fn test_recursive_functions() {
+    let input = "let countDown = fn(x) { countDown(x - 1); }; countDown(1);";
+    let count_down = CompiledFunction{
+        instructions: concat(vec![
+            make(Opcode::OpCurrentClosure, &[]),
+            make(Opcode::OpGetLocal, &[0]),
+            make(Opcode::OpConstant, &[0]),
+            make(Opcode::OpSubtract, &[]),
+            make(Opcode::OpCall, &[1]),
+            make(Opcode::OpReturnValue, &[])
+        ]),
+        num_locals: 1,
+        num_params: 1,
+        lines: vec![(0, 1)]
+    };
+    let expected_constants = vec![
+        Object::Integer(1),
+        Object::CompiledFunction(Rc::new(count_down)),
+        Object::Integer(1)
+    ];
+    let expected_instructions = vec![
+        make(Opcode::OpClosure, &[1, 0]),
+        make(Opcode::OpSetGlobal, &[0]),
+        make(Opcode::OpGetGlobal, &[0]),
+        make(Opcode::OpConstant, &[2]),
+        make(Opcode::OpCall, &[1]),
+        make(Opcode::OpPop, &[])
+    ];
+
+    test_run_vm(input, expected_constants, expected_instructions);
+}
+
+#[test]
+fn test_line_tables() {
+    let input = "let a = 1;
+let f = fn(x) {
+  let y = x;
+  y * a
+};
+f(2);";
+    let bytecode = compile(input);
+    assert_eq!(vec![(0, 1), (6, 2), (13, 6)], bytecode.lines);
+
+    match bytecode.constants[1].as_ref() {
+        Object::CompiledFunction(func) => assert_eq!(vec![(0, 3), (4, 4)], func.lines),
+        x => panic!("expected a compiled function, got {}", x)
+    }
+}
+
+#[test]
+fn test_disassembler_listing() {
+    let input = "let add = fn(a, b) {
+  a + b
+};
+add(1, \"two\");";
+    let expected = "== main ==
+     ; line 1: let add = fn(a, b) {
+0000 OpClosure 0 0
+0004 OpSetGlobal 0
+     ; line 4: add(1, \"two\");
+0007 OpGetGlobal 0
+0010 OpConstant 1
+0013 OpConstant 2
+0016 OpCall 2
+0018 OpPop
+
+== constants ==
+0000 CompiledFunction params=2 locals=2
+0001 Integer 1
+0002 String \"two\"
+
+== fn 0000 (params=2, locals=2) ==
+     ; line 2: a + b
+0000 OpGetLocal 0
+0002 OpGetLocal 1
+0004 OpAdd
+0005 OpReturnValue
+";
+    let bytecode = compile(input);
+    assert_eq!(expected, disassembler::disassemble(&bytecode, Some(input)));
+
+    // Without the source only the line numbers are shown
+    let listing = bytecode.to_string();
+    assert!(listing.contains("     ; line 4\n0007 OpGetGlobal 0\n"), "{}", listing);
+
+    // Lines that are not in the source are shown as numbers too
+    let mut bytecode = compile("1");
+    bytecode.lines = vec![(0, 0)];
+    assert_eq!("== main ==\n     ; line 0\n0000 OpConstant 0\n0003 OpPop\n\n== constants ==\n0000 Integer 1\n", disassembler::disassemble(&bytecode, Some("1")));
+}
+
+#[test]
+fn test_operand_limits() {
+    // Built as trees, since sources this long take the lexer a while
+    let truth = || Expr::LiteralExpr(Literal::Bool(true));
+    let function = |body: Block| Expr::Fn(Rc::new(vec![]), Rc::new(body));
+    let constants = |n| vec![Stmt::ExprStmt(Expr::LiteralExpr(Literal::Int(1)), Location::default()); n];
+    let globals = |n| vec![Stmt::LetStmt(Ident("g".to_string()), truth(), Location::default()); n];
+    let locals = |n| vec![Stmt::ExprStmt(function(vec![Stmt::LetStmt(Ident("l".to_string()), truth(), Location::default()); n]), Location::default())];
+    let arguments = |n| vec![Stmt::ExprStmt(Expr::Call(Box::new(Expr::IdentExpr(Ident("len".to_string()))), vec![truth(); n]), Location::default())];
+    let free = |n| {
+        let names: Vec<Ident> = (0..n).map(|i| Ident(format!("x{}", i))).collect();
+        let mut body: Block = names.iter().map(|x| Stmt::LetStmt(x.clone(), truth(), Location::default())).collect();
+        let inner = Expr::Array(names.into_iter().map(Expr::IdentExpr).collect());
+        body.push(Stmt::ExprStmt(function(vec![Stmt::ExprStmt(inner, Location::default())]), Location::default()));
+        vec![Stmt::ExprStmt(function(body), Location::default())]
+    };
+    let elements = |n| vec![Stmt::ExprStmt(Expr::Array(vec![truth(); n]), Location::default())];
+    let pairs = |n| vec![Stmt::ExprStmt(Expr::LiteralExpr(Literal::Hash(vec![(truth(), truth()); n])), Location::default())];
+    // Two bytes per statement, and a jump past the last one to the end of the alternative
+    let jumps = |n| vec![Stmt::ExprStmt(Expr::If(Box::new(truth()), vec![Stmt::ExprStmt(truth(), Location::default()); n], None), Location::default())];
+
+    // Each program at the limit, then one past it
+    let tests = vec![
+        (constants(65536), constants(65537), "too many constants: 65536 does not fit in OpConstant, which takes at most 65535"),
+        (globals(65536), globals(65537), "too many global bindings: 65536 does not fit in OpSetGlobal, which takes at most 65535"),
+        (locals(256), locals(257), "too many local bindings in one function: 256 does not fit in OpSetLocal, which takes at most 255"),
+        (arguments(255), arguments(256), "too many arguments in one call: 256 does not fit in OpCall, which takes at most 255"),
+        (free(255), free(256), "too many free variables in one function: 256 does not fit in OpClosure, which takes at most 255"),
+        (elements(65535), elements(65536), "too many elements in an array literal: 65536 does not fit in OpArray, which takes at most 65535"),
+        (pairs(65535), pairs(65536), "too many pairs in a hash literal: 65536 does not fit in OpHash, which takes at most 65535"),
+        (jumps(32764), jumps(32765), "too many bytes of code in one function: 65536 does not fit in OpJumpNotTruthy, which takes at most 65535")
+    ];
+
+    for (at_limit, past_limit, expected) in tests {
+        if let Err(x) = Compiler::new().compile(at_limit) {
+            panic!("{} at the limit: {}", expected, x)
+        }
+        match Compiler::new().compile(past_limit) {
+            Ok(_) => panic!("expected {}", expected),
+            Err(x) => assert_eq!(x.to_string(), expected)
+        }
+    }
+}
+
+fn test_run_vm(input: &str, expected_constants: Vec<Object>, expected_instructions: Vec<Instructions>) {
     let program = parse(input);
     let mut compiler = Compiler::new();
     if let Err(x) = compiler.compile(program) {
@@ -31,9 +248,8 @@ fn test_run_vm(input: &str, expected_constants: Vec<Object>, expected_instructio
 
     let bytecode = compiler.bytecode();
 
-    for (i,s) in expected_instructions.iter().enumerate() {
-        assert_eq!(*s, bytecode.instructions[i]);
-    }
+    let expected_instructions = concat(expected_instructions);
+    assert_eq!(expected_instructions[..], bytecode.instructions[..expected_instructions.len()]);
 
     for (i,s) in expected_constants.iter().enumerate() {
         assert_eq!(*s, *bytecode.constants[i].as_ref());
@@ -41,6 +257,19 @@ fn test_run_vm(input: &str, expected_constants: Vec<Object>, expected_instructio
 }
 
 
+fn compile(input: &str) -> ByteCode {
+    let mut compiler = Compiler::new();
+    if let Err(x) = compiler.compile(parse(input)) {
+        panic!("{}", x)
+    }
+    return compiler.bytecode();
+}
+
+fn concat(instructions: Vec<Instructions>) -> Instructions {
+    return instructions.into_iter().flatten().collect();
+}
+
+
 fn parse(input: &str) -> Program {
     let mut l = Lexer::new(input);
     let mut p = Parser::new(&mut l);
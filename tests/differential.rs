@@ -0,0 +1,322 @@
+extern crate interpreter;
+use interpreter::lexer::Lexer;
+use interpreter::parser::Parser;
+use interpreter::evaluator::{self, object::Object, Environment};
+use interpreter::compiler::{Compiler, OptLevel};
+use interpreter::vm::{VM, verifier};
+use interpreter::regvm;
+use std::{fs, path::Path};
+
+// Every snippet runs through the evaluator, through the compiler + VM at each
+// optimization level and through the register VM, and all of them have to
+// produce the same value or fail with the same kind of error.
+// Snippets end in an expression statement, which is the value of the program
+// for both engines.
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Value(Object),
+    Function,
+    Error(String)
+}
+
+// Error messages may carry details such as the offending object after a colon
+fn error_kind(msg: &str) -> String {
+    return msg.split(':').next().unwrap_or("").trim().to_string();
+}
+
+fn outcome(obj: Object) -> Outcome {
+    match obj {
+        Object::Error(msg) => Outcome::Error(error_kind(&msg)),
+        Object::Closure(..) | Object::CompiledClosure(_) | Object::RegisterClosure(_) | Object::Builtin(_) => Outcome::Function,
+        x => Outcome::Value(x)
+    }
+}
+
+fn run_evaluator(input: &str) -> Outcome {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}\n{}", errors, input)
+    };
+
+    let mut env = Environment::new();
+    return outcome(evaluator::evaluate(program, &mut env));
+}
+
+fn run_vm(input: &str, opt_level: OptLevel) -> Outcome {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}\n{}", errors, input)
+    };
+
+    let mut compiler = Compiler::with_opt_level(opt_level);
+    if let Err(x) = compiler.compile(program) {
+        return Outcome::Error(error_kind(&x.to_string()));
+    }
+
+    // Everything the compiler produces has to pass the verifier
+    let bytecode = compiler.bytecode();
+    if let Err(x) = verifier::verify(&bytecode) {
+        panic!("{}\n{}\n{}", x, input, bytecode);
+    }
+
+    let mut vm = VM::new(bytecode);
+    if let Err(x) = vm.run() {
+        return Outcome::Error(error_kind(&x.to_string()));
+    }
+
+    return outcome(vm.last_popped_stack_elem());
+}
+
+fn run_register_vm(input: &str) -> Outcome {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}\n{}", errors, input)
+    };
+
+    let mut compiler = regvm::compiler::Compiler::new();
+    if let Err(x) = compiler.compile(program) {
+        return Outcome::Error(error_kind(&x.to_string()));
+    }
+
+    let mut vm = regvm::VM::new(compiler.program());
+    if let Err(x) = vm.run() {
+        return Outcome::Error(error_kind(&x.to_string()));
+    }
+
+    return match vm.last_popped() {
+        Some(x) => outcome(x.clone()),
+        None => Outcome::Value(Object::Null)
+    }
+}
+
+fn assert_engines_agree(input: &str) -> Outcome {
+    let evaluated = run_evaluator(input);
+    for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let executed = run_vm(input, opt_level);
+        assert_eq!(evaluated, executed, "engines disagree at {:?} on:\n{}", opt_level, input);
+    }
+    assert_eq!(evaluated, run_register_vm(input), "register vm disagrees on:\n{}", input);
+    return evaluated;
+}
+
+#[test]
+fn test_corpus() {
+    // Deep recursion needs more native stack in the evaluator than a test thread has
+    evaluator::with_stack(check_corpus);
+}
+
+fn check_corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    let mut files: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "monkey"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no .monkey files in {:?}", dir);
+
+    for path in files {
+        let input = fs::read_to_string(&path).unwrap();
+        let result = assert_engines_agree(&input);
+
+        // Files named errors_* are expected to fail, all others to succeed
+        let name = path.file_name().unwrap().to_string_lossy();
+        let failed = matches!(result, Outcome::Error(_));
+        assert_eq!(name.starts_with("errors_"), failed, "{} produced {:?}", name, result);
+    }
+}
+
+#[test]
+fn test_random_programs() {
+    for seed in 1..=500 {
+        let input = Generator::new(seed).program();
+        assert_engines_agree(&input);
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Type {
+    Int,
+    Bool,
+    Str,
+    Array,
+    // Functions from one integer to an integer
+    Func
+}
+
+const TYPES: [Type; 5] = [Type::Int, Type::Bool, Type::Str, Type::Array, Type::Func];
+
+// Generates random but mostly well typed programs. Now and then an operand of
+// the wrong type is chosen on purpose so that error paths get compared too.
+struct Generator {
+    state: u64,
+    scope: Vec<(String, Type)>,
+    names: usize
+}
+
+impl Generator {
+    fn new(seed: u64) -> Self {
+        return Generator{ state: seed.wrapping_mul(0x9E3779B97F4A7C15) | 1, scope: vec![], names: 0 };
+    }
+
+    // xorshift64*
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545F4914F6CDD1D);
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        return (self.next() % n as u64) as usize;
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        return self.below(100) < percent;
+    }
+
+    // Identifiers may only contain letters
+    fn fresh_name(&mut self) -> String {
+        self.names += 1;
+        let mut n = self.names;
+        let mut name = String::from("v");
+        while n > 0 {
+            name.push((b'a' + (n % 26) as u8) as char);
+            n /= 26;
+        }
+        return name;
+    }
+
+    fn variable(&mut self, t: Type) -> Option<String> {
+        let candidates: Vec<String> = self.scope.iter().filter(|(_, vt)| *vt == t).map(|(n, _)| n.clone()).collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let idx = self.below(candidates.len());
+        return Some(candidates[idx].clone());
+    }
+
+    fn program(&mut self) -> String {
+        let mut stmts = vec![];
+        for _ in 0..self.below(6) {
+            if self.chance(25) {
+                stmts.push(self.function_definition());
+            } else {
+                let t = TYPES[self.below(4)];
+                let value = self.expr(t, 3);
+                let name = self.fresh_name();
+                stmts.push(format!("let {} = {};", name, value));
+                self.scope.push((name, t));
+            }
+
+            if self.chance(10) {
+                let condition = self.expr(Type::Bool, 2);
+                let value = self.expr(Type::Int, 2);
+                stmts.push(format!("if ({}) {{ return {}; }};", condition, value));
+            }
+        }
+
+        let t = TYPES[self.below(4)];
+        stmts.push(self.expr(t, 4));
+        return stmts.join("\n");
+    }
+
+    fn function_definition(&mut self) -> String {
+        let name = self.fresh_name();
+        let param = self.fresh_name();
+        self.scope.push((param.clone(), Type::Int));
+        let body = if self.chance(30) {
+            // A function returning another closure over the parameter
+            let inner = self.fresh_name();
+            self.scope.push((inner.clone(), Type::Int));
+            let body = self.expr(Type::Int, 3);
+            self.scope.pop();
+            format!("fn({}) {{ {} }}({})", inner, body, param)
+        } else if self.chance(30) {
+            let local = self.fresh_name();
+            let value = self.expr(Type::Int, 2);
+            self.scope.push((local.clone(), Type::Int));
+            let body = self.expr(Type::Int, 3);
+            self.scope.pop();
+            format!("let {} = {}; {}", local, value, body)
+        } else {
+            self.expr(Type::Int, 3)
+        };
+        self.scope.pop();
+        self.scope.push((name.clone(), Type::Func));
+        return format!("let {} = fn({}) {{ {} }};", name, param, body);
+    }
+
+    fn expr(&mut self, t: Type, depth: usize) -> String {
+        // Deliberately ill typed operands
+        let t = if self.chance(1) { TYPES[self.below(TYPES.len())] } else { t };
+
+        if depth == 0 || self.chance(20) {
+            return self.leaf(t);
+        }
+
+        let d = depth - 1;
+        match t {
+            Type::Int => match self.below(9) {
+                0 => format!("({} + {})", self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                1 => format!("({} - {})", self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                2 => format!("({} * {})", self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                3 => format!("({} / {})", self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                4 => format!("-{}", self.expr(Type::Int, d)),
+                5 => format!("if ({}) {{ {} }} else {{ {} }}", self.expr(Type::Bool, d), self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                6 => if self.chance(50) { format!("len({})", self.expr(Type::Array, d)) } else { format!("len({})", self.expr(Type::Str, d)) },
+                7 => match self.variable(Type::Func) {
+                    Some(f) => format!("{}({})", f, self.expr(Type::Int, d)),
+                    None => format!("fn(x) {{ x * 2 }}({})", self.expr(Type::Int, d))
+                },
+                _ => format!("{}[{}]", self.expr(Type::Array, d), self.below(4))
+            },
+            Type::Bool => match self.below(6) {
+                0 => format!("({} < {})", self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                1 => format!("({} > {})", self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                2 => format!("({} == {})", self.expr(Type::Int, d), self.expr(Type::Int, d)),
+                3 => format!("({} != {})", self.expr(Type::Bool, d), self.expr(Type::Bool, d)),
+                4 => format!("({} == {})", self.expr(Type::Str, d), self.expr(Type::Str, d)),
+                _ => format!("!{}", self.expr(Type::Bool, d))
+            },
+            Type::Str => match self.below(3) {
+                0 => format!("({} + {})", self.expr(Type::Str, d), self.expr(Type::Str, d)),
+                1 => format!("if ({}) {{ {} }} else {{ {} }}", self.expr(Type::Bool, d), self.expr(Type::Str, d), self.expr(Type::Str, d)),
+                _ => format!("{{\"a\": {}, \"b\": {}}}[\"{}\"]", self.expr(Type::Str, d), self.expr(Type::Str, d), ["a", "b"][self.below(2)])
+            },
+            Type::Array => match self.below(4) {
+                0 => format!("push({}, {})", self.expr(Type::Array, d), self.expr(Type::Int, d)),
+                1 => format!("rest({})", self.expr(Type::Array, d)),
+                _ => {
+                    let len = self.below(4);
+                    let elements: Vec<String> = (0..len).map(|_| self.expr(Type::Int, d)).collect();
+                    format!("[{}]", elements.join(", "))
+                }
+            },
+            Type::Func => self.leaf(Type::Func)
+        }
+    }
+
+    fn leaf(&mut self, t: Type) -> String {
+        if self.chance(40) {
+            if let Some(name) = self.variable(t) {
+                return name;
+            }
+        }
+
+        match t {
+            Type::Int => self.below(20).to_string(),
+            Type::Bool => if self.chance(50) { "true".to_string() } else { "false".to_string() },
+            Type::Str => format!("\"{}\"", ["", "a", "monkey", "b c"][self.below(4)]),
+            Type::Array => format!("[{}, {}]", self.below(10), self.below(10)),
+            Type::Func => format!("fn(x) {{ x + {} }}", self.below(10))
+        }
+    }
+}
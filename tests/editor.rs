@@ -0,0 +1,159 @@
+extern crate interpreter;
+use interpreter::editor::{self, Editor, history::{self, History}};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[test]
+fn test_editing_keys() {
+    let tests = vec![
+        ("let x = 1;\r", "let x = 1;"),
+        // Left arrow and Backspace
+        ("helo\x1b[Dl\r", "hello"),
+        ("abcd\x7f\x08\r", "ab"),
+        // Ctrl-A, Ctrl-E, Home, End and Delete
+        ("bc\x01a\x05d\r", "abcd"),
+        ("bc\x1b[Ha\x1b[Fd\r", "abcd"),
+        ("xabc\x1b[H\x1b[3~\r", "abc"),
+        ("abc\x02\x02\x04\r", "ac"),
+        // Ctrl-K, Ctrl-U and Ctrl-W
+        ("abcdef\x02\x02\x02\x0b\r", "abc"),
+        ("abcdef\x02\x02\x15\r", "ef"),
+        ("let add = fn\x17\r", "let add = "),
+        // Letters beyond ASCII
+        ("\"h\u{e9}llo\"\x1b[D\x1b[D\x7f\r", "\"h\u{e9}lo\""),
+        // Keys the editor does not know change nothing
+        ("ab\x1b[5~\x1bxc\r", "abc")
+    ];
+
+    for (keys, expected) in tests {
+        let mut editor = Editor::new(History::new());
+        assert_eq!(read_line(&mut editor, keys).unwrap(), Some(expected.to_string()), "{:?}", keys);
+    }
+}
+
+#[test]
+fn test_end_of_input() {
+    let mut editor = Editor::new(History::new());
+    assert_eq!(read_line(&mut editor, "").unwrap(), None);
+    assert_eq!(read_line(&mut editor, "\x04").unwrap(), None);
+    // A line cut off by the end of the input is still a line
+    assert_eq!(read_line(&mut editor, "1 + 2").unwrap(), Some("1 + 2".to_string()));
+
+    let interrupted = read_line(&mut editor, "let x\x03");
+    assert_eq!(interrupted.unwrap_err().kind(), io::ErrorKind::Interrupted);
+}
+
+#[test]
+fn test_history() {
+    let mut editor = Editor::new(History::new());
+    for line in ["let a = 1;\r", "let b = 2;\r", "\r", "let b = 2;\r", "a + b\r"] {
+        read_line(&mut editor, line).unwrap();
+    }
+    assert_eq!(editor.history().entries(), ["let a = 1;", "let b = 2;", "a + b"]);
+
+    let tests = vec![
+        ("\x1b[A\r", "a + b"),
+        ("\x1b[A\x1b[A\x1b[A\x1b[A\r", "let a = 1;"),
+        ("\x10\x10\x0e\r", "a + b"),
+        // Down past the newest entry gives back what was being typed
+        ("b\x1b[A\x1b[B\r", "b"),
+        // The entry can be edited before it is entered
+        ("\x1b[A\x7fc\r", "a + c")
+    ];
+
+    for (keys, expected) in tests {
+        let mut editor = Editor::new(editor.history().clone());
+        assert_eq!(read_line(&mut editor, keys).unwrap(), Some(expected.to_string()), "{:?}", keys);
+    }
+}
+
+#[test]
+fn test_reverse_search() {
+    let mut history = History::new();
+    for line in ["let add = fn(a, b) { a + b };", "let double = fn(a) { a * 2 };", "add(1, 2)", "double(4)"] {
+        history.add(line).unwrap();
+    }
+
+    let tests = vec![
+        ("\x12add\r", "add(1, 2)"),
+        // Ctrl-R again goes further back
+        ("\x12add\x12\r", "let add = fn(a, b) { a + b };"),
+        ("\x12add\x12\x12\r", "let add = fn(a, b) { a + b };"),
+        ("\x12doux\x7f\r", "double(4)"),
+        // Other keys take the entry to edit it
+        ("\x12add\x1b[D\x7f3\r", "add(1, 3)"),
+        // Ctrl-G goes back to the line
+        ("x\x12add\x07\r", "x"),
+        ("\x12nothing\r", "")
+    ];
+
+    for (keys, expected) in tests {
+        let mut editor = Editor::new(history.clone());
+        assert_eq!(read_line(&mut editor, keys).unwrap(), Some(expected.to_string()), "{:?}", keys);
+    }
+}
+
+#[test]
+fn test_completion() {
+    let names = vec!["counter".to_string(), "count_down".to_string()];
+    assert_eq!(editor::completions("le", &names), ["len", "let"]);
+    assert_eq!(editor::completions("pu", &names), ["push", "puts"]);
+    assert_eq!(editor::completions("coun", &names), ["count_down", "counter"]);
+    assert_eq!(editor::completions("re", &[]), ["rest", "return"]);
+    assert!(editor::completions("z", &names).is_empty());
+
+    let tests = vec![
+        ("ret\t 1\r", "return 1"),
+        ("pus\t(xs, 1)\r", "push(xs, 1)"),
+        ("coun\t\r", "count"),
+        ("let x = cou\ter + 1\r", "let x = counter + 1"),
+        // Nothing to complete
+        ("z\t\r", "z"),
+        ("\t\r", ""),
+        ("1 +\t\r", "1 +")
+    ];
+
+    for (keys, expected) in tests {
+        let mut editor = Editor::new(History::new());
+        editor.names = names.clone();
+        assert_eq!(read_line(&mut editor, keys).unwrap(), Some(expected.to_string()), "{:?}", keys);
+    }
+
+    // Candidates that agree on nothing more are listed
+    let mut editor = Editor::new(History::new());
+    let mut output = vec![];
+    editor.read_line_from(">> ", &mut "pu\t\r".as_bytes(), &mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("\r\npush  puts\r\n"));
+}
+
+#[test]
+fn test_history_file() {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push("history");
+    let _ = fs::remove_file(&path);
+
+    let mut history = History::load(path.clone()).unwrap();
+    assert!(history.entries().is_empty());
+    history.add("let x = 1;").unwrap();
+    history.add("x").unwrap();
+    history.add("x").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let x = 1;\nx\n");
+
+    let mut editor = Editor::new(History::load(path.clone()).unwrap());
+    assert_eq!(read_line(&mut editor, "\x1b[A\x1b[A\r").unwrap(), Some("let x = 1;".to_string()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let x = 1;\nx\nlet x = 1;\n");
+
+    // Only the newest lines are kept
+    let lines: Vec<String> = (0..history::MAX_ENTRIES + 10).map(|x| x.to_string()).collect();
+    fs::write(&path, lines.join("\n")).unwrap();
+    let history = History::load(path.clone()).unwrap();
+    assert_eq!(history.entries().len(), history::MAX_ENTRIES);
+    assert_eq!(history.entries()[0], "10");
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), history::MAX_ENTRIES);
+}
+
+
+fn read_line(editor: &mut Editor, keys: &str) -> io::Result<Option<String>> {
+    return editor.read_line_from(">> ", &mut keys.as_bytes(), &mut io::sink());
+}
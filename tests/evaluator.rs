@@ -1,9 +1,11 @@
+// The table-driven tests build their cases with `vec!`
+#![allow(clippy::useless_vec)]
 extern crate interpreter;
 use interpreter::lexer::{Lexer};
 use interpreter::parser::{Parser};
 use interpreter::parser::ast::*;
 use interpreter::evaluator::{object::Object, evaluate, Environment};
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{collections::HashMap, rc::Rc};
 
 
 #[test]
@@ -62,6 +64,7 @@ fn test_array_lit() {
 }
 
 #[test]
+#[allow(clippy::mutable_key_type)]
 fn test_hash_lit() {
     let test_case = vec![
         "{\"one\": 1, \"two\": 2, \"three\": 3}"
@@ -261,7 +264,7 @@ fn test_eval_bool() {
 }
 
 fn test_eval(input: String) -> Object {
-    let mut l = Lexer::new(&input.as_str());
+    let mut l = Lexer::new(input.as_str());
     let mut p = Parser::new(&mut l);
 
 
@@ -369,7 +372,12 @@ fn test_error() {
         "true + false",
         "5; true + false; 5",
         "if (10 > 1) { true + false }",
-        "\"Hello\" - \"World\""
+        "\"Hello\" - \"World\"",
+        "let x = 5 + true; 5;",
+        "[1, -true, 3]",
+        "len(1) + 1",
+        "10 / 0",
+        "let f = fn(a, b) { a }; f(1)"
     ];
 
     let expected = vec![
@@ -380,6 +388,11 @@ fn test_error() {
         Object::Error("unknown operator".to_string()),
         Object::Error("unknown operator".to_string()),
         Object::Error("unknown operator".to_string()),
+        Object::Error("type mismatch".to_string()),
+        Object::Error("unknown operator".to_string()),
+        Object::Error("argument to len not supported".to_string()),
+        Object::Error("division by zero".to_string()),
+        Object::Error("wrong number of arguments: want=2, got=1".to_string()),
     ];
 
     for (i,s) in expected.iter().enumerate() {
@@ -429,7 +442,7 @@ fn test_function_object() {
                     Box::new(Expr::IdentExpr(Ident("x".to_string()))),
                     Infix::Plus,
                     Box::new(Expr::LiteralExpr(Literal::Int(2)))
-                ))
+                ), Location::default())
             ]),
             Environment::new()
         )
@@ -451,6 +464,8 @@ fn test_function_application() {
         "let add = fn(x, y) { x + y; }; add(5, 5);",
         "let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));",
         "fn(x) { x; } (5)",
+        "let early = fn() { return 1; 2; }; early() + 1;",
+        "let fact = fn(n) { if (n == 0) { 1 } else { n * fact(n - 1) } }; fact(5);",
     ];
 
     let expected = vec![
@@ -460,6 +475,8 @@ fn test_function_application() {
         Object::Integer(10),
         Object::Integer(20),
         Object::Integer(5),
+        Object::Integer(2),
+        Object::Integer(120),
     ];
 
     for (i,s) in expected.iter().enumerate() {
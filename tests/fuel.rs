@@ -0,0 +1,165 @@
+extern crate interpreter;
+use interpreter::compiler::{Compiler, OptLevel};
+use interpreter::parser::ast::Program;
+use interpreter::parser::Parser;
+use interpreter::lexer::Lexer;
+use interpreter::evaluator::{self, object::Object, BudgetExhausted, Environment};
+use interpreter::vm::VM;
+use interpreter::regvm;
+use std::error::Error;
+
+const PROGRAMS: [&str; 4] = [
+    "1 + 2",
+    "let f = fn(x) { x * 2 }; f(3) + f(4)",
+    "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(10)",
+    r#"let xs = [1, 2, 3]; {"a": len(xs)}["a"]"#
+];
+
+#[test]
+fn test_unbounded_recursion_runs_out_of_fuel() {
+    let input = "let f = fn(n) { f(n + 1) }; f(0)";
+    let budget = 1000;
+
+    let mut env = Environment::new();
+    let mut fuel = budget;
+    let evaluated = evaluator::evaluate_with_fuel(parse(input), &mut env, &mut fuel);
+    assert_eq!(evaluated, Err(BudgetExhausted));
+    assert_eq!(fuel, 0);
+
+    for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let mut vm = VM::new(compile(input, opt_level));
+        vm.set_fuel(budget);
+        assert_budget_exhausted(vm.run());
+        assert_eq!(vm.fuel(), Some(0));
+    }
+
+    let mut vm = regvm::VM::new(compile_register(input));
+    vm.set_fuel(budget);
+    assert_budget_exhausted(vm.run());
+    assert_eq!(vm.fuel(), Some(0));
+}
+
+#[test]
+fn test_evaluator_fuel() {
+    for input in PROGRAMS.iter() {
+        let expected = evaluator::evaluate(parse(input), &mut Environment::new());
+
+        // Learn how much the program uses, then check that exactly that much is enough
+        let mut fuel = 1_000_000;
+        assert_eq!(evaluator::evaluate_with_fuel(parse(input), &mut Environment::new(), &mut fuel), Ok(expected.clone()), "{}", input);
+        let used = 1_000_000 - fuel;
+        assert!(used > 0, "{}", input);
+
+        let mut fuel = used;
+        assert_eq!(evaluator::evaluate_with_fuel(parse(input), &mut Environment::new(), &mut fuel), Ok(expected), "{}", input);
+        assert_eq!(fuel, 0, "{}", input);
+
+        let mut fuel = used - 1;
+        let evaluated = evaluator::evaluate_with_fuel(parse(input), &mut Environment::new(), &mut fuel);
+        assert_eq!(evaluated, Err(BudgetExhausted), "{}", input);
+    }
+
+    // Fuel only applies to the metered evaluation
+    let mut fuel = 0;
+    assert_eq!(evaluator::evaluate_with_fuel(parse("1"), &mut Environment::new(), &mut fuel), Err(BudgetExhausted));
+    assert_eq!(evaluator::evaluate(parse("1 + 2"), &mut Environment::new()), Object::Integer(3));
+}
+
+#[test]
+fn test_vm_fuel() {
+    for opt_level in [OptLevel::O0, OptLevel::O2] {
+        for input in PROGRAMS.iter() {
+            let mut vm = VM::new(compile(input, opt_level));
+            assert_eq!(vm.fuel(), None);
+            vm.run().unwrap();
+            let expected = vm.last_popped_stack_elem();
+
+            let mut vm = VM::new(compile(input, opt_level));
+            vm.set_fuel(1_000_000);
+            vm.run().unwrap();
+            let used = 1_000_000 - vm.fuel().unwrap();
+
+            let mut vm = VM::new(compile(input, opt_level));
+            vm.set_fuel(used);
+            vm.run().unwrap();
+            assert_eq!(vm.last_popped_stack_elem(), expected, "{}", input);
+            assert_eq!(vm.fuel(), Some(0), "{}", input);
+
+            let mut vm = VM::new(compile(input, opt_level));
+            vm.set_fuel(used - 1);
+            assert_budget_exhausted(vm.run());
+        }
+    }
+}
+
+#[test]
+fn test_register_vm_fuel() {
+    for input in PROGRAMS.iter() {
+        let mut vm = regvm::VM::new(compile_register(input));
+        vm.set_fuel(1_000_000);
+        vm.run().unwrap();
+        let expected = vm.last_popped().cloned();
+        let used = 1_000_000 - vm.fuel().unwrap();
+
+        let mut vm = regvm::VM::new(compile_register(input));
+        vm.set_fuel(used);
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped().cloned(), expected, "{}", input);
+        assert_eq!(vm.fuel(), Some(0), "{}", input);
+
+        let mut vm = regvm::VM::new(compile_register(input));
+        vm.set_fuel(used - 1);
+        assert_budget_exhausted(vm.run());
+    }
+}
+
+#[test]
+fn test_refueled_runs_resume() {
+    let input = PROGRAMS[2];
+    for opt_level in [OptLevel::O0, OptLevel::O2] {
+        let mut vm = VM::new(compile(input, opt_level));
+        vm.set_fuel(7);
+        while let Err(x) = vm.run() {
+            assert_eq!(x.downcast_ref::<BudgetExhausted>(), Some(&BudgetExhausted), "{}", x);
+            vm.set_fuel(7);
+        }
+        assert_eq!(vm.last_popped_stack_elem(), Object::Integer(55), "{:?}", opt_level);
+    }
+
+    let mut vm = regvm::VM::new(compile_register(input));
+    vm.set_fuel(7);
+    while let Err(x) = vm.run() {
+        assert_eq!(x.downcast_ref::<BudgetExhausted>(), Some(&BudgetExhausted), "{}", x);
+        vm.set_fuel(7);
+    }
+    assert_eq!(vm.last_popped().cloned(), Some(Object::Integer(55)));
+}
+
+
+fn assert_budget_exhausted(result: Result<(), Box<dyn Error>>) {
+    match result {
+        Ok(_) => panic!("expected the budget to run out"),
+        Err(x) => assert_eq!(x.downcast_ref::<BudgetExhausted>(), Some(&BudgetExhausted))
+    }
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
+
+fn compile(input: &str, opt_level: OptLevel) -> interpreter::compiler::ByteCode {
+    let mut compiler = Compiler::with_opt_level(opt_level);
+    compiler.compile(parse(input)).unwrap();
+    return compiler.bytecode();
+}
+
+fn compile_register(input: &str) -> regvm::compiler::CompiledProgram {
+    let mut compiler = regvm::compiler::Compiler::new();
+    compiler.compile(parse(input)).unwrap();
+    return compiler.program();
+}
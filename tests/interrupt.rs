@@ -0,0 +1,119 @@
+extern crate interpreter;
+use interpreter::compiler::{Compiler, OptLevel};
+use interpreter::parser::ast::{Ident, Program};
+use interpreter::parser::Parser;
+use interpreter::lexer::Lexer;
+use interpreter::evaluator::{self, object::Object, Environment};
+use interpreter::evaluator::interrupt::{self, CancelToken, Cancelled, Timeout, Watchdog};
+use interpreter::vm::VM;
+use interpreter::regvm;
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Runs for minutes unless something stops it
+const ENDLESS: &str = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(40)";
+const FIB: &str = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)";
+
+#[test]
+fn test_cancel_from_another_thread() {
+    let token = CancelToken::new();
+    let canceller = token.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        canceller.cancel();
+    });
+
+    let mut vm = VM::new(compile(ENDLESS));
+    vm.set_cancel_token(token.clone());
+    let start = Instant::now();
+    assert_interrupted::<Cancelled>(vm.run());
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(token.is_cancelled());
+
+    // A cancelled token stops the next run straight away
+    let mut vm = regvm::VM::new(compile_register(ENDLESS));
+    vm.set_cancel_token(token.clone());
+    assert_interrupted::<Cancelled>(vm.run());
+
+    let mut env = Environment::new();
+    let evaluated = interrupt::with_watchdog(Watchdog::new(Some(token), None), || evaluator::evaluate(parse(ENDLESS), &mut env));
+    assert_eq!(evaluated, Object::Error(Cancelled.to_string()));
+}
+
+#[test]
+fn test_deadline() {
+    let deadline = Instant::now() + Duration::from_millis(20);
+
+    let mut vm = VM::new(compile(ENDLESS));
+    vm.set_deadline(deadline);
+    assert_interrupted::<Timeout>(vm.run());
+
+    let mut vm = regvm::VM::new(compile_register(ENDLESS));
+    vm.set_deadline(deadline);
+    assert_interrupted::<Timeout>(vm.run());
+
+    let mut env = Environment::new();
+    let evaluated = interrupt::with_watchdog(Watchdog::new(None, Some(deadline)), || evaluator::evaluate(parse(ENDLESS), &mut env));
+    assert_eq!(evaluated, Object::Error(Timeout.to_string()));
+    assert!(Instant::now() < deadline + Duration::from_secs(10));
+}
+
+#[test]
+fn test_interrupted_runs_keep_their_state() {
+    // Stopped at the first check, then resumed where they left off
+    let token = CancelToken::new();
+    token.cancel();
+    let mut vm = VM::new(compile(FIB));
+    vm.set_cancel_token(token);
+    assert_interrupted::<Cancelled>(vm.run());
+    vm.set_cancel_token(CancelToken::new());
+    vm.run().unwrap();
+    assert_eq!(vm.last_popped_stack_elem(), Object::Integer(610));
+
+    let mut vm = regvm::VM::new(compile_register(FIB));
+    vm.set_deadline(Instant::now());
+    assert_interrupted::<Timeout>(vm.run());
+    vm.set_deadline(Instant::now() + Duration::from_secs(60));
+    vm.run().unwrap();
+    assert_eq!(vm.last_popped(), Some(&Object::Integer(610)));
+
+    // The evaluator unwinds, but keeps the bindings made before the interruption
+    let mut env = Environment::new();
+    let input = format!("let before = 1; {}", ENDLESS);
+    let watchdog = Watchdog::new(None, Some(Instant::now() + Duration::from_millis(20)));
+    interrupt::with_watchdog(watchdog, || evaluator::evaluate(parse(&input), &mut env));
+    assert_eq!(*env[&Ident("before".to_string())].borrow(), Object::Integer(1));
+
+    // Nothing is watched outside of the watchdog
+    assert_eq!(evaluator::evaluate(parse(FIB), &mut Environment::new()), Object::Integer(610));
+}
+
+
+fn assert_interrupted<E: Error + 'static>(result: Result<(), Box<dyn Error>>) {
+    match result {
+        Ok(_) => panic!("expected the run to be interrupted"),
+        Err(x) => assert!(x.downcast_ref::<E>().is_some(), "unexpected error {}", x)
+    }
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
+
+fn compile(input: &str) -> interpreter::compiler::ByteCode {
+    let mut compiler = Compiler::with_opt_level(OptLevel::O2);
+    compiler.compile(parse(input)).unwrap();
+    return compiler.bytecode();
+}
+
+fn compile_register(input: &str) -> regvm::compiler::CompiledProgram {
+    let mut compiler = regvm::compiler::Compiler::new();
+    compiler.compile(parse(input)).unwrap();
+    return compiler.program();
+}
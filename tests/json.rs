@@ -0,0 +1,178 @@
+extern crate interpreter;
+use interpreter::lexer::Lexer;
+use interpreter::parser::Parser;
+use interpreter::parser::ast::*;
+use interpreter::parser::ast::visit;
+use interpreter::parser::json;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+#[test]
+fn test_schema() {
+    let tests = vec![
+        ("x", r#"{"type":"Identifier","name":"x"}"#),
+        ("-12", r#"{"type":"Prefix","operator":"-","right":{"type":"Integer","value":12}}"#),
+        ("!true", r#"{"type":"Prefix","operator":"!","right":{"type":"Boolean","value":true}}"#),
+        (r#""a b""#, r#"{"type":"String","value":"a b"}"#),
+        (
+            "a != b * c",
+            r#"{"type":"Infix","operator":"!=","left":{"type":"Identifier","name":"a"},"right":{"type":"Infix","operator":"*","left":{"type":"Identifier","name":"b"},"right":{"type":"Identifier","name":"c"}}}"#
+        ),
+        (
+            "{1: [true]}",
+            r#"{"type":"Hash","pairs":[{"key":{"type":"Integer","value":1},"value":{"type":"Array","elements":[{"type":"Boolean","value":true}]}}]}"#
+        ),
+        (
+            "xs[0]",
+            r#"{"type":"Index","left":{"type":"Identifier","name":"xs"},"index":{"type":"Integer","value":0}}"#
+        ),
+        ("if (a) {}", r#"{"type":"If","condition":{"type":"Identifier","name":"a"},"consequence":[],"alternative":null}"#),
+        ("fn() {}()", r#"{"type":"Call","function":{"type":"Function","parameters":[],"body":[]},"arguments":[]}"#)
+    ];
+
+    for (input, expected) in tests {
+        let expected = format!(r#"{{"version":1,"body":[{{"type":"Expression","span":null,"expression":{}}}]}}"#, expected);
+        let program = json::from_json(&expected).unwrap();
+        assert_eq!(program, parse(input), "{}", input);
+        assert_eq!(json::to_json(&program), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_statements_and_spans() {
+    let source = "let f = fn(a) {\n  return a;\n};\nf(1)";
+    let written = json::dump(source).unwrap();
+    assert_eq!(written, concat!(
+        r#"{"version":1,"body":["#,
+        r#"{"type":"Let","span":{"start":{"line":1,"column":1},"end":{"line":3,"column":2}},"name":"f","value":"#,
+        r#"{"type":"Function","parameters":["a"],"body":["#,
+        r#"{"type":"Return","span":{"start":{"line":2,"column":3},"end":{"line":2,"column":11}},"value":{"type":"Identifier","name":"a"}}]}},"#,
+        r#"{"type":"Expression","span":{"start":{"line":4,"column":1},"end":{"line":4,"column":4}},"#,
+        r#""expression":{"type":"Call","function":{"type":"Identifier","name":"f"},"arguments":[{"type":"Integer","value":1}]}}]}"#
+    ));
+
+    let program = parse(source);
+    let read = json::from_json(&written).unwrap();
+    assert_eq!(read, program);
+    assert_eq!(locations(&read), locations(&program));
+
+    // A statement without a span is read back at line 0
+    let partial = written.replacen(r#""span":{"start":{"line":2,"column":3},"end":{"line":2,"column":11}}"#, r#""span":null"#, 1);
+    let read = json::from_json(&partial).unwrap();
+    let lines: Vec<usize> = locations(&read).iter().map(|x| x.0.line).collect();
+    assert_eq!(lines, vec![1, 0, 4]);
+    assert_eq!(json::to_json(&read), partial);
+}
+
+#[test]
+fn test_round_trip_corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let program = parse(&fs::read_to_string(&path).unwrap());
+        let read = json::from_json(&json::to_json(&program)).unwrap();
+        assert_eq!(read, program, "{}", path.display());
+        assert_eq!(locations(&read), locations(&program), "{}", path.display());
+    }
+}
+
+#[test]
+fn test_values_other_tools_write() {
+    // Trees built by hand can hold what no source parses to
+    let program = vec![
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Int(i64::MIN)), Location::default()),
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Int(i64::MAX)), Location::default()),
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Str(Rc::new("\"quoted\" \\ \u{1} \u{e9} \u{1f412}\n".to_string()))), Location::default())
+    ];
+    let written = json::to_json(&program);
+    assert!(written.contains(r#""value":-9223372036854775808"#), "{}", written);
+    assert!(written.contains(r#""\"quoted\" \\ \u0001 é 🐒\n""#), "{}", written);
+    assert_eq!(json::from_json(&written).unwrap(), program);
+
+    // Any layout, field order, escapes and fields this version does not know
+    let written = r#"
+        {
+            "body": [
+                {"value": {"value": "\u00e9\ud83d\udc12\/\t", "type": "String"}, "type": "Return", "comment": "new"},
+                {"type": "Let", "name": "x", "value": {"type": "Boolean", "value": false}, "span": null}
+            ],
+            "version": 1
+        }
+    "#;
+    let program = json::from_json(written).unwrap();
+    assert_eq!(program, vec![
+        Stmt::ReturnStmt(Expr::LiteralExpr(Literal::Str(Rc::new("\u{e9}\u{1f412}/\t".to_string()))), Location::default()),
+        Stmt::LetStmt(Ident("x".to_string()), Expr::LiteralExpr(Literal::Bool(false)), Location::default())
+    ]);
+}
+
+#[test]
+fn test_errors() {
+    let tests = vec![
+        ("", "unexpected end of input at 1:1"),
+        ("{\"version\": 1, \"body\": []} x", "expected the end of the input at 1:28"),
+        ("{\"version\": 1,\n \"body\": [}", "expected a value at 2:11"),
+        ("{\"version\": 2, \"body\": []}", "unsupported version, expected 1"),
+        ("{\"body\": []}", "program is missing version"),
+        ("{\"version\": 1, \"body\": {}}", "expected block to be an array"),
+        ("{\"version\": 1, \"body\": [{\"type\": \"Loop\"}]}", "unknown statement type Loop"),
+        ("{\"version\": 1, \"body\": [{\"type\": \"Return\"}]}", "Return is missing value"),
+        (
+            "{\"version\": 1, \"body\": [{\"type\": \"Return\", \"value\": {\"type\": \"Integer\", \"value\": 1.5}}]}",
+            "1.5 is not a 64-bit integer"
+        ),
+        (
+            "{\"version\": 1, \"body\": [{\"type\": \"Return\", \"value\": {\"type\": \"Prefix\", \"operator\": \"~\"}}]}",
+            "unknown prefix operator ~"
+        ),
+        ("{\"version\": 1, \"body\": [{\"type\": \"Let\", \"span\": {\"start\": 1}}]}", "expected position to be an object"),
+        ("\"\\ud83d\"", "invalid character escape at 1:2"),
+        ("\"\\x\"", "invalid escape at 1:2"),
+        ("\"a\\ud83d\\u0041\"", "invalid surrogate pair at 1:3"),
+        ("\"tab\there\"", "control character in string at 1:5"),
+        ("[1,]", "expected a value at 1:4"),
+        ("[01e]", "expected a digit at 1:5")
+    ];
+
+    for (input, expected) in tests {
+        let error = json::from_json(input).unwrap_err();
+        assert_eq!(error.to_string(), expected, "{}", input);
+    }
+
+    // Deeply nested input is refused rather than overflowing the stack
+    let deep = "[".repeat(100_000);
+    assert_eq!(json::from_json(&deep).unwrap_err().to_string(), "nested too deeply at 1:513");
+
+    let errors = json::dump("let x = 1;\nlet = 2;").unwrap_err();
+    assert_eq!(errors[0].0.line, 2);
+}
+
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
+
+// Where every statement starts and ends, in the order the walk meets them.
+// Comparing trees leaves these out.
+fn locations(program: &Program) -> Vec<(Span, Span)> {
+    let mut locations = Locations::default();
+    locations.visit_block(program);
+    return locations.0;
+}
+
+#[derive(Default)]
+struct Locations(Vec<(Span, Span)>);
+
+impl<'a> Visitor<'a> for Locations {
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        let location = stmt.location();
+        self.0.push((location.start, location.end));
+        visit::walk_stmt(self, stmt);
+    }
+}
@@ -1,6 +1,6 @@
 extern crate interpreter;
 
-use interpreter::lexer::token::{Token};
+use interpreter::lexer::token::{Token, Comment, Span};
 use interpreter::lexer::{Lexer};
 use std::{ rc::Rc};
 
@@ -142,4 +142,56 @@ fn lex_next_token() {
         assert_eq!(tt, tok);
     }
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn lex_comments() {
+    let input = "// leading\nlet x = 10 / 2; // trailing \n\"// no comment\"//last";
+    let expected = vec![
+        Token::Let,
+        Token::Ident("x".to_string()),
+        Token::Assign,
+        Token::Int(10),
+        Token::Slash,
+        Token::Int(2),
+        Token::Semicolon,
+        Token::Str(Rc::new("// no comment".to_string())),
+        Token::EOF
+    ];
+
+    let mut l = Lexer::new(input);
+    for tt in expected {
+        assert_eq!(tt, l.next_token());
+    }
+    assert_eq!(l.comments, vec![
+        Comment{ text: "// leading".to_string(), span: Span{ line: 1, column: 1 } },
+        Comment{ text: "// trailing".to_string(), span: Span{ line: 2, column: 17 } },
+        Comment{ text: "//last".to_string(), span: Span{ line: 3, column: 16 } }
+    ]);
+}
+
+#[test]
+fn lex_non_ascii() {
+    let input = "// café
+let s = \"naïve 🐒\"; // ünïcode
+s";
+    let expected = vec![
+        (Token::Let, Span{ line: 2, column: 1 }),
+        (Token::Ident("s".to_string()), Span{ line: 2, column: 5 }),
+        (Token::Assign, Span{ line: 2, column: 7 }),
+        (Token::Str(Rc::new("naïve 🐒".to_string())), Span{ line: 2, column: 9 }),
+        (Token::Semicolon, Span{ line: 2, column: 18 }),
+        (Token::Ident("s".to_string()), Span{ line: 3, column: 1 }),
+        (Token::EOF, Span{ line: 3, column: 2 })
+    ];
+
+    let mut l = Lexer::new(input);
+    for (tt, span) in expected {
+        assert_eq!(tt, l.next_token());
+        assert_eq!(span, l.span());
+    }
+    assert_eq!(l.comments, vec![
+        Comment{ text: "// café".to_string(), span: Span{ line: 1, column: 1 } },
+        Comment{ text: "// ünïcode".to_string(), span: Span{ line: 2, column: 20 } }
+    ]);
+}
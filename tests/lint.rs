@@ -0,0 +1,104 @@
+extern crate interpreter;
+use interpreter::lint::{self, Rule};
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_rules() {
+    let tests = vec![
+        // Unused bindings, in functions too, but not parameters or names starting with _
+        ("let x = 1;", vec![("1:1", Rule::UnusedBinding)]),
+        ("let x = 1; x", vec![]),
+        ("let x = 1; let x = 2; x", vec![("1:1", Rule::UnusedBinding)]),
+        ("let f = fn(a, b) {\n  let c = 1;\n  a\n};\nf(1, 2)", vec![("2:3", Rule::UnusedBinding)]),
+        ("let _x = 1;", vec![]),
+        // A function that calls itself is still unused
+        ("let f = fn(n) { f(n - 1) };", vec![("1:1", Rule::UnusedBinding)]),
+        // Builtins shadowed by lets and by parameters
+        ("let len = 1;\nlen", vec![("1:1", Rule::ShadowedBuiltin)]),
+        ("let f = fn(puts) { puts };\nf(1)", vec![("1:1", Rule::ShadowedBuiltin)]),
+        // Only the first statement after a return is reported
+        ("let f = fn() {\n  return 1;\n  puts(2);\n  3\n};\nf()", vec![("3:3", Rule::UnreachableCode)]),
+        ("return 1;\nputs(2)", vec![("2:1", Rule::UnreachableCode)]),
+        ("if (true) { return 1; } else { 2 }; puts(3)", vec![]),
+        // Arity of functions bound by let, their other names, literals and builtins
+        ("let add = fn(a, b) { a + b };\nadd(1)", vec![("2:1", Rule::WrongArity)]),
+        ("let add = fn(a, b) { a + b };\nlet plus = add;\nplus(1, 2, 3)", vec![("3:1", Rule::WrongArity)]),
+        ("fn(a) { a }(1, 2)", vec![("1:1", Rule::WrongArity)]),
+        ("len([1], [2]);\npush([1]);\nputs(1, 2, 3)", vec![("1:1", Rule::WrongArity), ("2:1", Rule::WrongArity)]),
+        // A parameter could be any function
+        ("let apply = fn(f) { f(1, 2) };\napply(fn(a, b) { a })", vec![]),
+        ("let f = fn(n) { if (n < 1) { 0 } else { f(n - 1, 1) } };\nf(3)", vec![("1:41", Rule::WrongArity)]),
+        // Undefined names, and ones used before they are defined
+        ("x + 1", vec![("1:1", Rule::UndefinedIdentifier)]),
+        ("let x = x;", vec![("1:1", Rule::UndefinedIdentifier), ("1:1", Rule::UnusedBinding)]),
+        ("let f = fn() { g() };\nlet g = fn() { 1 };\nf()", vec![("1:16", Rule::UndefinedIdentifier), ("2:1", Rule::UnusedBinding)]),
+        ("let f = fn(a) { fn(b) { a + b } };\nf(1)(2)", vec![]),
+        // An if block shares the scope around it
+        ("if (true) { let x = 1; }\nx", vec![]),
+        ("{\"a\": y}[z]", vec![("1:1", Rule::UndefinedIdentifier), ("1:1", Rule::UndefinedIdentifier)])
+    ];
+
+    for (input, expected) in tests {
+        let found: Vec<(String, Rule)> = lint::check(input).unwrap().iter()
+            .map(|x| (format!("{}:{}", x.span.line, x.span.column), x.rule))
+            .collect();
+        let expected: Vec<(String, Rule)> = expected.into_iter().map(|(span, rule)| (span.to_string(), rule)).collect();
+        assert_eq!(found, expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_messages() {
+    let diagnostics = lint::check("let add = fn(a, b) { a + b };\nadd(1);\nlet first = undefined;").unwrap();
+    let messages: Vec<String> = diagnostics.iter().map(|x| x.to_string()).collect();
+    assert_eq!(messages, [
+        "2:1: add takes 2 arguments, but is given 1 [wrong-arity]",
+        "3:1: undefined is not defined [undefined-identifier]",
+        "3:1: first shadows the builtin of the same name [shadowed-builtin]",
+        "3:1: first is never used [unused-binding]"
+    ]);
+
+    for rule in Rule::ALL {
+        assert_eq!(Rule::from_id(rule.id()), Some(rule));
+    }
+    assert_eq!(Rule::from_id("unused"), None);
+}
+
+#[test]
+fn test_allow_comments() {
+    let tests = vec![
+        ("let x = 1; // lint: allow(unused-binding)", 0),
+        ("// lint: allow(unused-binding)\nlet x = 1;", 0),
+        ("//lint:allow( shadowed-builtin , unused-binding )\nlet len = 1;", 0),
+        // Only the rules named, and only on their line
+        ("let len = 1; // lint: allow(unused-binding)", 1),
+        ("let x = 1; // lint: allow(unused-binding)\nlet y = 2;", 1),
+        ("// lint: allow(unused-binding)\n\nlet x = 1;", 1),
+        ("let x = 1; // allow(unused-binding)", 1),
+        ("let x = 1; // lint: allow(unused)", 1)
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(lint::check(input).unwrap().len(), expected, "{}", input);
+    }
+
+    let errors = lint::check("let x = 1;\nlet = 2;").unwrap_err();
+    assert_eq!(errors[0].0.line, 2);
+}
+
+#[test]
+fn test_corpus() {
+    // Only the program written to call a function wrongly is found wanting
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let diagnostics = lint::check(&fs::read_to_string(&path).unwrap()).unwrap();
+        let rules: Vec<Rule> = diagnostics.iter().map(|x| x.rule).collect();
+        if path.ends_with("errors_arity.monkey") {
+            assert_eq!(rules, [Rule::WrongArity]);
+        } else {
+            assert_eq!(rules, [], "{}", path.display());
+        }
+    }
+}
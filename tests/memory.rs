@@ -0,0 +1,154 @@
+extern crate interpreter;
+use interpreter::compiler::{Compiler, OptLevel};
+use interpreter::parser::ast::Program;
+use interpreter::parser::Parser;
+use interpreter::lexer::Lexer;
+use interpreter::evaluator::{self, memory, memory::MemoryLimitExceeded, object::Object, Environment};
+use interpreter::vm::VM;
+use interpreter::regvm;
+use std::error::Error;
+
+#[test]
+fn test_runaway_scripts_hit_the_limit() {
+    let programs = [
+        // Arrays through push in recursion
+        "let grow = fn(xs) { grow(push(xs, 1)) }; grow([])",
+        // Strings doubling through +
+        r#"let grow = fn(s) { grow(s + s) }; grow("ab")"#,
+        // Array literals
+        "let grow = fn(xs) { grow([xs, xs, xs, xs]) }; grow(1)",
+        // Hash literals
+        "let grow = fn(n) { {n: n}; grow(n + 1) }; grow(0)",
+        // Rest of a long array
+        "let long = fn(xs, n) { if (n == 0) { xs } else { long(push(xs, n), n - 1) } }; let drain = fn(xs) { drain(rest(xs)); }; drain(long([], 50))"
+    ];
+    let limit = 100_000;
+
+    for input in programs.iter() {
+        let (evaluated, used) = memory::with_limit(limit, || evaluator::evaluate(parse(input), &mut Environment::new()));
+        assert_eq!(evaluated, Object::Error(MemoryLimitExceeded.to_string()), "{}", input);
+        assert!(used <= limit, "{}", input);
+
+        for opt_level in [OptLevel::O0, OptLevel::O2] {
+            let mut vm = VM::new(compile(input, opt_level));
+            vm.set_memory_limit(limit);
+            assert_memory_limit_exceeded(vm.run(), input);
+            assert!(vm.memory_allocated() <= limit, "{}", input);
+        }
+
+        let mut vm = regvm::VM::new(compile_register(input));
+        vm.set_memory_limit(limit);
+        assert_memory_limit_exceeded(vm.run(), input);
+        assert!(vm.memory_allocated() <= limit, "{}", input);
+    }
+}
+
+#[test]
+fn test_engines_account_the_same_bytes() {
+    let programs = [
+        ("1 + 2", 0),
+        ("let s = \"ab\"; s + s + s", 10),
+        ("[1, 2, 3]", memory::array_size(3)),
+        ("push(rest([1, 2, 3]), 4)", memory::array_size(3) + memory::array_size(2) + memory::array_size(3)),
+        ("{1: 2, 3: 4}", memory::hash_size(2)),
+        ("let f = fn(x) { [x, x] }; len(f(1)) + len(f(2))", 2 * memory::array_size(2))
+    ];
+
+    for (input, expected) in programs.iter() {
+        let (_, used) = memory::with_limit(usize::MAX, || evaluator::evaluate(parse(input), &mut Environment::new()));
+        assert_eq!(used, *expected, "{}", input);
+
+        let mut vm = VM::new(compile(input, OptLevel::O0));
+        vm.set_memory_limit(usize::MAX);
+        vm.run().unwrap();
+        assert_eq!(vm.memory_allocated(), *expected, "{}", input);
+
+        let mut vm = regvm::VM::new(compile_register(input));
+        vm.set_memory_limit(usize::MAX);
+        vm.run().unwrap();
+        assert_eq!(vm.memory_allocated(), *expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_scripts_within_the_limit() {
+    let input = "let xs = push([1, 2], 3); len(xs)";
+    let needed = memory::array_size(2) + memory::array_size(3);
+
+    let (evaluated, _) = memory::with_limit(needed, || evaluator::evaluate(parse(input), &mut Environment::new()));
+    assert_eq!(evaluated, Object::Integer(3));
+    let (evaluated, _) = memory::with_limit(needed - 1, || evaluator::evaluate(parse(input), &mut Environment::new()));
+    assert_eq!(evaluated, Object::Error(MemoryLimitExceeded.to_string()));
+
+    let mut vm = VM::new(compile(input, OptLevel::O2));
+    vm.set_memory_limit(needed);
+    vm.run().unwrap();
+    assert_eq!(vm.last_popped_stack_elem(), Object::Integer(3));
+    assert_eq!(vm.memory_allocated(), needed);
+
+    let mut vm = VM::new(compile(input, OptLevel::O2));
+    vm.set_memory_limit(needed - 1);
+    assert_memory_limit_exceeded(vm.run(), input);
+
+    // Nothing is accounted without a limit
+    let mut vm = VM::new(compile(input, OptLevel::O2));
+    vm.run().unwrap();
+    assert_eq!(vm.memory_allocated(), 0);
+    assert_eq!(evaluator::evaluate(parse("\"a\" + \"b\""), &mut Environment::new()).to_string(), "ab");
+}
+
+
+#[test]
+fn test_limit_counts_everything_allocated() {
+    // Each string is dropped as soon as it is built, but still counts
+    let input = "let s = \"ab\"; let f = fn(n) { if (n == 0) { 0 } else { s + s; f(n - 1) } }; f(10)";
+    let (evaluated, used) = memory::with_limit(usize::MAX, || evaluator::evaluate(parse(input), &mut Environment::new()));
+    assert_eq!((evaluated, used), (Object::Integer(0), 40));
+
+    let mut vm = VM::new(compile(input, OptLevel::O2));
+    vm.set_memory_limit(39);
+    assert_memory_limit_exceeded(vm.run(), input);
+}
+
+#[test]
+fn test_folded_strings_are_charged() {
+    let input = "\"abc\" + \"de\"";
+    let (bytecode, used) = memory::with_limit(5, || compile(input, OptLevel::O1));
+    assert_eq!((bytecode.constants.len(), used), (1, 5));
+
+    // Left for the VM to build, and fail on, when the compile cannot afford it
+    let (bytecode, used) = memory::with_limit(4, || compile(input, OptLevel::O1));
+    assert_eq!((bytecode.constants.len(), used), (2, 0));
+    let mut vm = VM::new(bytecode);
+    vm.set_memory_limit(4);
+    assert_memory_limit_exceeded(vm.run(), input);
+}
+
+
+fn assert_memory_limit_exceeded(result: Result<(), Box<dyn Error>>, input: &str) {
+    match result {
+        Ok(_) => panic!("expected to run out of memory: {}", input),
+        Err(x) => assert_eq!(x.downcast_ref::<MemoryLimitExceeded>(), Some(&MemoryLimitExceeded), "{}: {}", input, x)
+    }
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
+
+fn compile(input: &str, opt_level: OptLevel) -> interpreter::compiler::ByteCode {
+    let mut compiler = Compiler::with_opt_level(opt_level);
+    compiler.compile(parse(input)).unwrap();
+    return compiler.bytecode();
+}
+
+fn compile_register(input: &str) -> regvm::compiler::CompiledProgram {
+    let mut compiler = regvm::compiler::Compiler::new();
+    compiler.compile(parse(input)).unwrap();
+    return compiler.program();
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const MESSY: &str = "// Adds\nlet add=fn(a,b){a+b}; // sum\n\n\n\nadd(1,2)";
+const TIDY: &str = "// Adds\nlet add = fn(a, b) {\n    a + b\n}; // sum\n\nadd(1, 2)\n";
+
+#[test]
+fn test_formats_in_place() {
+    let path = script("in_place.mk", MESSY);
+    let output = run(&[&path]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&path).unwrap(), TIDY);
+
+    let output = run(&["--indent=2", &path]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&path).unwrap(), TIDY.replace("    a + b", "  a + b"));
+}
+
+#[test]
+fn test_check() {
+    let messy = script("check_messy.mk", MESSY);
+    let tidy = script("check_tidy.mk", TIDY);
+
+    let output = run(&["--check", &tidy]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    let output = run(&["--check", &tidy, &messy]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{} is not formatted\n", messy));
+    // Checking leaves the file alone
+    assert_eq!(fs::read_to_string(&messy).unwrap(), MESSY);
+
+    // The same file is formatted in another style
+    let output = run(&["--check", "--width=20", &tidy]);
+    assert_eq!(output.status.code(), Some(0));
+    let output = run(&["--check", "--width=8", &tidy]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_stdin() {
+    let output = pipe(&[], MESSY);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), TIDY);
+
+    let output = pipe(&["--check"], MESSY);
+    assert_eq!(output.status.code(), Some(1));
+    let output = pipe(&["--check"], TIDY);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_errors() {
+    let broken = script("broken.mk", "let x = 1;\nlet = 2;\n");
+    let tidy = script("errors_tidy.mk", MESSY);
+    let output = run(&[&broken, &tidy]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with(&format!("{}:2:5: expected next token to be Ident(\"\"), got Assign instead\n", broken)), "{}", stderr);
+    // Files that parse are still formatted, the others are left alone
+    assert_eq!(fs::read_to_string(&tidy).unwrap(), TIDY);
+    assert_eq!(fs::read_to_string(&broken).unwrap(), "let x = 1;\nlet = 2;\n");
+
+    let output = run(&["no_such_file.mk"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--tabs"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&["--width=wide"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+fn script(name: &str, source: &str) -> String {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push(name);
+    fs::write(&path, source).unwrap();
+    return path.to_str().unwrap().to_string();
+}
+
+fn pipe(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_monkeyfmt")).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    return child.wait_with_output().unwrap();
+}
+
+fn run(args: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_monkeyfmt")).args(args).output().unwrap();
+}
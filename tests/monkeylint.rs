@@ -0,0 +1,72 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const FAULTY: &str = "let len = fn(a, b) { a + b };\nlen(1);\nlet x = 1;\n";
+
+#[test]
+fn test_reports() {
+    let faulty = script("faulty.mk", FAULTY);
+    let clean = script("clean.mk", "let x = 1;\nputs(x)\n");
+
+    let output = run(&[&clean]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    let output = run(&[&faulty, &clean]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!(
+        "{0}:1:1: len shadows the builtin of the same name [shadowed-builtin]\n\
+         {0}:2:1: len takes 2 arguments, but is given 1 [wrong-arity]\n\
+         {0}:3:1: x is never used [unused-binding]\n",
+        faulty
+    ));
+}
+
+#[test]
+fn test_allow() {
+    let output = pipe(&["--allow=shadowed-builtin", "--allow=wrong-arity"], FAULTY);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "<stdin>:3:1: x is never used [unused-binding]\n");
+
+    let output = pipe(&["--allow=shadowed-builtin", "--allow=wrong-arity", "--allow=unused-binding"], FAULTY);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_errors() {
+    let broken = script("lint_broken.mk", "let x = 1;\nlet = 2;\n");
+    let output = run(&[&broken]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with(&format!("{}:2:5: expected next token to be Ident(\"\"), got Assign instead\n", broken)), "{}", stderr);
+
+    let output = run(&["no_such_file.mk"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--allow=everything"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unused-binding"));
+    let output = run(&["--fix"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+
+fn script(name: &str, source: &str) -> String {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push(name);
+    fs::write(&path, source).unwrap();
+    return path.to_str().unwrap().to_string();
+}
+
+fn pipe(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_monkeylint")).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    return child.wait_with_output().unwrap();
+}
+
+fn run(args: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_monkeylint")).args(args).output().unwrap();
+}
@@ -0,0 +1,192 @@
+extern crate interpreter;
+use interpreter::compiler::{Compiler, OptLevel, optimizer};
+use interpreter::code::{Opcode, Instructions, make};
+use interpreter::parser::ast::Program;
+use interpreter::parser::{Parser, printer};
+use interpreter::lexer::Lexer;
+use interpreter::evaluator::object::{Object, CompiledFunction};
+use std::rc::Rc;
+
+#[test]
+fn test_constant_folding() {
+    let tests: Vec<(&str, Vec<Object>, Vec<Instructions>)> = vec![
+        ("1 + 2", vec![Object::Integer(3)], vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]),
+        ("2 * (3 + 4) - 10 / 5", vec![Object::Integer(12)], vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]),
+        ("-(5 - 8)", vec![Object::Integer(3)], vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]),
+        ("9223372036854775807 + 1", vec![Object::Integer(i64::MIN)], vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]),
+        ("1 < 2", vec![], vec![make(Opcode::OpTrue, &[]), make(Opcode::OpPop, &[])]),
+        ("(1 > 2) == false", vec![], vec![make(Opcode::OpTrue, &[]), make(Opcode::OpPop, &[])]),
+        ("!5", vec![], vec![make(Opcode::OpFalse, &[]), make(Opcode::OpPop, &[])]),
+        ("\"mon\" + \"key\"", vec![Object::Str(Rc::new("monkey".to_string()))], vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]),
+        ("\"a\" != \"b\"", vec![], vec![make(Opcode::OpTrue, &[]), make(Opcode::OpPop, &[])])
+    ];
+
+    for (input, constants, instructions) in tests {
+        test_optimized(input, constants, instructions);
+    }
+}
+
+#[test]
+fn test_errors_are_not_folded() {
+    let tests: Vec<(&str, Vec<Object>, Vec<Instructions>)> = vec![
+        ("1 / 0", vec![Object::Integer(1), Object::Integer(0)], vec![
+            make(Opcode::OpConstant, &[0]),
+            make(Opcode::OpConstant, &[1]),
+            make(Opcode::OpDivide, &[]),
+            make(Opcode::OpPop, &[])
+        ]),
+        ("1 + true", vec![Object::Integer(1)], vec![
+            make(Opcode::OpConstant, &[0]),
+            make(Opcode::OpTrue, &[]),
+            make(Opcode::OpAdd, &[]),
+            make(Opcode::OpPop, &[])
+        ]),
+        ("-\"a\"", vec![Object::Str(Rc::new("a".to_string()))], vec![
+            make(Opcode::OpConstant, &[0]),
+            make(Opcode::OpMinus, &[]),
+            make(Opcode::OpPop, &[])
+        ])
+    ];
+
+    for (input, constants, instructions) in tests {
+        test_optimized(input, constants, instructions);
+    }
+}
+
+#[test]
+fn test_constant_deduplication() {
+    let input = "let a = 5; let b = \"x\"; [5, \"x\", 5, 2 + 3]";
+    let constants = vec![Object::Integer(5), Object::Str(Rc::new("x".to_string()))];
+    let instructions = vec![
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpSetGlobal, &[0]),
+        make(Opcode::OpConstant, &[1]),
+        make(Opcode::OpSetGlobal, &[1]),
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpConstant, &[1]),
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpArray, &[4]),
+        make(Opcode::OpPop, &[])
+    ];
+
+    test_optimized(input, constants, instructions);
+}
+
+#[test]
+fn test_branch_pruning() {
+    let tests: Vec<(&str, Vec<Object>, Vec<Instructions>)> = vec![
+        ("if (true) { 10 } else { 20 }", vec![Object::Integer(10)], vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]),
+        ("if (1 > 2) { 10 } else { 20 }", vec![Object::Integer(20)], vec![make(Opcode::OpConstant, &[0]), make(Opcode::OpPop, &[])]),
+        ("if (false) { 10 }", vec![], vec![make(Opcode::OpNull, &[]), make(Opcode::OpPop, &[])]),
+        ("if (\"yes\") { }", vec![], vec![make(Opcode::OpNull, &[]), make(Opcode::OpPop, &[])])
+    ];
+
+    for (input, constants, instructions) in tests {
+        test_optimized(input, constants, instructions);
+    }
+}
+
+#[test]
+fn test_unreachable_code_after_return() {
+    let input = "let f = fn(x) { return x; puts(x); x + 1 }; return f(1); puts(2);";
+    let f = CompiledFunction{
+        instructions: concat(vec![
+            make(Opcode::OpGetLocal, &[0]),
+            make(Opcode::OpReturnValue, &[])
+        ]),
+        num_locals: 1,
+        num_params: 1,
+        lines: vec![(0, 1)]
+    };
+    let constants = vec![Object::CompiledFunction(Rc::new(f)), Object::Integer(1)];
+    let instructions = vec![
+        make(Opcode::OpClosure, &[0, 0]),
+        make(Opcode::OpSetGlobal, &[0]),
+        make(Opcode::OpGetGlobal, &[0]),
+        make(Opcode::OpConstant, &[1]),
+        make(Opcode::OpCall, &[1]),
+        make(Opcode::OpReturnValue, &[])
+    ];
+
+    test_optimized(input, constants, instructions);
+}
+
+#[test]
+fn test_constants_are_shared() {
+    let mut compiler = Compiler::with_opt_level(OptLevel::O1);
+    compiler.compile(parse("[1, \"1\", 1, \"1\", 2]")).unwrap();
+    let constants: Vec<Object> = compiler.bytecode().constants.iter().map(|x| x.as_ref().clone()).collect();
+    assert_eq!(constants, vec![Object::Integer(1), Object::Str(Rc::new("1".to_string())), Object::Integer(2)]);
+
+    // The next input of a session shares with the ones before it
+    let mut compiler = compiler.continuation();
+    compiler.compile(parse("\"1\"; 3; 2")).unwrap();
+    let bytecode = compiler.bytecode();
+    assert_eq!(bytecode.constants.len(), 4);
+    let expected = concat(vec![
+        make(Opcode::OpConstant, &[1]),
+        make(Opcode::OpPop, &[]),
+        make(Opcode::OpConstant, &[3]),
+        make(Opcode::OpPop, &[]),
+        make(Opcode::OpConstant, &[2]),
+        make(Opcode::OpPop, &[])
+    ]);
+    assert_eq!(expected, bytecode.instructions);
+}
+
+#[test]
+fn test_optimized_trees() {
+    let tests = vec![
+        ("let x = 1 + 2 * 3; return x; x", "let x = 7;\nreturn x;\n"),
+        ("fn() { return -(2 - 4); 5 }", "fn() {\n    return 2;\n}\n"),
+        ("{\"a\" + \"b\": [!true]}[x + (1 + 1)]", "{\"ab\": [false]}[x + 2]\n"),
+        ("if (1 < 2) { 3 } else { 4 }", "if (true) {\n    3\n} else {\n    4\n}\n")
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(printer::print(&optimizer::optimize(parse(input))), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_unoptimized_output_is_unchanged() {
+    let mut compiler = Compiler::with_opt_level(OptLevel::O0);
+    compiler.compile(parse("1 + 1")).unwrap();
+    let bytecode = compiler.bytecode();
+
+    let expected = concat(vec![
+        make(Opcode::OpConstant, &[0]),
+        make(Opcode::OpConstant, &[1]),
+        make(Opcode::OpAdd, &[]),
+        make(Opcode::OpPop, &[])
+    ]);
+    assert_eq!(expected, bytecode.instructions);
+    assert_eq!(2, bytecode.constants.len());
+}
+
+
+fn test_optimized(input: &str, expected_constants: Vec<Object>, expected_instructions: Vec<Instructions>) {
+    let mut compiler = Compiler::with_opt_level(OptLevel::O1);
+    if let Err(x) = compiler.compile(parse(input)) {
+        panic!("{}", x)
+    }
+    let bytecode = compiler.bytecode();
+
+    assert_eq!(concat(expected_instructions), bytecode.instructions, "{}", input);
+    let constants: Vec<Object> = bytecode.constants.iter().map(|x| x.as_ref().clone()).collect();
+    assert_eq!(expected_constants, constants, "{}", input);
+}
+
+fn concat(instructions: Vec<Instructions>) -> Instructions {
+    return instructions.into_iter().flatten().collect();
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
@@ -1,8 +1,10 @@
+// The table-driven tests build their cases with `vec!`
+#![allow(clippy::useless_vec)]
 extern crate interpreter;
-use interpreter::lexer::token::{Token};
 use interpreter::lexer::{Lexer};
 use interpreter::parser::{Parser};
 use interpreter::parser::ast::*;
+use interpreter::parser::ast::visit;
 use std::{ rc::Rc};
 
 
@@ -24,9 +26,10 @@ fn hash_expr() {
                     (Expr::LiteralExpr(Literal::Str(Rc::new("two".to_string()))), (Expr::LiteralExpr(Literal::Int(2)))),
                     (Expr::LiteralExpr(Literal::Str(Rc::new("three".to_string()))), (Expr::LiteralExpr(Literal::Int(3))))
                 ])
-            )
+            ),
+            Location::default()
         ),
-        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Hash(vec![])))
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Hash(vec![])), Location::default())
     ];
 
 
@@ -60,7 +63,8 @@ fn idx_expr() {
                         Box::new(Expr::LiteralExpr(Literal::Int(1))),
                     )
                 )
-            )
+            ),
+            Location::default()
         )
     ];
 
@@ -100,7 +104,7 @@ fn array_expr() {
         )
     ];
     let expected = vec![
-        Stmt::ExprStmt(Expr::Array(l))
+        Stmt::ExprStmt(Expr::Array(l), Location::default())
     ];
 
     match p.parse_program() {
@@ -130,9 +134,9 @@ fn let_statement() {
 
 
     let expected = vec![
-        Stmt::LetStmt(Ident("x".to_string()), Expr::LiteralExpr(Literal::Int(5))),
-        Stmt::LetStmt(Ident("y".to_string()), Expr::LiteralExpr(Literal::Int(10))),
-        Stmt::LetStmt(Ident("foobar".to_string()), Expr::LiteralExpr(Literal::Int(838383))),
+        Stmt::LetStmt(Ident("x".to_string()), Expr::LiteralExpr(Literal::Int(5)), Location::default()),
+        Stmt::LetStmt(Ident("y".to_string()), Expr::LiteralExpr(Literal::Int(10)), Location::default()),
+        Stmt::LetStmt(Ident("foobar".to_string()), Expr::LiteralExpr(Literal::Int(838383)), Location::default()),
 
     ];
 
@@ -160,9 +164,9 @@ fn return_statement() {
 
 
     let expected = vec![
-        Stmt::ReturnStmt(Expr::LiteralExpr(Literal::Int(5))),
-        Stmt::ReturnStmt(Expr::LiteralExpr(Literal::Int(10))),
-        Stmt::ReturnStmt(Expr::LiteralExpr(Literal::Int(993322))),
+        Stmt::ReturnStmt(Expr::LiteralExpr(Literal::Int(5)), Location::default()),
+        Stmt::ReturnStmt(Expr::LiteralExpr(Literal::Int(10)), Location::default()),
+        Stmt::ReturnStmt(Expr::LiteralExpr(Literal::Int(993322)), Location::default()),
     ];
 
     match p.parse_program() {
@@ -188,7 +192,7 @@ fn ident_expression() {
 
 
     let expected = vec![
-        Stmt::ExprStmt(Expr::IdentExpr(Ident("foobar".to_string())))
+        Stmt::ExprStmt(Expr::IdentExpr(Ident("foobar".to_string())), Location::default())
     ];
 
     match p.parse_program() {
@@ -217,10 +221,10 @@ fn literal_expression() {
 
 
     let expected = vec![
-        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Int(5))),
-        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Bool(true))),
-        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Bool(false))),
-        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Str(Rc::new("hello world".to_string()))))
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Int(5)), Location::default()),
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Bool(true)), Location::default()),
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Bool(false)), Location::default()),
+        Stmt::ExprStmt(Expr::LiteralExpr(Literal::Str(Rc::new("hello world".to_string()))), Location::default())
     ];
 
     match p.parse_program() {
@@ -247,9 +251,9 @@ fn prefix_expr() {
 
 
     let expected = vec![
-        Stmt::ExprStmt(Expr::Prefix(Prefix::Not, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Prefix(Prefix::Minus, Box::new(Expr::LiteralExpr(Literal::Int(15))))),
-        Stmt::ExprStmt(Expr::Prefix(Prefix::Plus, Box::new(Expr::LiteralExpr(Literal::Int(7)))))
+        Stmt::ExprStmt(Expr::Prefix(Prefix::Not, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Prefix(Prefix::Minus, Box::new(Expr::LiteralExpr(Literal::Int(15)))), Location::default()),
+        Stmt::ExprStmt(Expr::Prefix(Prefix::Plus, Box::new(Expr::LiteralExpr(Literal::Int(7)))), Location::default())
     ];
 
     match p.parse_program() {
@@ -281,14 +285,14 @@ fn infix_expr() {
 
 
     let expected = vec![
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Plus, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Minus, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Multiply, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Divide, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::GT, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::LT, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Equal, Box::new(Expr::LiteralExpr(Literal::Int(5))))),
-        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::NotEqual, Box::new(Expr::LiteralExpr(Literal::Int(5)))))
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Plus, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Minus, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Multiply, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Divide, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::GT, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::LT, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::Equal, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default()),
+        Stmt::ExprStmt(Expr::Infix(Box::new(Expr::LiteralExpr(Literal::Int(5))), Infix::NotEqual, Box::new(Expr::LiteralExpr(Literal::Int(5)))), Location::default())
     ];
 
     match p.parse_program() {
@@ -324,6 +328,7 @@ fn grouped_expr() {
                     Box::new(Expr::LiteralExpr(Literal::Int(5)))
                 )),
                 ),
+            Location::default()
         ),
         Stmt::ExprStmt(
             Expr::Prefix(
@@ -333,7 +338,8 @@ fn grouped_expr() {
                     Infix::Plus,
                     Box::new(Expr::LiteralExpr(Literal::Int(5)))
                 ))
-            )
+            ),
+            Location::default()
         )
 
     ];
@@ -364,17 +370,19 @@ fn if_expr() {
         Stmt::ExprStmt(
             Expr::If(
                 Box::new(Expr::Infix(Box::new(Expr::IdentExpr(Ident("x".to_string()))), Infix::LT, Box::new(Expr::IdentExpr(Ident("y".to_string()))))),
-                vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("x".to_string())))],
+                vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("x".to_string())), Location::default())],
                 None
             ),
+            Location::default()
             
         ),
         Stmt::ExprStmt(
             Expr::If(
                 Box::new(Expr::Infix(Box::new(Expr::IdentExpr(Ident("x".to_string()))), Infix::LT, Box::new(Expr::IdentExpr(Ident("y".to_string()))))),
-                vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("x".to_string())))],
-                Some(vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("y".to_string())))]),
+                vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("x".to_string())), Location::default())],
+                Some(vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("y".to_string())), Location::default())]),
             ),
+            Location::default()
             
         )
 
@@ -409,9 +417,10 @@ fn fn_expr() {
                     Ident("y".to_string())
                 ]),
                 Rc::new(vec![
-                    Stmt::ExprStmt(Expr::Infix(Box::new(Expr::IdentExpr(Ident("x".to_string()))), Infix::Plus, Box::new(Expr::IdentExpr(Ident("y".to_string())))))
+                    Stmt::ExprStmt(Expr::Infix(Box::new(Expr::IdentExpr(Ident("x".to_string()))), Infix::Plus, Box::new(Expr::IdentExpr(Ident("y".to_string())))), Location::default())
                 ]),
-            )
+            ),
+            Location::default()
         ),
     ];
 
@@ -444,7 +453,8 @@ fn call_expr() {
                     Expr::IdentExpr(Ident("a".to_string())),
                     Expr::IdentExpr(Ident("b".to_string()))
                 ]
-            )
+            ),
+            Location::default()
         ),
     ];
 
@@ -457,4 +467,71 @@ fn call_expr() {
         Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
     }
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn error_spans() {
+    let input = "let x = 1;\nlet y = x +;\n  let = 3;\nlet z = f(1, 2;";
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let count = match p.parse_program() {
+        Ok(_) => panic!("expected parse errors"),
+        Err(errors) => errors.len()
+    };
+    assert_eq!(p.error_spans.len(), count);
+    let lines: Vec<(usize, usize)> = p.error_spans.iter().map(|x| (x.line, x.column)).collect();
+    // Each error is at the token it is about, not where its statement starts
+    assert_eq!(lines, vec![(2, 12), (3, 7), (3, 7), (4, 15), (4, 15)]);
+}
+
+#[test]
+fn statement_and_block_ends() {
+    let input = "let f = fn(a) {\n  if (a) { 1 } else {}\n};\nf(1) // one";
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = p.parse_program().unwrap();
+    let mut locations = Locations::default();
+    locations.visit_block(&program);
+    let lines = |spans: Vec<Span>| spans.iter().map(|x| (x.line, x.column)).collect::<Vec<(usize, usize)>>();
+    assert_eq!(lines(locations.0.iter().map(|x| x.start).collect()), vec![(1, 1), (2, 3), (2, 12), (4, 1)]);
+    assert_eq!(lines(locations.0.iter().map(|x| x.end).collect()), vec![(3, 2), (2, 22), (2, 12), (4, 4)]);
+    assert_eq!(lines(p.block_ends.clone()), vec![(2, 14), (2, 22), (3, 1)]);
+    assert_eq!(p.comments().len(), 1);
+}
+
+#[test]
+fn statement_locations() {
+    let input = "let f = fn(a) {\n  if (a) { 1 } else {}\n};\nf(1)";
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    let program = p.parse_program().unwrap();
+    let start = |stmt: &Stmt| (stmt.location().start.line, stmt.location().start.column);
+    assert_eq!(start(&program[0]), (1, 1));
+    assert_eq!(start(&program[1]), (4, 1));
+    let body = match &program[0] {
+        Stmt::LetStmt(_, Expr::Fn(_, body), _) => body.clone(),
+        x => panic!("expected a function, got {:?}", x)
+    };
+    assert_eq!(start(&body[0]), (2, 3));
+    match &body[0] {
+        Stmt::ExprStmt(Expr::If(_, consequence, _), _) => assert_eq!(start(&consequence[0]), (2, 12)),
+        x => panic!("expected an if, got {:?}", x)
+    }
+
+    // A copy keeps them, and statements that were not parsed are at line 0
+    let copy = program.clone();
+    assert_eq!(start(&copy[0]), (1, 1));
+    assert_eq!(start(&Stmt::ExprStmt(Expr::IdentExpr(Ident("f".to_string())), Location::default())), (0, 0));
+}
+
+
+// Where every statement is, in the order the walk meets them
+#[derive(Default)]
+struct Locations(Vec<Location>);
+
+impl<'a> Visitor<'a> for Locations {
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        self.0.push(stmt.location());
+        visit::walk_stmt(self, stmt);
+    }
+}
@@ -0,0 +1,234 @@
+extern crate interpreter;
+use interpreter::lexer::Lexer;
+use interpreter::parser::Parser;
+use interpreter::parser::ast::*;
+use interpreter::parser::printer::{self, Style};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+#[test]
+fn test_canonical_layout() {
+    let tests = vec![
+        ("let x=1;x", "let x = 1;\nx\n"),
+        ("let x = 1\nreturn x", "let x = 1;\nreturn x;\n"),
+        ("puts(1)   puts(2);", "puts(1);\nputs(2)\n"),
+        ("let add = fn(a,b){a+b;};", "let add = fn(a, b) {\n    a + b\n};\n"),
+        ("fn(){}", "fn() {}\n"),
+        (
+            "if (x > 1) { let y = x * 2; y } else { if (x) { 1 } }",
+            "if (x > 1) {\n    let y = x * 2;\n    y\n} else {\n    if (x) {\n        1\n    }\n}\n"
+        ),
+        ("[1,2,[3]][0]", "[1, 2, [3]][0]\n"),
+        ("{\"a\":1,true:fn(x){x}}", "{\"a\": 1, true: fn(x) {\n    x\n}}\n"),
+        ("{}; []", "{};\n[]\n")
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(printer::print(&parse(input)), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_minimal_parentheses() {
+    let tests = vec![
+        ("(1 + 2) + 3", "1 + 2 + 3"),
+        ("1 + (2 + 3)", "1 + (2 + 3)"),
+        ("1 - (2 - 3)", "1 - (2 - 3)"),
+        ("(1 * 2) + (3 * 4)", "1 * 2 + 3 * 4"),
+        ("(1 + 2) * (3 + 4)", "(1 + 2) * (3 + 4)"),
+        ("(a < b) == (c > d)", "a < b == c > d"),
+        ("a == (b == c)", "a == (b == c)"),
+        ("-(a + b)", "-(a + b)"),
+        ("-(a[0])", "-a[0]"),
+        ("(-a)[0]", "(-a)[0]"),
+        ("!(-(f(x)))", "!-f(x)"),
+        ("(a + b)(c)", "(a + b)(c)"),
+        ("(f(a))(b)[c](d)", "f(a)(b)[c](d)"),
+        ("(fn(x) { x })(1)", "fn(x) {\n    x\n}(1)"),
+        ("a * (-b)", "a * -b"),
+        ("a - (b * c) / d", "a - b * c / d"),
+        ("a - (b * c / d)", "a - b * c / d"),
+        ("a / (b * c)", "a / (b * c)")
+    ];
+
+    for (input, expected) in tests {
+        let program = parse(input);
+        assert_eq!(program[0].to_string(), expected, "{}", input);
+        assert_eq!(parse(expected), program, "{}", input);
+    }
+}
+
+#[test]
+fn test_round_trip_corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let source = fs::read_to_string(&path).unwrap();
+        let program = parse(&source);
+        let printed = printer::print(&program);
+        assert_eq!(parse(&printed), program, "{}", path.display());
+        assert_eq!(printer::print(&parse(&printed)), printed, "{}", path.display());
+    }
+}
+
+#[test]
+fn test_round_trip_random_programs() {
+    // The same programs every run, so that a failure can be looked into
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    for _ in 0..2000 {
+        let program: Program = (0..random.below(4) + 1).map(|_| statement(&mut random, 3)).collect();
+        let printed = printer::print(&program);
+        assert_eq!(parse(&printed), program, "{}", printed);
+    }
+}
+
+#[test]
+fn test_comments_and_blank_lines() {
+    let tests = vec![
+        // Leading and trailing comments, and a blank line kept from three
+        ("// adds\nlet add=fn(a,b){a+b};// done\n\n\n\nadd(1,2)", "// adds\nlet add = fn(a, b) {\n    a + b\n}; // done\n\nadd(1, 2)\n"),
+        // Comments in blocks, empty ones too
+        ("let f = fn() {\n// later\n};", "let f = fn() {\n    // later\n};\n"),
+        ("if (x) {\n  1 // one\n\n  // two\n  2\n  // end\n}", "if (x) {\n    1; // one\n\n    // two\n    2\n    // end\n}\n"),
+        // Comments inside an expression go after its statement
+        ("let xs = [1, // one\n  2];\nxs", "let xs = [1, 2];\n// one\nxs\n"),
+        // And the ones at the end stay there
+        ("x\n\n// bye\n// now", "x\n\n// bye\n// now\n"),
+        ("// just a comment", "// just a comment\n")
+    ];
+
+    for (input, expected) in tests {
+        assert_eq!(printer::format(input, Style::default()).unwrap(), expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_styles() {
+    let input = "let f = fn(a) { if (a) { [first(a), rest(a)] } }; let xs = [1, 2, 3]; puts({\"a\": 1});";
+
+    let style = Style{ indent: 2, width: 100 };
+    assert_eq!(printer::format(input, style).unwrap(), "let f = fn(a) {\n  if (a) {\n    [first(a), rest(a)]\n  }\n};\nlet xs = [1, 2, 3];\nputs({\"a\": 1})\n");
+
+    // Lists that do not fit are broken up, as are the lists around them
+    let style = Style{ indent: 4, width: 24 };
+    assert_eq!(
+        printer::format(input, style).unwrap(),
+        "let f = fn(a) {\n    if (a) {\n        [\n            first(a),\n            rest(a)\n        ]\n    }\n};\nlet xs = [1, 2, 3];\nputs({\"a\": 1})\n"
+    );
+    assert_eq!(printer::format("let xs = [1, 2, 3];", Style{ indent: 2, width: 12 }).unwrap(), "let xs = [\n  1,\n  2,\n  3\n];\n");
+    assert_eq!(printer::format("puts(f(1, 2, 3), 4)", Style{ indent: 4, width: 16 }).unwrap(), "puts(\n    f(1, 2, 3),\n    4\n)\n");
+    assert_eq!(printer::format("map(xs, fn(x) { x * 2 })", Style{ indent: 4, width: 24 }).unwrap(), "map(xs, fn(x) {\n    x * 2\n})\n");
+}
+
+#[test]
+fn test_if_statements_need_no_semicolon() {
+    let tests = vec![
+        ("if (a) { 1 }; let b = 2;", "if (a) {\n    1\n}\nlet b = 2;\n"),
+        ("if (a) { 1 }; puts(b);", "if (a) {\n    1\n}\nputs(b)\n"),
+        // Unless the next statement would carry on the expression
+        ("if (a) { 1 }; -b", "if (a) {\n    1\n};\n-b\n"),
+        ("if (a) { 1 }; (b)", "if (a) {\n    1\n}\nb\n"),
+        ("if (a) { 1 }; [b]", "if (a) {\n    1\n};\n[b]\n"),
+        ("if (a) { 1 }; // one\n+b", "if (a) {\n    1\n}; // one\n+b\n")
+    ];
+
+    for (input, expected) in tests {
+        let formatted = printer::format(input, Style::default()).unwrap();
+        assert_eq!(formatted, expected, "{}", input);
+        assert_eq!(parse(&formatted), parse(input), "{}", input);
+    }
+}
+
+#[test]
+fn test_format_is_stable() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let source = fs::read_to_string(&path).unwrap();
+        for style in [Style::default(), Style{ indent: 2, width: 30 }] {
+            let formatted = printer::format(&source, style).unwrap();
+            assert_eq!(parse(&formatted), parse(&source), "{}", path.display());
+            assert_eq!(printer::format(&formatted, style).unwrap(), formatted, "{}", path.display());
+        }
+    }
+
+    let errors = printer::format("let x = 1;\nlet = 2;", Style::default()).unwrap_err();
+    assert_eq!(errors[0].0.line, 2);
+}
+
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
+
+// A xorshift generator, good enough to pick trees with
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0 % n;
+    }
+
+    fn pick<'a, T>(&mut self, xs: &'a [T]) -> &'a T {
+        return &xs[self.below(xs.len() as u64) as usize];
+    }
+}
+
+fn ident(random: &mut Random) -> Ident {
+    return Ident(random.pick(&["a", "b", "count", "add_one"]).to_string());
+}
+
+fn block(random: &mut Random, depth: u32) -> Block {
+    return (0..random.below(3)).map(|_| statement(random, depth)).collect();
+}
+
+fn statement(random: &mut Random, depth: u32) -> Stmt {
+    return match random.below(4) {
+        0 => Stmt::LetStmt(ident(random), expression(random, depth), Location::default()),
+        1 => Stmt::ReturnStmt(expression(random, depth), Location::default()),
+        _ => Stmt::ExprStmt(expression(random, depth), Location::default())
+    }
+}
+
+// Any tree the parser can make, up to depth levels of nesting
+fn expression(random: &mut Random, depth: u32) -> Expr {
+    let leaves = 4;
+    let choice = if depth == 0 { random.below(leaves) } else { random.below(leaves + 9) };
+    let depth = depth.saturating_sub(1);
+    return match choice {
+        0 => Expr::IdentExpr(ident(random)),
+        1 => Expr::LiteralExpr(Literal::Int(random.below(100) as i64)),
+        2 => Expr::LiteralExpr(Literal::Bool(random.below(2) == 0)),
+        3 => Expr::LiteralExpr(Literal::Str(Rc::new(random.pick(&["", "monkey", "a b"]).to_string()))),
+        4 => Expr::Prefix(random.pick(&[Prefix::Plus, Prefix::Minus, Prefix::Not]).clone(), Box::new(expression(random, depth))),
+        5 | 6 => {
+            let operators = [Infix::Plus, Infix::Minus, Infix::Divide, Infix::Multiply, Infix::GT, Infix::LT, Infix::Equal, Infix::NotEqual];
+            let operator = random.pick(&operators).clone();
+            Expr::Infix(Box::new(expression(random, depth)), operator, Box::new(expression(random, depth)))
+        },
+        7 => {
+            let alternative = if random.below(2) == 0 { Some(block(random, depth)) } else { None };
+            Expr::If(Box::new(expression(random, depth)), block(random, depth), alternative)
+        },
+        8 => {
+            let params = (0..random.below(3)).map(|_| ident(random)).collect();
+            Expr::Fn(Rc::new(params), Rc::new(block(random, depth)))
+        },
+        9 => Expr::Call(Box::new(expression(random, depth)), (0..random.below(3)).map(|_| expression(random, depth)).collect()),
+        10 => Expr::Array((0..random.below(3)).map(|_| expression(random, depth)).collect()),
+        11 => Expr::IndexExpr(Box::new(expression(random, depth)), Box::new(expression(random, depth))),
+        _ => {
+            let pairs = (0..random.below(3)).map(|_| (expression(random, depth), expression(random, depth))).collect();
+            Expr::LiteralExpr(Literal::Hash(pairs))
+        }
+    }
+}
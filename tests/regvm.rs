@@ -0,0 +1,162 @@
+extern crate interpreter;
+use interpreter::parser::ast::Program;
+use interpreter::parser::Parser;
+use interpreter::lexer::Lexer;
+use interpreter::evaluator::object::Object;
+use interpreter::code::Opcode;
+use interpreter::regvm::{VM, compiler::{Compiler, CompiledProgram}, instruction::Instruction};
+use std::rc::Rc;
+
+#[test]
+fn test_main_uses_temporaries() {
+    let program = compile("let a = 1; a + 2");
+    let expected = vec![
+        Instruction::LoadConst{ dst: 0, idx: 0 },
+        Instruction::SetGlobal{ idx: 0, src: 0 },
+        Instruction::GetGlobal{ dst: 1, idx: 0 },
+        Instruction::LoadConst{ dst: 2, idx: 1 },
+        Instruction::Binary{ op: Opcode::OpAdd, dst: 0, left: 1, right: 2 },
+        Instruction::Pop{ src: 0 }
+    ];
+    assert_eq!(expected, program.main.instructions);
+    assert_eq!(3, program.main.num_registers);
+}
+
+#[test]
+fn test_locals_are_used_in_place() {
+    // Parameters and locals come first, temporaries follow them
+    let program = compile("fn(a, b) { let c = a * b; c - a }");
+    let expected = vec![
+        Instruction::Binary{ op: Opcode::OpMultiply, dst: 3, left: 0, right: 1 },
+        Instruction::Move{ dst: 2, src: 3 },
+        Instruction::Binary{ op: Opcode::OpSubtract, dst: 3, left: 2, right: 0 },
+        Instruction::Return{ src: 3 }
+    ];
+    assert_eq!(expected, program.functions[0].instructions);
+    assert_eq!(4, program.functions[0].num_registers);
+    assert_eq!(2, program.functions[0].num_params);
+}
+
+#[test]
+fn test_call_arguments_are_consecutive() {
+    let program = compile("fn(f, x) { f(x, 1) }");
+    let expected = vec![
+        Instruction::Move{ dst: 3, src: 0 },
+        Instruction::Move{ dst: 4, src: 1 },
+        Instruction::LoadConst{ dst: 5, idx: 0 },
+        Instruction::Call{ dst: 2, func: 3, argc: 2 },
+        Instruction::Return{ src: 2 }
+    ];
+    assert_eq!(expected, program.functions[0].instructions);
+}
+
+#[test]
+fn test_conditionals() {
+    test_run("if (true) { 10 }", &Object::Integer(10));
+    test_run("if (false) { 10 } else { 20 }", &Object::Integer(20));
+    test_run("if (1 < 2) { 10 } else { 20 }", &Object::Integer(10));
+    test_run("if (1 > 2) { 10 }", &Object::Null);
+    test_run("if ((if (false) { 10 })) { 10 } else { 20 }", &Object::Integer(20));
+    test_run("let x = if (true) { let y = 5; y * 2 }; x", &Object::Integer(10));
+}
+
+#[test]
+fn test_functions_and_closures() {
+    test_run("let f = fn(a, b) { a + b }; f(1, 2)", &Object::Integer(3));
+    test_run("let f = fn() { }; f()", &Object::Null);
+    test_run("let f = fn() { let a = 1; }; f()", &Object::Null);
+    test_run("let f = fn(x) { return x; 99 }; f(5)", &Object::Integer(5));
+    test_run("let adder = fn(a) { fn(b) { a + b } }; adder(2)(3)", &Object::Integer(5));
+    test_run("let f = fn(a) { fn(b) { fn(c) { a + b + c } } }; f(1)(2)(3)", &Object::Integer(6));
+    test_run("let g = 10; let f = fn() { let l = 5; fn() { g + l } }; f()()", &Object::Integer(15));
+    test_run("return 7; 8", &Object::Integer(7));
+}
+
+#[test]
+fn test_recursion() {
+    test_run("let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)", &Object::Integer(610));
+    test_run("let wrapper = fn() { let count = fn(x) { if (x == 0) { 0 } else { 1 + count(x - 1) } }; count(50) }; wrapper()", &Object::Integer(50));
+    // Frames live on the heap, so recursion is limited by MAX_CALL_DEPTH rather than the native stack
+    test_run("let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + n) } }; sum(1023, 0)", &Object::Integer(523776));
+    test_run_error("let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + n) } }; sum(1024, 0)", "stack overflow");
+}
+
+#[test]
+fn test_collections_and_builtins() {
+    test_run("[1, 2 * 2, 3 + 3][1]", &Object::Integer(4));
+    test_run("{\"a\": 1, 2: 3}[2]", &Object::Integer(3));
+    test_run("len(push([1], 2))", &Object::Integer(2));
+    test_run("first(rest([1, 2, 3]))", &Object::Integer(2));
+    test_run("let map = fn(arr, f) { if (len(arr) == 0) { [] } else { push(map(rest(arr), f), f(first(arr))) } }; map([1, 2], fn(x) { x * 10 })",
+        &Object::Array(Rc::new(vec![Object::Integer(20), Object::Integer(10)])));
+}
+
+#[test]
+fn test_runtime_errors() {
+    test_run_error("1 + true", "type mismatch");
+    test_run_error("1 / 0", "division by zero");
+    test_run_error("5()", "Not callable: 5");
+    test_run_error("fn(a) { a }()", "wrong number of arguments: want=1, got=0");
+    test_run_error("{[1]: 2}", "invalid key object for hash");
+    test_run_error("x", "unknown identifier: x");
+}
+
+
+#[test]
+fn test_state_across_inputs() {
+    let inputs = vec![
+        ("let x = 2;", None),
+        ("let f = fn(a) { a * x };", None),
+        ("f(3)", Some(Object::Integer(6))),
+        // A new binding does not change what f captured
+        ("let x = 10; f(3)", Some(Object::Integer(6))),
+        ("let g = fn() { f(1) + x }; g()", Some(Object::Integer(12)))
+    ];
+
+    let mut compiler = Compiler::new();
+    let mut globals = vec![];
+    for (input, expected) in inputs {
+        let mut next = compiler.continuation();
+        next.compile(parse(input)).unwrap();
+        let mut vm = VM::new(next.program());
+        vm.set_globals(globals);
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped().cloned(), expected, "{}", input);
+        globals = vm.into_globals();
+        compiler = next;
+    }
+}
+
+fn test_run(input: &str, expected: &Object) {
+    let mut vm = VM::new(compile(input));
+    if let Err(x) = vm.run() {
+        panic!("{}: {}", input, x)
+    }
+    assert_eq!(Some(expected), vm.last_popped(), "{}", input);
+}
+
+fn test_run_error(input: &str, expected: &str) {
+    let mut compiler = Compiler::new();
+    let result = compiler.compile(parse(input)).and_then(|_| VM::new(compiler.program()).run());
+    match result {
+        Ok(_) => panic!("expected an error from {}", input),
+        Err(x) => assert_eq!(expected, x.to_string())
+    }
+}
+
+fn compile(input: &str) -> CompiledProgram {
+    let mut compiler = Compiler::new();
+    if let Err(x) = compiler.compile(parse(input)) {
+        panic!("{}", x)
+    }
+    return compiler.program();
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
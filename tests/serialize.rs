@@ -0,0 +1,142 @@
+extern crate interpreter;
+use interpreter::compiler::{Compiler, ByteCode, serialize::{MAGIC, VERSION}};
+use interpreter::parser::{Parser, ast::*};
+use interpreter::lexer::Lexer;
+use interpreter::vm::VM;
+use std::rc::Rc;
+
+#[test]
+fn test_round_trip() {
+    let inputs = vec![
+        "1 + 2",
+        "let greeting = \"hello\"; greeting + \" world\"",
+        "let add = fn(a, b) {\n  a + b\n};\nadd(-1, 9223372036854775807)",
+        "let counter = fn(x) { if (x > 100) { true } else { counter(x + 1) } }; counter(0)",
+        "let adder = fn(a) { fn(b) { a + b } }; adder(2)(3)",
+        "len(push([1, 2], 3)) + {\"a\": 4}[\"a\"]",
+        ""
+    ];
+
+    for input in inputs {
+        let bytecode = compile(input);
+        let decoded = round_trip(&bytecode);
+        assert_eq!(bytecode, decoded, "{}", input);
+        assert_eq!(run(bytecode), run(decoded), "{}", input);
+    }
+}
+
+#[test]
+fn test_without_debug_section() {
+    // let f = fn(x) { x }; f(1), built rather than parsed so that it has no lines
+    let name = |x: &str| Ident(x.to_string());
+    let body = vec![Stmt::ExprStmt(Expr::IdentExpr(name("x")), Location::default())];
+    let program = vec![
+        Stmt::LetStmt(name("f"), Expr::Fn(Rc::new(vec![name("x")]), Rc::new(body)), Location::default()),
+        Stmt::ExprStmt(Expr::Call(Box::new(Expr::IdentExpr(name("f"))), vec![Expr::LiteralExpr(Literal::Int(1))]), Location::default())
+    ];
+    let mut compiler = Compiler::new();
+    compiler.compile(program).unwrap();
+    let bytecode = compiler.bytecode();
+    assert!(bytecode.lines.is_empty());
+
+    let bytes = encode(&bytecode);
+    assert_eq!(0, bytes[6]);
+    assert_eq!(bytecode, ByteCode::read_from(&mut bytes.as_slice()).unwrap());
+}
+
+#[test]
+fn test_header() {
+    let bytes = encode(&compile("1"));
+    assert_eq!(&MAGIC[..], &bytes[..4]);
+    assert_eq!(VERSION.to_be_bytes(), bytes[4..6]);
+}
+
+#[test]
+fn test_rejects_bad_files() {
+    let bytes = encode(&compile("let f = fn(x) { x * 2 }; f(\"a\")"));
+
+    let mut other_version = bytes.clone();
+    other_version[4..6].copy_from_slice(&(VERSION + 1).to_be_bytes());
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+
+    let mut bad_flags = bytes.clone();
+    bad_flags[6] = 0x80;
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+
+    let tests = vec![
+        (other_version, format!("unsupported bytecode version {}, expected {}", VERSION + 1, VERSION)),
+        (bad_magic, "not a bytecode file".to_string()),
+        (bad_flags, "unknown bytecode flags 0x80".to_string()),
+        (trailing, "trailing data after bytecode".to_string()),
+        (bytes[..bytes.len() - 1].to_vec(), "unexpected end of bytecode file".to_string()),
+        (bytes[..3].to_vec(), "not a bytecode file".to_string())
+    ];
+
+    for (input, expected) in tests {
+        match ByteCode::read_from(&mut input.as_slice()) {
+            Ok(_) => panic!("expected {:?}", expected),
+            Err(x) => assert_eq!(expected, x.to_string())
+        }
+    }
+
+    // Line tables that do not fit the code they are for
+    let tests = vec![
+        (vec![(0, 0)], "line table entry out of range: line 0 at 0"),
+        (vec![(0, 1), (8, 2)], "line table entry out of range: line 2 at 8"),
+        (vec![(4, 1), (0, 2)], "line table entry out of range: line 2 at 0"),
+        (vec![(0, 1), (0, 2)], "line table entry out of range: line 2 at 0")
+    ];
+
+    for (lines, expected) in tests {
+        let mut bytecode = compile("1; 2");
+        bytecode.lines = lines;
+        match ByteCode::read_from(&mut encode(&bytecode).as_slice()) {
+            Ok(_) => panic!("expected {:?}", expected),
+            Err(x) => assert_eq!(expected, x.to_string())
+        }
+    }
+}
+
+
+fn run(bytecode: ByteCode) -> String {
+    let mut vm = VM::new(bytecode);
+    if let Err(x) = vm.run() {
+        return format!("ERROR {}", x);
+    }
+    return vm.last_popped_stack_elem().to_string();
+}
+
+fn round_trip(bytecode: &ByteCode) -> ByteCode {
+    let bytes = encode(bytecode);
+    return match ByteCode::read_from(&mut bytes.as_slice()) {
+        Ok(x) => x,
+        Err(x) => panic!("{}", x)
+    }
+}
+
+fn encode(bytecode: &ByteCode) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytecode.write_to(&mut bytes).unwrap();
+    return bytes;
+}
+
+fn compile(input: &str) -> ByteCode {
+    let mut compiler = Compiler::new();
+    if let Err(x) = compiler.compile(parse(input)) {
+        panic!("{}", x)
+    }
+    return compiler.bytecode();
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
@@ -0,0 +1,177 @@
+extern crate interpreter;
+use interpreter::code::{Opcode, Instructions, make};
+use interpreter::compiler::{Compiler, ByteCode, OptLevel};
+use interpreter::evaluator::object::{Object, CompiledFunction};
+use interpreter::lexer::Lexer;
+use interpreter::parser::ast::Program;
+use interpreter::parser::Parser;
+use interpreter::vm::VM;
+use interpreter::vm::verifier::{verify, verify_continuation, VerifyError, VerifyErrorKind};
+use std::rc::Rc;
+
+#[test]
+fn test_compiled_programs_verify() {
+    let inputs = vec![
+        "",
+        "if (1 > 2) { 10 } else { 20 }; if (false) { 1 }",
+        "let x = [1, 2, 3]; let h = {\"a\": x}; h[\"a\"][1]",
+        "let f = fn(a) { let b = a * 2; fn(c) { if (c) { return b; } a } }; f(1)(false)",
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(10)",
+        "let wrapper = fn() { let inner = fn(x) { if (x == 0) { 0 } else { inner(x - 1) } }; inner(3) }; wrapper()",
+        "puts(len(\"four\"), first([1]), rest([1, 2]))",
+        "return 5; 6"
+    ];
+
+    for input in inputs {
+        for opt_level in [OptLevel::O0, OptLevel::O2] {
+            let bytecode = compile(input, opt_level);
+            assert_eq!(Ok(()), verify(&bytecode), "{:?} {}", opt_level, input);
+        }
+    }
+}
+
+#[test]
+fn test_malformed_main() {
+    let tests = vec![
+        (vec![make(Opcode::OpConstant, &[5]), make(Opcode::OpPop, &[])], 0, VerifyErrorKind::ConstantOutOfRange(5)),
+        (vec![make(Opcode::OpNull, &[]), vec![255]], 1, VerifyErrorKind::UnknownOpcode(255)),
+        (vec![make(Opcode::OpConstant, &[0])[..2].to_vec()], 0, VerifyErrorKind::TruncatedInstruction(Opcode::OpConstant)),
+        (vec![make(Opcode::OpJump, &[2]), make(Opcode::OpConstant, &[0])], 0, VerifyErrorKind::JumpIntoInstruction(2)),
+        (vec![make(Opcode::OpJump, &[100])], 0, VerifyErrorKind::JumpOutOfRange(100)),
+        (vec![make(Opcode::OpNull, &[]), make(Opcode::OpAdd, &[])], 1, VerifyErrorKind::StackUnderflow(Opcode::OpAdd)),
+        (vec![make(Opcode::OpPop, &[])], 0, VerifyErrorKind::StackUnderflow(Opcode::OpPop)),
+        (vec![make(Opcode::OpGetLocal, &[0])], 0, VerifyErrorKind::LocalOutOfRange(0)),
+        (vec![make(Opcode::OpGetGlobal, &[3])], 0, VerifyErrorKind::GlobalNeverSet(3)),
+        (vec![make(Opcode::OpGetBuiltin, &[6])], 0, VerifyErrorKind::BuiltinOutOfRange(6)),
+        (vec![make(Opcode::OpNull, &[]), make(Opcode::OpAddConst, &[3])], 1, VerifyErrorKind::ConstantOutOfRange(3)),
+        (vec![make(Opcode::OpNull, &[]), make(Opcode::OpJumpNotGreater, &[0])], 1, VerifyErrorKind::StackUnderflow(Opcode::OpJumpNotGreater)),
+        (vec![make(Opcode::OpClosure, &[0, 0])], 0, VerifyErrorKind::NotAFunction(0)),
+        (vec![make(Opcode::OpReturn, &[])], 0, VerifyErrorKind::ReturnOutsideFunction),
+        (
+            vec![
+                // 0000
+                make(Opcode::OpTrue, &[]),
+                // 0001
+                make(Opcode::OpJumpNotTruthy, &[7]),
+                // 0004
+                make(Opcode::OpConstant, &[0]),
+                // 0007
+                make(Opcode::OpNull, &[])
+            ],
+            7,
+            VerifyErrorKind::StackMismatch{ expected: 1, found: 0 }
+        )
+    ];
+
+    for (instructions, offset, kind) in tests {
+        let bytecode = ByteCode{ instructions: concat(instructions), constants: vec![Rc::new(Object::Integer(1))], lines: vec![] };
+        assert_eq!(Err(VerifyError{ function: None, offset, kind }), verify(&bytecode));
+    }
+}
+
+#[test]
+fn test_malformed_functions() {
+    let closure = vec![make(Opcode::OpClosure, &[0, 0]), make(Opcode::OpPop, &[])];
+    let tests = vec![
+        (vec![make(Opcode::OpNull, &[])], 0, 0, closure.clone(), 1, VerifyErrorKind::MissingReturn),
+        (vec![make(Opcode::OpGetFree, &[0]), make(Opcode::OpReturnValue, &[])], 0, 0, closure.clone(), 0, VerifyErrorKind::FreeOutOfRange(0)),
+        (vec![make(Opcode::OpSetLocal, &[0]), make(Opcode::OpReturn, &[])], 1, 0, closure.clone(), 0, VerifyErrorKind::StackUnderflow(Opcode::OpSetLocal)),
+        (vec![make(Opcode::OpGetLocal, &[1]), make(Opcode::OpReturnValue, &[])], 1, 1, closure.clone(), 0, VerifyErrorKind::LocalOutOfRange(1)),
+        (vec![make(Opcode::OpReturn, &[])], 1, 2, closure.clone(), 0, VerifyErrorKind::MoreParamsThanLocals{ params: 2, locals: 1 })
+    ];
+
+    for (body, num_locals, num_params, main, offset, kind) in tests {
+        let func = CompiledFunction{ instructions: concat(body), num_locals, num_params, lines: vec![] };
+        let bytecode = ByteCode{
+            instructions: concat(main),
+            constants: vec![Rc::new(Object::CompiledFunction(Rc::new(func)))],
+            lines: vec![]
+        };
+        assert_eq!(Err(VerifyError{ function: Some(0), offset, kind }), verify(&bytecode));
+    }
+}
+
+#[test]
+fn test_free_count_mismatch() {
+    let func = CompiledFunction{ instructions: make(Opcode::OpReturn, &[]), ..CompiledFunction::default() };
+    let bytecode = ByteCode{
+        instructions: concat(vec![
+            // 0000
+            make(Opcode::OpNull, &[]),
+            // 0001
+            make(Opcode::OpClosure, &[0, 1]),
+            // 0005
+            make(Opcode::OpClosure, &[0, 0])
+        ]),
+        constants: vec![Rc::new(Object::CompiledFunction(Rc::new(func)))],
+        lines: vec![]
+    };
+    let kind = VerifyErrorKind::FreeCountMismatch{ function: 0, expected: 1, found: 0 };
+    assert_eq!(Err(VerifyError{ function: None, offset: 5, kind }), verify(&bytecode));
+}
+
+#[test]
+fn test_vm_rejects_unverified_bytecode() {
+    let bytecode = ByteCode{ instructions: make(Opcode::OpConstant, &[0]), constants: vec![], lines: vec![] };
+    match VM::new_verified(bytecode) {
+        Ok(_) => panic!("expected a verification error"),
+        Err(x) => assert_eq!("invalid bytecode in main at 0000: constant 0 out of range", x.to_string())
+    }
+
+    let mut vm = VM::new_verified(compile("1 + 2", OptLevel::O0)).unwrap();
+    vm.run().unwrap();
+    assert_eq!(Object::Integer(3), vm.last_popped_stack_elem());
+
+    // VM::new verifies too, and run gives the error back instead of executing anything
+    let bytecode = ByteCode{ instructions: make(Opcode::OpReturn, &[]), constants: vec![], lines: vec![] };
+    let mut vm = VM::new(bytecode);
+    let expected = "invalid bytecode in main at 0000: return without a value outside of a function";
+    assert_eq!(expected, vm.run().unwrap_err().to_string());
+    assert_eq!(expected, vm.run().unwrap_err().to_string());
+}
+
+#[test]
+fn test_continuations_verify() {
+    let mut compiler = Compiler::new();
+    compiler.compile(parse("let x = 1; let y = 2;")).unwrap();
+    let mut next = compiler.continuation();
+    next.compile(parse("fn() { x }() + y")).unwrap();
+    let bytecode = next.bytecode();
+
+    // On its own the continuation reads globals it never sets
+    match verify(&bytecode) {
+        Err(VerifyError{ kind: VerifyErrorKind::GlobalNeverSet(_), .. }) => {},
+        x => panic!("expected a global that is never set, got {:?}", x)
+    }
+    assert_eq!(Ok(()), verify_continuation(&bytecode, compiler.symbol_table().globals().len()));
+
+    let mut vm = VM::new(compiler.bytecode());
+    vm.run().unwrap();
+    let globals = vm.into_globals();
+    let mut vm = VM::new(bytecode);
+    vm.set_globals(globals);
+    vm.run().unwrap();
+    assert_eq!(Object::Integer(3), vm.last_popped_stack_elem());
+}
+
+
+fn concat(instructions: Vec<Instructions>) -> Instructions {
+    return instructions.into_iter().flatten().collect();
+}
+
+fn compile(input: &str, opt_level: OptLevel) -> ByteCode {
+    let mut compiler = Compiler::with_opt_level(opt_level);
+    if let Err(x) = compiler.compile(parse(input)) {
+        panic!("{}", x)
+    }
+    return compiler.bytecode();
+}
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
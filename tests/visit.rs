@@ -0,0 +1,139 @@
+extern crate interpreter;
+use interpreter::lexer::Lexer;
+use interpreter::parser::Parser;
+use interpreter::parser::ast::*;
+use interpreter::parser::ast::visit;
+use interpreter::parser::json;
+use interpreter::parser::printer;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// Every name, read or bound, in the order the walk meets it
+#[derive(Default)]
+struct Names(Vec<String>);
+
+impl<'a> Visitor<'a> for Names {
+    fn visit_ident(&mut self, ident: &'a Ident) {
+        self.0.push(ident.0.clone());
+    }
+
+    fn visit_binding(&mut self, ident: &'a Ident) {
+        self.0.push(format!("let {}", ident.0));
+    }
+}
+
+#[derive(Default)]
+struct Statements(usize);
+
+impl<'a> Visitor<'a> for Statements {
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        self.0 += 1;
+        visit::walk_stmt(self, stmt);
+    }
+}
+
+// Doubles integers, but leaves function bodies alone
+struct Double;
+
+impl Folder for Double {
+    fn fold_literal(&mut self, literal: Literal) -> Literal {
+        return match literal {
+            Literal::Int(x) => Literal::Int(x * 2),
+            x => visit::rebuild_literal(self, x)
+        };
+    }
+
+    fn fold_function(&mut self, params: Rc<Params>, body: Rc<Block>) -> Expr {
+        return Expr::Fn(params, body);
+    }
+}
+
+struct Rename;
+
+impl Folder for Rename {
+    fn fold_ident(&mut self, ident: Ident) -> Ident {
+        return Ident(ident.0.to_uppercase());
+    }
+
+    fn fold_binding(&mut self, ident: Ident) -> Ident {
+        return Ident(ident.0.to_uppercase());
+    }
+}
+
+struct Identity;
+
+impl Folder for Identity {}
+
+#[test]
+fn test_visitor_order() {
+    let tests = vec![
+        ("let x = y + z;", vec!["y", "z", "let x"]),
+        ("let f = fn(a, b) { let c = a; c(b) };", vec!["let a", "let b", "a", "let c", "c", "b", "let f"]),
+        ("if (a) { b } else { c }; d[e]", vec!["a", "b", "c", "d", "e"]),
+        ("{k: v, \"s\": [w, -x]}; f(g)(h)", vec!["k", "v", "w", "x", "f", "g", "h"]),
+        ("return 1 + 2;", vec![])
+    ];
+
+    for (input, expected) in tests {
+        let mut names = Names::default();
+        names.visit_block(&parse(input));
+        assert_eq!(names.0, expected, "{}", input);
+    }
+}
+
+#[test]
+fn test_visitor_meets_every_statement() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let program = parse(&fs::read_to_string(&path).unwrap());
+        let mut statements = Statements::default();
+        statements.visit_block(&program);
+        // The JSON writer gives every statement a span of its own
+        assert_eq!(statements.0, json::to_json(&program).matches("\"span\":").count(), "{}", path.display());
+    }
+}
+
+#[test]
+fn test_folders() {
+    let tests = vec![
+        ("1 + x * 3", "2 + x * 6\n"),
+        ("let f = fn(a) { a + 1 }; f([1, {2: 3}])", "let f = fn(a) {\n    a + 1\n};\nf([2, {4: 6}])\n"),
+        ("if (1 < 2) { 3 } else { 4 }", "if (2 < 4) {\n    6\n} else {\n    8\n}\n")
+    ];
+
+    for (input, expected) in tests {
+        let program = Double.fold_block(parse(input));
+        assert_eq!(printer::print(&program), expected, "{}", input);
+    }
+
+    let program = Rename.fold_block(parse("let add = fn(a, b) { a + b }; add(x, 1)"));
+    assert_eq!(printer::print(&program), "let ADD = fn(A, B) {\n    A + B\n};\nADD(X, 1)\n");
+}
+
+#[test]
+fn test_folding_leaves_shared_functions_alone() {
+    let program = parse("fn(a) { a }");
+    let copy = program.clone();
+    let renamed = Rename.fold_block(copy);
+    assert_eq!(printer::print(&program), "fn(a) {\n    a\n}\n");
+    assert_eq!(printer::print(&renamed), "fn(A) {\n    A\n}\n");
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let program = parse(&fs::read_to_string(&path).unwrap());
+        assert_eq!(Identity.fold_block(program.clone()), program, "{}", path.display());
+    }
+}
+
+
+fn parse(input: &str) -> Program {
+    let mut l = Lexer::new(input);
+    let mut p = Parser::new(&mut l);
+    match p.parse_program() {
+        Ok(program) => program,
+        Err(errors) => panic!("Some errors were produced during parsing {:?}", errors)
+    }
+}
@@ -1,11 +1,13 @@
+// The table-driven tests build their cases with `vec!`
+#![allow(clippy::useless_vec)]
 extern crate interpreter;
-use interpreter::compiler::Compiler;
-use interpreter::code::Instruction;
-use interpreter::parser::ast::Program;
+use interpreter::compiler::{Compiler, OptLevel};
+use interpreter::parser::ast::*;
 use interpreter::parser::Parser;
 use interpreter::lexer::Lexer;
 use interpreter::evaluator::object::Object;
 use interpreter::vm::VM;
+use std::rc::Rc;
 
 #[test]
 fn test_integer_arithmetic() {
@@ -31,6 +33,244 @@ fn test_boolean() {
     test_run_vm("false", &Object::Boolean(false));
 }
 
+#[test]
+fn test_conditionals() {
+    test_run_vm("if (true) { 10 }", &Object::Integer(10));
+    test_run_vm("if (true) { 10 } else { 20 }", &Object::Integer(10));
+    test_run_vm("if (false) { 10 } else { 20 }", &Object::Integer(20));
+    test_run_vm("if (1 < 2) { 10 } else { 20 }", &Object::Integer(10));
+    test_run_vm("if (1 > 2) { 10 }", &Object::Null);
+    test_run_vm("if ((if (false) { 10 })) { 10 } else { 20 }", &Object::Integer(20));
+    test_run_vm("!(if (false) { 5; })", &Object::Boolean(true));
+}
+
+#[test]
+fn test_global_let() {
+    test_run_vm("let one = 1; one", &Object::Integer(1));
+    test_run_vm("let one = 1; let two = one + one; one + two", &Object::Integer(3));
+}
+
+#[test]
+fn test_functions() {
+    test_run_vm("let fivePlusTen = fn() { 5 + 10; }; fivePlusTen();", &Object::Integer(15));
+    test_run_vm("let earlyExit = fn() { return 99; 100; }; earlyExit();", &Object::Integer(99));
+    test_run_vm("let noReturn = fn() { }; noReturn();", &Object::Null);
+    test_run_vm("let sum = fn(a, b) { let c = a + b; c; }; sum(1, 2) + sum(3, 4);", &Object::Integer(10));
+    test_run_vm("let globalSeed = 50; let minusOne = fn() { let num = 1; globalSeed - num; }; minusOne();", &Object::Integer(49));
+}
+
+#[test]
+fn test_closures() {
+    test_run_vm("let newClosure = fn(a) { fn() { a; }; }; let closure = newClosure(99); closure();", &Object::Integer(99));
+    test_run_vm("
+        let newAdder = fn(a, b) {
+            fn(c) { a + b + c };
+        };
+        let adder = newAdder(1, 2);
+        adder(8);
+    ", &Object::Integer(11));
+    test_run_vm("
+        let newAdderOuter = fn(a, b) {
+            let c = a + b;
+            fn(d) {
+                let e = d + c;
+                fn(f) { e + f; };
+            };
+        };
+        let newAdderInner = newAdderOuter(1, 2);
+        let adder = newAdderInner(3);
+        adder(8);
+    ", &Object::Integer(14));
+    test_run_vm("
+        let newCounter = fn(start) {
+            fn(step) { start + step };
+        };
+        let counter = newCounter(10);
+        counter(1) + counter(2);
+    ", &Object::Integer(23));
+}
+
+#[test]
+fn test_recursive_closures() {
+    test_run_vm("
+        let countDown = fn(x) {
+            if (x == 0) {
+                return 0;
+            } else {
+                countDown(x - 1);
+            }
+        };
+        countDown(10);
+    ", &Object::Integer(0));
+    test_run_vm("
+        let wrapper = fn() {
+            let countDown = fn(x) {
+                if (x == 0) {
+                    return 0;
+                } else {
+                    countDown(x - 1);
+                }
+            };
+            countDown(1);
+        };
+        wrapper();
+    ", &Object::Integer(0));
+    test_run_vm("
+        let fibonacci = fn(x) {
+            if (x == 0) {
+                return 0;
+            } else {
+                if (x == 1) {
+                    return 1;
+                } else {
+                    fibonacci(x - 1) + fibonacci(x - 2);
+                }
+            }
+        };
+        fibonacci(15);
+    ", &Object::Integer(610));
+}
+
+#[test]
+fn test_builtin_functions() {
+    test_run_vm("len(\"\")", &Object::Integer(0));
+    test_run_vm("len(\"four\")", &Object::Integer(4));
+    test_run_vm_error("len(1)", "argument to len not supported");
+    test_run_vm_error("len(\"one\", \"two\")", "wrong number of arguments");
+    test_run_vm("len([1, 2, 3])", &Object::Integer(3));
+    test_run_vm("first([\"abc\", 1, 2])", &Object::Str(Rc::new("abc".to_string())));
+    test_run_vm("last([\"abc\", 1, 2])", &Object::Integer(2));
+    test_run_vm("rest([1, 2, 3])", &Object::Array(Rc::new(vec![Object::Integer(2), Object::Integer(3)])));
+    test_run_vm("push([1, 2, 3], 4)", &Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(2), Object::Integer(3), Object::Integer(4)])));
+    test_run_vm("puts(\"hello\")", &Object::Null);
+}
+
+#[test]
+fn test_builtins_in_closures() {
+    test_run_vm("
+        let map = fn(arr, f) {
+            let iter = fn(arr, acc) {
+                if (len(arr) == 0) {
+                    acc
+                } else {
+                    iter(rest(arr), push(acc, f(first(arr))));
+                }
+            };
+            iter(arr, []);
+        };
+        map([1, 2, 3], fn(x) { x * 2 });
+    ", &Object::Array(Rc::new(vec![Object::Integer(2), Object::Integer(4), Object::Integer(6)])));
+}
+
+#[test]
+fn test_runtime_errors() {
+    test_run_vm_error("fn(a) { a; }();", "wrong number of arguments: want=1, got=0");
+    test_run_vm_error("1 + true", "type mismatch");
+    test_run_vm_error("true + false", "unknown operator");
+    test_run_vm_error("10 / (5 - 5)", "division by zero");
+    test_run_vm_error("let x = 1; x()", "Not callable: 1");
+    // Both operands of `<` fail; the left one runs first
+    test_run_vm_error("fn(a) { a }() < (1 + true)", "wrong number of arguments: want=1, got=0");
+}
+
+
+#[test]
+fn test_stack_overflow() {
+    test_run_vm_error("let f = fn() { f() }; f()", "stack overflow");
+    test_run_vm_error("let f = fn(n) { 1 + f(n + 1) }; f(0)", "stack overflow");
+
+    // Deep enough for the default stack, but not for a small one
+    let input = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(100)";
+    test_run_vm(input, &Object::Integer(100));
+    let mut compiler = Compiler::new();
+    compiler.compile(parse(input)).unwrap();
+    let mut vm = VM::with_stack_size(compiler.bytecode(), 64);
+    assert_eq!(vm.run().unwrap_err().to_string(), "stack overflow");
+
+    let mut compiler = Compiler::new();
+    compiler.compile(parse("[1, 2, 3, 4]")).unwrap();
+    let mut vm = VM::with_stack_size(compiler.bytecode(), 3);
+    assert_eq!(vm.run().unwrap_err().to_string(), "stack overflow");
+}
+
+#[test]
+fn test_last_popped_stack_elem() {
+    let tests = vec![
+        ("1; 2", Object::Integer(2)),
+        ("1; let x = 2;", Object::Null),
+        ("let x = 2; x", Object::Integer(2)),
+        ("", Object::Null),
+        ("fn() { 1; 2 }()", Object::Integer(2)),
+        ("return 3; 4", Object::Integer(3))
+    ];
+
+    for (input, expected) in tests {
+        let mut compiler = Compiler::new();
+        compiler.compile(parse(input)).unwrap();
+        let mut vm = VM::new(compiler.bytecode());
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped_stack_elem(), expected, "{}", input);
+        assert_eq!(vm.stack_top(), None, "{}", input);
+    }
+}
+
+#[test]
+fn test_current_line() {
+    let tests = vec![
+        ("1 + true", Some(1)),
+        ("let f = fn(a) {\n  a;\n  a + true\n};\n\nf(1)", Some(3)),
+        ("let x = 1;\nlet y = [1][0];\nx()", Some(3)),
+        ("let f = fn(a) { a };\nf(1, 2)", Some(2))
+    ];
+
+    for (input, expected) in tests {
+        let mut compiler = Compiler::new();
+        compiler.compile(parse(input)).unwrap();
+        let mut vm = VM::new(compiler.bytecode());
+        assert!(vm.run().is_err(), "{}", input);
+        assert_eq!(vm.current_line(), expected, "{}", input);
+    }
+
+    // Trees that were not parsed get no line tables, so there is nothing to report
+    let one = Box::new(Expr::LiteralExpr(Literal::Int(1)));
+    let truth = Box::new(Expr::LiteralExpr(Literal::Bool(true)));
+    let mut compiler = Compiler::new();
+    compiler.compile(vec![Stmt::ExprStmt(Expr::Infix(one, Infix::Plus, truth), Location::default())]).unwrap();
+    let mut vm = VM::new(compiler.bytecode());
+    assert!(vm.run().is_err());
+    assert_eq!(vm.current_line(), None);
+}
+
+#[test]
+fn test_state_across_inputs() {
+    let inputs = vec![
+        ("let x = 2;", Object::Null),
+        ("let f = fn(a) { a * x };", Object::Null),
+        ("f(3)", Object::Integer(6)),
+        // A new binding does not change what f captured
+        ("let x = 10; f(3)", Object::Integer(6)),
+        ("let s = \"a\"; s + \"b\"", Object::Str(Rc::new("ab".to_string()))),
+        ("len(s) + f(1) + x", Object::Integer(13))
+    ];
+
+    for opt_level in [OptLevel::O0, OptLevel::O2] {
+        let mut compiler = Compiler::with_opt_level(opt_level);
+        let mut globals = vec![];
+        for (input, expected) in inputs.iter() {
+            let mut next = compiler.continuation();
+            next.compile(parse(input)).unwrap();
+            let mut vm = VM::new(next.bytecode());
+            vm.set_globals(globals);
+            vm.run().unwrap();
+            assert_eq!(&vm.last_popped_stack_elem(), expected, "{}", input);
+            globals = vm.into_globals();
+            compiler = next;
+        }
+
+        // Each continuation starts from an empty main program
+        assert_eq!(compiler.continuation().bytecode().instructions, Vec::<u8>::new());
+    }
+}
 
 fn test_run_vm(input: &str, expected: &Object) {
     let program = parse(input);
@@ -44,7 +284,22 @@ fn test_run_vm(input: &str, expected: &Object) {
         panic!("{}", x)
     }
 
-    assert_eq!(vm.last_popped(), Some(expected));
+    assert_eq!(&vm.last_popped_stack_elem(), expected);
+}
+
+
+fn test_run_vm_error(input: &str, expected: &str) {
+    let program = parse(input);
+    let mut compiler = Compiler::new();
+    if let Err(x) = compiler.compile(program) {
+        panic!("{}", x)
+    }
+
+    let mut vm = VM::new(compiler.bytecode());
+    match vm.run() {
+        Ok(_) => panic!("expected an error running {}", input),
+        Err(x) => assert_eq!(x.to_string(), expected)
+    }
 }
 
 